@@ -152,4 +152,148 @@ pub extern "C" fn pow(x: i32, y: i32) -> i32 {
         result = result * x;
     }
     result
-}
\ No newline at end of file
+}
+
+// Payload ABI: the host writes the input into a block from `alloc`, calls
+// `f(ptr, len) -> i64` and reads the output block whose address and length
+// are packed into the result. The host frees both blocks with `dealloc`.
+
+/// Reserves a block of memory for the host
+///
+/// # Arguments
+/// * `len` - Size of the block in bytes
+///
+/// # Returns
+/// The address of the block
+#[no_mangle]
+pub extern "C" fn alloc(len: i32) -> i32 {
+    let block = vec![0u8; len as usize].into_boxed_slice();
+    Box::into_raw(block) as *mut u8 as i32
+}
+
+/// Releases a block returned by `alloc`
+///
+/// # Arguments
+/// * `ptr` - Address of the block
+/// * `len` - Size of the block in bytes
+///
+/// # Safety
+/// `ptr` and `len` must describe a block returned by `alloc` that was not
+/// released yet.
+#[no_mangle]
+pub unsafe extern "C" fn dealloc(ptr: i32, len: i32) {
+    drop(Box::from_raw(core::ptr::slice_from_raw_parts_mut(ptr as *mut u8, len as usize)));
+}
+
+/// Borrows the payload the host wrote at `ptr`
+///
+/// # Safety
+/// `ptr` and `len` must be the arguments the host passed to the function.
+pub unsafe fn input<'a>(ptr: i32, len: i32) -> &'a [u8] {
+    core::slice::from_raw_parts(ptr as *const u8, len as usize)
+}
+
+/// Hands bytes to the host as a function's output
+///
+/// # Returns
+/// The address of the output in the high 32 bits and its length in the low 32 bits
+pub fn output(bytes: Vec<u8>) -> i64 {
+    let len = bytes.len() as u32;
+    let ptr = Box::into_raw(bytes.into_boxed_slice()) as *mut u8 as u32;
+    ((ptr as u64) << 32 | len as u64) as i64
+}
+
+/// Reverses the bytes of the payload
+///
+/// # Arguments
+/// * `ptr`, `len` - The payload
+///
+/// # Returns
+/// The reversed bytes, packed as described for `output`
+///
+/// # Safety
+/// Called by the host with a payload block from `alloc`.
+#[no_mangle]
+pub unsafe extern "C" fn reverse(ptr: i32, len: i32) -> i64 {
+    let mut bytes = input(ptr, len).to_vec();
+    bytes.reverse();
+    output(bytes)
+}
+
+/// Sums a JSON array of integers, e.g. `[1, 2, 3]`
+///
+/// # Arguments
+/// * `ptr`, `len` - The payload
+///
+/// # Returns
+/// A JSON object such as `{"sum":6,"count":3}`, or `{"error":"..."}` when the
+/// payload is not a flat array of integers, packed as described for `output`
+///
+/// # Safety
+/// Called by the host with a payload block from `alloc`.
+#[no_mangle]
+pub unsafe extern "C" fn sum(ptr: i32, len: i32) -> i64 {
+    let reply = match parse_integers(input(ptr, len)) {
+        Ok(numbers) => match numbers.iter().try_fold(0i64, |total, n| total.checked_add(*n)) {
+            Some(total) => format!("{{\"sum\":{},\"count\":{}}}", total, numbers.len()),
+            None => "{\"error\":\"sum does not fit in 64 bits\"}".to_string(),
+        },
+        Err(message) => format!("{{\"error\":\"{}\"}}", message),
+    };
+    output(reply.into_bytes())
+}
+
+/// Parses a JSON array whose items are all integers
+///
+/// # Arguments
+/// * `bytes` - The JSON text
+///
+/// # Returns
+/// The integers in order, or a message saying why the text was rejected
+pub fn parse_integers(bytes: &[u8]) -> Result<Vec<i64>, &'static str> {
+    const NOT_AN_ARRAY: &str = "expected a JSON array of integers";
+    let text = core::str::from_utf8(bytes).map_err(|_| "payload is not UTF-8")?;
+    let items = text
+        .trim()
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .ok_or(NOT_AN_ARRAY)?;
+    if items.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    items
+        .split(',')
+        .map(|item| {
+            let item = item.trim();
+            // JSON integers: an optional minus, then 0 or digits without a leading zero
+            let digits = item.strip_prefix('-').unwrap_or(item);
+            let valid = digits.bytes().all(|b| b.is_ascii_digit())
+                && (digits == "0" || digits.bytes().next().is_some_and(|b| b != b'0'));
+            if !valid {
+                return Err(NOT_AN_ARRAY);
+            }
+            item.parse().map_err(|_| "integer does not fit in 64 bits")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_integers() {
+        assert_eq!(parse_integers(b" [1, -2,3 ] "), Ok(vec![1, -2, 3]));
+        assert_eq!(parse_integers(b"[]"), Ok(vec![]));
+        assert_eq!(parse_integers(b"[0]"), Ok(vec![0]));
+    }
+
+    #[test]
+    fn test_parse_integers_rejects_other_json() {
+        for text in ["[[1,2],[3]]", "{\"a\":1}", "[1,]", "[1.5]", "[+1]", "[01]", "[\"1\"]", "1", "", "[1 2]"] {
+            assert_eq!(parse_integers(text.as_bytes()), Err("expected a JSON array of integers"), "{}", text);
+        }
+        assert_eq!(parse_integers(b"[99999999999999999999]"), Err("integer does not fit in 64 bits"));
+        assert_eq!(parse_integers(&[b'[', 0xff, b']']), Err("payload is not UTF-8"));
+    }
+}
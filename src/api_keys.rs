@@ -0,0 +1,215 @@
+// Long-lived API keys for CI pipelines and other non-interactive clients.
+//
+// Keys look like `lrc_<id>_<secret>` and are sent as `Authorization: Bearer <key>`.
+// Only a SHA-256 hash of the full key is kept, so a lost key cannot be
+// recovered, only revoked.
+use crate::auth::{authenticated_user, authenticated_user_for, bearer_error, generate_token_id};
+use crate::models::{ApiKey, AuthenticatedUser, CreateApiKeyRequest, CreateApiKeyResponse};
+use crate::state::{AppState, AppStateInner};
+use chrono::{Duration, Utc};
+use sha2::{Digest, Sha256};
+use tide::Request;
+use tracing::info;
+
+pub const API_KEY_PREFIX: &str = "lrc_";
+pub const SCOPE_DATA_READ: &str = "data:read";
+pub const SCOPE_DATA_WRITE: &str = "data:write";
+pub const SCOPE_EXECUTE: &str = "execute";
+const ALL_SCOPES: [&str; 3] = [SCOPE_DATA_READ, SCOPE_DATA_WRITE, SCOPE_EXECUTE];
+
+// Creates a key for the caller; the plaintext key is only returned here
+pub async fn create_api_key(mut req: Request<AppState>) -> tide::Result {
+    let username = authenticated_user(&req)?.username;
+    let create_req: CreateApiKeyRequest = req.body_json().await?;
+
+    if create_req.name.trim().is_empty() {
+        return Err(tide::Error::from_str(400, "API key name must not be empty"));
+    }
+    if create_req.scopes.is_empty() {
+        return Err(tide::Error::from_str(400, format!("At least one scope is required: {:?}", ALL_SCOPES)));
+    }
+    if let Some(scope) = create_req.scopes.iter().find(|scope| !ALL_SCOPES.contains(&scope.as_str())) {
+        return Err(tide::Error::from_str(400, format!("Unknown scope '{}'. Available scopes: {:?}", scope, ALL_SCOPES)));
+    }
+    if create_req.expires_in_days.is_some_and(|days| days <= 0) {
+        return Err(tide::Error::from_str(400, "expires_in_days must be positive"));
+    }
+
+    let mut app_state = req.state().lock().unwrap();
+    let (key, info) = issue_api_key(&mut app_state, &username, create_req);
+    info!(user = %username, key_id = %info.id, scopes = ?info.scopes, "API key created");
+    let mut response = tide::Response::new(201);
+    response.set_body(tide::Body::from_json(&CreateApiKeyResponse { key, info })?);
+    Ok(response)
+}
+
+// Lists the caller's keys (without secrets)
+pub async fn list_api_keys(req: Request<AppState>) -> tide::Result {
+    let username = authenticated_user(&req)?.username;
+    let app_state = req.state().read().unwrap();
+    let mut keys: Vec<&ApiKey> = app_state.api_keys.values().filter(|key| key.owner == username).collect();
+    keys.sort_by_key(|key| key.created_at);
+    Ok(tide::Body::from_json(&keys)?.into())
+}
+
+// Revokes one of the caller's keys
+pub async fn revoke_api_key(req: Request<AppState>) -> tide::Result {
+    let username = authenticated_user(&req)?.username;
+    let id = req.param("id")?.to_string();
+    let mut app_state = req.state().lock().unwrap();
+    match app_state.api_keys.get(&id) {
+        Some(key) if key.owner == username => {
+            app_state.api_keys.remove(&id);
+            info!(user = %username, key_id = %id, "API key revoked");
+            Ok(tide::Response::new(204))
+        }
+        Some(_) => Err(tide::Error::from_str(403, "Access denied: not the owner")),
+        None => Ok(tide::Response::new(404)),
+    }
+}
+
+// Generates and stores a new key, returning the plaintext and the stored record
+pub fn issue_api_key(app_state: &mut AppStateInner, owner: &str, create_req: CreateApiKeyRequest) -> (String, ApiKey) {
+    let id = generate_token_id()[..16].to_string();
+    let key = format!("{}{}_{}{}", API_KEY_PREFIX, id, generate_token_id(), generate_token_id());
+    let now = Utc::now();
+    let info = ApiKey {
+        id: id.clone(),
+        name: create_req.name,
+        owner: owner.to_string(),
+        key_hash: hash_api_key(&key),
+        scopes: create_req.scopes,
+        created_at: now,
+        expires_at: create_req.expires_in_days.map(|days| now + Duration::days(days)),
+        last_used_at: None,
+    };
+    app_state.api_keys.insert(id, info.clone());
+    (key, info)
+}
+
+// Checks a presented key and its scope, returning the key's owner
+pub fn authenticate_api_key(app_state: &mut AppStateInner, key: &str, required_scope: Option<&str>) -> Result<AuthenticatedUser, tide::Error> {
+    let id = key
+        .strip_prefix(API_KEY_PREFIX)
+        .and_then(|rest| rest.split_once('_'))
+        .map(|(id, _)| id)
+        .ok_or_else(|| bearer_error(401, Some("invalid_token"), "Invalid API key"))?;
+    let stored = app_state
+        .api_keys
+        .get_mut(id)
+        .filter(|stored| stored.key_hash == hash_api_key(key))
+        .ok_or_else(|| bearer_error(401, Some("invalid_token"), "Invalid API key"))?;
+
+    let now = Utc::now();
+    if stored.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(bearer_error(401, Some("invalid_token"), "API key expired"));
+    }
+    match required_scope {
+        Some(scope) if stored.scopes.iter().any(|granted| granted == scope) => {}
+        Some(scope) => return Err(bearer_error(403, Some("insufficient_scope"), format!("API key lacks the '{}' scope", scope))),
+        None => return Err(bearer_error(403, Some("insufficient_scope"), "API keys cannot be used for this endpoint")),
+    }
+
+    stored.last_used_at = Some(now);
+    let (owner, id) = (stored.owner.clone(), stored.id.clone());
+    Ok(authenticated_user_for(app_state, owner, id))
+}
+
+// The scope an API key needs for a request, or None if API keys are not accepted there
+pub fn required_scope(method: tide::http::Method, path: &str) -> Option<&'static str> {
+    use tide::http::Method;
+    let under = |prefix: &str| path == prefix || path.starts_with(&format!("{}/", prefix));
+    // Instances live under their record but are used for executing it
+    let instances = under("/instances") || (path.starts_with("/data/") && path.ends_with("/instances"));
+    if instances || path.starts_with("/execute/") || under("/jobs") {
+        Some(SCOPE_EXECUTE)
+    } else if under("/data") {
+        match method {
+            Method::Get => Some(SCOPE_DATA_READ),
+            _ => Some(SCOPE_DATA_WRITE),
+        }
+    } else {
+        None
+    }
+}
+
+fn hash_api_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::new_state;
+    use tide::http::Method;
+
+    fn create_request(scopes: &[&str], expires_in_days: Option<i64>) -> CreateApiKeyRequest {
+        CreateApiKeyRequest {
+            name: "ci".to_string(),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+            expires_in_days,
+        }
+    }
+
+    #[test]
+    fn test_api_key_is_stored_hashed() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        let (key, info) = issue_api_key(&mut app_state, "user1", create_request(&[SCOPE_DATA_WRITE], None));
+
+        assert!(key.starts_with(API_KEY_PREFIX));
+        assert_ne!(info.key_hash, key);
+        assert!(!app_state.api_keys[&info.id].key_hash.contains(&key));
+        assert!(serde_json::to_value(&info).unwrap().get("key_hash").is_none());
+    }
+
+    #[test]
+    fn test_authenticate_api_key_scopes() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        let (key, info) = issue_api_key(&mut app_state, "user1", create_request(&[SCOPE_DATA_WRITE], None));
+
+        let user = authenticate_api_key(&mut app_state, &key, Some(SCOPE_DATA_WRITE)).unwrap();
+        assert_eq!(user.username, "user1");
+        assert_eq!(user.token_id, info.id);
+        assert!(app_state.api_keys[&info.id].last_used_at.is_some());
+        assert_eq!(authenticate_api_key(&mut app_state, &key, Some(SCOPE_EXECUTE)).unwrap_err().status(), 403);
+        assert_eq!(authenticate_api_key(&mut app_state, &key, None).unwrap_err().status(), 403);
+
+        // A tampered secret with a valid id is rejected
+        let tampered = format!("{}0", &key[..key.len() - 1]);
+        assert_eq!(authenticate_api_key(&mut app_state, &tampered, Some(SCOPE_DATA_WRITE)).unwrap_err().status(), 401);
+    }
+
+    #[test]
+    fn test_expired_and_revoked_api_keys() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        let (key, info) = issue_api_key(&mut app_state, "user1", create_request(&[SCOPE_EXECUTE], Some(1)));
+        app_state.api_keys.get_mut(&info.id).unwrap().expires_at = Some(Utc::now() - Duration::seconds(1));
+        assert_eq!(authenticate_api_key(&mut app_state, &key, Some(SCOPE_EXECUTE)).unwrap_err().status(), 401);
+
+        let (key, info) = issue_api_key(&mut app_state, "user1", create_request(&[SCOPE_EXECUTE], None));
+        app_state.api_keys.remove(&info.id);
+        assert_eq!(authenticate_api_key(&mut app_state, &key, Some(SCOPE_EXECUTE)).unwrap_err().status(), 401);
+    }
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(required_scope(Method::Get, "/data"), Some(SCOPE_DATA_READ));
+        assert_eq!(required_scope(Method::Get, "/data/1"), Some(SCOPE_DATA_READ));
+        assert_eq!(required_scope(Method::Post, "/data"), Some(SCOPE_DATA_WRITE));
+        assert_eq!(required_scope(Method::Delete, "/data/1"), Some(SCOPE_DATA_WRITE));
+        assert_eq!(required_scope(Method::Post, "/execute/1"), Some(SCOPE_EXECUTE));
+        assert_eq!(required_scope(Method::Post, "/jobs"), Some(SCOPE_EXECUTE));
+        assert_eq!(required_scope(Method::Delete, "/jobs/abc"), Some(SCOPE_EXECUTE));
+        assert_eq!(required_scope(Method::Post, "/data/1/instances"), Some(SCOPE_EXECUTE));
+        assert_eq!(required_scope(Method::Delete, "/instances/abc"), Some(SCOPE_EXECUTE));
+        assert_eq!(required_scope(Method::Post, "/auth/api-keys"), None);
+        assert_eq!(required_scope(Method::Get, "/admin/export"), None);
+        assert_eq!(required_scope(Method::Get, "/database"), None);
+    }
+}
@@ -1,22 +1,41 @@
-use crate::models::{AuthRequest, AuthResponse, Claims, CreateDataRequest, DataEntry, RefreshRequest, RefreshTokenInfo};
-use crate::state::AppState;
+use crate::models::{AuthRequest, AuthResponse, AuthenticatedUser, Claims, CreateDataRequest, DataEntry, IssuedAccessToken, MfaChallengeResponse, RefreshRequest, RefreshTokenInfo, SessionClient};
+use crate::state::{AppState, AppStateInner};
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use crate::api_keys::{authenticate_api_key, required_scope, API_KEY_PREFIX};
+use crate::keys::signing_keys;
+use crate::oidc::{authenticate_idp_token, is_idp_token, purge_expired_oidc_logins};
+use crate::mfa::mfa_enabled;
+use crate::lockout::{check_login_allowed, client_ip, purge_stale_login_attempts, record_login_failure, record_login_success};
+use jsonwebtoken::{decode, decode_header, encode, Validation};
 use tide::Request;
 use std::env;
+use tracing::{debug, info, warn};
 
-// JWT Configuration - Get from environment variables with defaults
-fn get_jwt_secret() -> Vec<u8> {
-    env::var("JWT_SECRET")
-        .unwrap_or_else(|_| "your-secret-key-change-in-production".to_string())
-        .into_bytes()
-}
+// Realm advertised in `WWW-Authenticate` challenges
+const AUTH_REALM: &str = "learn-rust-crud";
+// Longest user agent kept on a session
+const MAX_USER_AGENT_LENGTH: usize = 256;
 
+// JWT Configuration - Get from environment variables with defaults
+// (signing keys live in crate::keys)
 fn get_jwt_issuer() -> String {
     env::var("JWT_ISSUER")
         .unwrap_or_else(|_| "learn-rust-crud".to_string())
 }
 
+fn get_jwt_audience() -> String {
+    env::var("JWT_AUDIENCE")
+        .unwrap_or_else(|_| "learn-rust-crud-api".to_string())
+}
+
+// Clock skew tolerated when checking `exp`
+pub fn get_jwt_leeway_secs() -> u64 {
+    env::var("JWT_LEEWAY_SECS")
+        .unwrap_or_else(|_| "60".to_string())
+        .parse()
+        .unwrap_or(60)
+}
+
 fn get_access_token_expiration_hours() -> i64 {
     env::var("ACCESS_TOKEN_EXPIRATION_HOURS")
         .unwrap_or_else(|_| "1".to_string())
@@ -31,126 +50,542 @@ fn get_refresh_token_expiration_days() -> i64 {
         .unwrap_or(30)
 }
 
-// Function to authenticate user and generate JWT tokens
+// Time allowed between the password and the second factor
+fn get_mfa_token_expiration_secs() -> i64 {
+    env::var("MFA_TOKEN_EXPIRATION_SECS")
+        .unwrap_or_else(|_| "300".to_string())
+        .parse()
+        .unwrap_or(300)
+}
+
+fn get_token_cleanup_interval_secs() -> u64 {
+    env::var("TOKEN_CLEANUP_INTERVAL_SECS")
+        .unwrap_or_else(|_| "300".to_string())
+        .parse()
+        .unwrap_or(300)
+}
+
+// Admin usernames, as a comma-separated list
+fn get_admin_users() -> Vec<String> {
+    env::var("ADMIN_USERS")
+        .unwrap_or_else(|_| "admin".to_string())
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+// Helper function to check if user has admin privileges
+pub fn is_admin(username: &str) -> bool {
+    get_admin_users().iter().any(|admin| admin == username)
+}
+
+pub const ROLE_USER: &str = "user";
+pub const ROLE_ADMIN: &str = "admin";
+
+// Builds the request extension for a caller authenticated with `token_id`.
+// Admins come from ADMIN_USERS or, for OIDC users, from OIDC_ADMIN_GROUPS.
+pub fn authenticated_user_for(app_state: &AppStateInner, username: String, token_id: String) -> AuthenticatedUser {
+    let external_admin = app_state.external_users.get(&username).is_some_and(|external| external.admin);
+    let mut roles = vec![ROLE_USER.to_string()];
+    if is_admin(&username) || external_admin {
+        roles.push(ROLE_ADMIN.to_string());
+    }
+    AuthenticatedUser { username, roles, token_id }
+}
+
+// Function to authenticate user and generate JWT tokens. Unknown users and wrong
+// passwords get the same answer, and repeated failures lock the username and IP.
 pub async fn login(mut req: Request<AppState>) -> tide::Result {
     let auth_req: AuthRequest = req.body_json().await?;
+    let client = session_client(&req);
+    let ip = client.ip.clone();
     let state = req.state();
     let mut app_state = state.lock().unwrap();
+    let now = Utc::now();
 
-    // Check if user exists and password is correct
-    if let Some(stored_password) = app_state.users.get(&auth_req.username) {
-        if stored_password == &auth_req.password {
-            // Generate access and refresh tokens
-            let access_token = generate_access_token(&auth_req.username)?;
-            let refresh_token = generate_refresh_token(&auth_req.username)?;
-            
-            // Store refresh token
-            let refresh_info = RefreshTokenInfo {
-                username: auth_req.username.clone(),
-                expires_at: Utc::now() + Duration::days(get_refresh_token_expiration_days()),
-            };
-            app_state.refresh_tokens.insert(refresh_token.clone(), refresh_info);
-            
-            let response = AuthResponse {
-                access_token,
-                refresh_token,
-                username: auth_req.username,
-                token_type: "Bearer".to_string(),
-                expires_in: get_access_token_expiration_hours() * 3600, // Convert hours to seconds
-            };
+    if let Err(retry_after) = check_login_allowed(&app_state.login_throttle, &auth_req.username, &ip, now) {
+        warn!(user = %auth_req.username, ip = %ip, retry_after_secs = retry_after, "Login refused - too many failed attempts");
+        let mut response = auth_error("too_many_attempts", "Too many failed login attempts; try again later");
+        response.set_status(429);
+        response.insert_header("Retry-After", retry_after.to_string());
+        return Ok(response);
+    }
 
-            Ok(tide::Body::from_json(&response)?.into())
-        } else {
-            Err(tide::Error::from_str(401, "Invalid credentials"))
-        }
-    } else {
-        Err(tide::Error::from_str(401, "User not found"))
+    // Check if user exists and password is correct. Disabled accounts fail the
+    // same way, so the answer never confirms a guessed password.
+    let valid = app_state
+        .users
+        .get(&auth_req.username)
+        .is_some_and(|stored_password| stored_password == &auth_req.password)
+        && !app_state.disabled_users.contains(&auth_req.username);
+    if !valid {
+        record_login_failure(&mut app_state.login_throttle, &auth_req.username, &ip, now);
+        info!(user = %auth_req.username, ip = %ip, "Login failed - invalid username or password");
+        return Err(tide::Error::from_str(401, "Invalid username or password"));
+    }
+
+    // With a second factor enrolled, the password only buys a short-lived token
+    // that /auth/mfa/login exchanges for a session once the code checks out.
+    // The failure counter stays until then, so wrong codes keep adding up.
+    if mfa_enabled(&app_state, &auth_req.username) {
+        info!(user = %auth_req.username, "Password accepted - waiting for second factor");
+        let challenge = MfaChallengeResponse {
+            mfa_required: true,
+            mfa_token: generate_mfa_token(&auth_req.username)?,
+            expires_in: get_mfa_token_expiration_secs(),
+        };
+        return Ok(tide::Body::from_json(&challenge)?.into());
+    }
+
+    record_login_success(&mut app_state.login_throttle, &auth_req.username);
+    let response = issue_session(&mut app_state, &auth_req.username, &client)?;
+    Ok(tide::Body::from_json(&response)?.into())
+}
+
+// Generates access and refresh tokens for a user who just signed in
+pub fn issue_session(app_state: &mut AppStateInner, username: &str, client: &SessionClient) -> Result<AuthResponse, tide::Error> {
+    if app_state.disabled_users.contains(username) {
+        return Err(tide::Error::from_str(403, "Account disabled"));
     }
+    // Each login starts a new refresh token family
+    let family_id = generate_token_id();
+    let access_token = issue_access_token(app_state, username, &family_id)?;
+    let refresh_token = issue_refresh_token(app_state, username, &family_id, Utc::now(), client)?;
+
+    Ok(AuthResponse {
+        access_token,
+        refresh_token,
+        username: username.to_string(),
+        token_type: "Bearer".to_string(),
+        expires_in: get_access_token_expiration_hours() * 3600, // Convert hours to seconds
+    })
 }
 
-// Function to refresh access token
+// Function to refresh access token. The presented refresh token is rotated:
+// a new one is returned and the old one can no longer be used.
 pub async fn refresh(mut req: Request<AppState>) -> tide::Result {
     let refresh_req: RefreshRequest = req.body_json().await?;
+    let client = session_client(&req);
     let state = req.state();
     let mut app_state = state.lock().unwrap();
 
-    // Check if refresh token exists and is valid
-    if let Some(refresh_info) = app_state.refresh_tokens.get(&refresh_req.refresh_token) {
-        // Check if refresh token is expired
-        if refresh_info.expires_at > Utc::now() {
-            // Generate new access token
-            let new_access_token = generate_access_token(&refresh_info.username)?;
-            
+    match rotate_refresh_token(&mut app_state, &refresh_req.refresh_token, &client) {
+        Ok((username, family_id, refresh_token)) => {
             let response = AuthResponse {
-                access_token: new_access_token,
-                refresh_token: refresh_req.refresh_token, // Keep the same refresh token
-                username: refresh_info.username.clone(),
+                access_token: issue_access_token(&mut app_state, &username, &family_id)?,
+                refresh_token,
+                username,
                 token_type: "Bearer".to_string(),
                 expires_in: get_access_token_expiration_hours() * 3600,
             };
-
             Ok(tide::Body::from_json(&response)?.into())
-        } else {
-            // Remove expired refresh token
-            app_state.refresh_tokens.remove(&refresh_req.refresh_token);
-            Err(tide::Error::from_str(401, "Refresh token expired"))
         }
-    } else {
-        Err(tide::Error::from_str(401, "Invalid refresh token"))
+        Err(RefreshError::Invalid) => Ok(auth_error("invalid_refresh_token", "Invalid refresh token")),
+        Err(RefreshError::Expired) => Ok(auth_error("refresh_token_expired", "Refresh token expired")),
+        Err(RefreshError::Reused) => {
+            warn!("Refresh token reuse detected, token family revoked");
+            Ok(auth_error(
+                "refresh_token_reused",
+                "Refresh token was already used; all sessions from this login have been revoked",
+            ))
+        }
     }
 }
 
-// Function to logout (remove refresh token)
+// Function to logout (revoke the refresh token and every token rotated from the same login).
+// The access token sent in the Authorization header, if any, is revoked too.
 pub async fn logout(mut req: Request<AppState>) -> tide::Result {
+    let access_claims = bearer_token(&req)
+        .ok()
+        .flatten()
+        .and_then(|token| decode_access_token(&token).ok());
     let refresh_req: RefreshRequest = req.body_json().await?;
     let state = req.state();
     let mut app_state = state.lock().unwrap();
-    
-    // Remove refresh token
-    app_state.refresh_tokens.remove(&refresh_req.refresh_token);
-    
+
+    if let Some(family_id) = app_state
+        .refresh_tokens
+        .get(&refresh_req.refresh_token)
+        .map(|info| info.family_id.clone())
+    {
+        revoke_session(&mut app_state, &family_id);
+    }
+    if let Some(claims) = access_claims {
+        revoke_access_token(&mut app_state, &claims.jti, claims.exp);
+    }
+
     Ok(tide::Response::new(200))
 }
 
-// Helper function to check if user is authenticated
-pub fn get_authenticated_user(req: &Request<AppState>) -> Result<String, tide::Error> {
-    if let Some(auth_header) = req.header("Authorization") {
-        // Handle the header properly - it might be an array or single value
-        let header_str = auth_header.to_string();
-        let token = if header_str.starts_with('[') && header_str.ends_with(']') {
-            // Remove brackets and quotes if present
-            header_str[1..header_str.len()-1].replace("\"", "").replace("Bearer ", "")
-        } else {
-            header_str.replace("Bearer ", "")
+// Function to logout everywhere: revokes every refresh and access token of the caller
+pub async fn logout_all(req: Request<AppState>) -> tide::Result {
+    let username = authenticated_user(&req)?.username;
+    let state = req.state();
+    let mut app_state = state.lock().unwrap();
+    let revoked = revoke_all_sessions(&mut app_state, &username);
+    info!(user = %username, revoked_tokens = revoked, "Logged out of all sessions");
+    Ok(tide::Body::from_json(&serde_json::json!({ "revoked_tokens": revoked }))?.into())
+}
+
+// Revokes every refresh token and every issued access token of a user.
+// Returns how many tokens were revoked.
+pub fn revoke_all_sessions(app_state: &mut AppStateInner, username: &str) -> usize {
+    let before = app_state.refresh_tokens.len();
+    app_state.refresh_tokens.retain(|_, info| info.username != username);
+    let mut revoked = before - app_state.refresh_tokens.len();
+
+    let jtis: Vec<(String, i64)> = app_state
+        .issued_access_tokens
+        .iter()
+        .filter(|(_, issued)| issued.username == username)
+        .map(|(jti, issued)| (jti.clone(), issued.expires_at.timestamp()))
+        .collect();
+    for (jti, exp) in jtis {
+        revoke_access_token(app_state, &jti, exp);
+        revoked += 1;
+    }
+    revoked
+}
+
+// Changes a user's password; if it actually changed, every session is revoked
+pub fn set_user_password(app_state: &mut AppStateInner, username: &str, password: String) {
+    let previous = app_state.users.insert(username.to_string(), password);
+    if previous.is_some_and(|previous| previous != app_state.users[username]) {
+        let revoked = revoke_all_sessions(app_state, username);
+        info!(user = %username, revoked_tokens = revoked, "Password changed, sessions revoked");
+    }
+}
+
+// Disables or re-enables a user. Disabling revokes every session and API key.
+pub fn set_user_disabled(app_state: &mut AppStateInner, username: &str, disabled: bool) {
+    if !disabled {
+        app_state.disabled_users.remove(username);
+        return;
+    }
+    if app_state.disabled_users.insert(username.to_string()) {
+        let revoked = revoke_all_sessions(app_state, username);
+        app_state.api_keys.retain(|_, key| key.owner != username);
+        info!(user = %username, revoked_tokens = revoked, "User disabled, sessions revoked");
+    }
+}
+
+// Adds an access token to the revocation list until it would have expired anyway
+fn revoke_access_token(app_state: &mut AppStateInner, jti: &str, exp: i64) {
+    app_state.issued_access_tokens.remove(jti);
+    let expires_at = chrono::DateTime::from_timestamp(exp, 0).unwrap_or_else(Utc::now);
+    app_state.revoked_access_tokens.insert(jti.to_string(), expires_at);
+}
+
+// Forgets tokens that have expired: they are rejected by signature validation anyway.
+// Access tokens are kept for the JWT leeway too, since validation accepts them
+// that long past `exp`. Returns how many entries were dropped.
+pub fn purge_expired_tokens(app_state: &mut AppStateInner, now: chrono::DateTime<Utc>) -> usize {
+    let before = app_state.revoked_access_tokens.len()
+        + app_state.issued_access_tokens.len()
+        + app_state.refresh_tokens.len();
+    let access_cutoff = now - Duration::seconds(get_jwt_leeway_secs() as i64);
+    app_state.revoked_access_tokens.retain(|_, expires_at| *expires_at > access_cutoff);
+    app_state.issued_access_tokens.retain(|_, issued| issued.expires_at > access_cutoff);
+    app_state.refresh_tokens.retain(|_, info| info.expires_at > now);
+    before
+        - app_state.revoked_access_tokens.len()
+        - app_state.issued_access_tokens.len()
+        - app_state.refresh_tokens.len()
+}
+
+// Background task that periodically purges expired tokens and stale login counters
+pub async fn run_token_cleanup(state: AppState) {
+    let interval = std::time::Duration::from_secs(get_token_cleanup_interval_secs());
+    loop {
+        async_std::task::sleep(interval).await;
+        let mut app_state = state.lock().unwrap();
+        let now = Utc::now();
+        let purged = purge_expired_tokens(&mut app_state, now);
+        if purged > 0 {
+            info!(purged = purged, "Expired tokens purged");
+        }
+        purge_expired_oidc_logins(&mut app_state, now);
+        let forgotten = purge_stale_login_attempts(&mut app_state.login_throttle, now);
+        if forgotten > 0 {
+            info!(forgotten = forgotten, "Stale failed-login counters purged");
+        }
+    }
+}
+
+// Why a refresh token was rejected
+#[derive(Debug, PartialEq)]
+enum RefreshError {
+    Invalid,
+    Expired,
+    Reused,
+}
+
+// Exchanges a refresh token for a new one in the same family, returning the
+// username, the family id and the new token. Presenting a token that was
+// already exchanged revokes the whole family.
+fn rotate_refresh_token(app_state: &mut AppStateInner, presented: &str, client: &SessionClient) -> Result<(String, String, String), RefreshError> {
+    // The token must be a valid refresh JWT before we look it up
+    let claims = decode_refresh_token(presented).map_err(|e| match e {
+        TokenError::Expired => RefreshError::Expired,
+        _ => RefreshError::Invalid,
+    })?;
+    let info = app_state
+        .refresh_tokens
+        .get(presented)
+        .cloned()
+        .filter(|info| info.username == claims.sub)
+        .ok_or(RefreshError::Invalid)?;
+
+    if info.rotated_at.is_some() {
+        revoke_session(app_state, &info.family_id);
+        return Err(RefreshError::Reused);
+    }
+    if info.expires_at <= Utc::now() {
+        app_state.refresh_tokens.remove(presented);
+        return Err(RefreshError::Expired);
+    }
+
+    let new_token = issue_refresh_token(app_state, &info.username, &info.family_id, info.issued_at, client)
+        .map_err(|_| RefreshError::Invalid)?;
+    // Keep the old token around (until it expires) so that reuse can be detected
+    if let Some(old) = app_state.refresh_tokens.get_mut(presented) {
+        old.rotated_at = Some(Utc::now());
+    }
+    Ok((info.username, info.family_id, new_token))
+}
+
+// Generates and stores a refresh token belonging to `family_id`, a session
+// that started at `issued_at`
+fn issue_refresh_token(
+    app_state: &mut AppStateInner,
+    username: &str,
+    family_id: &str,
+    issued_at: chrono::DateTime<Utc>,
+    client: &SessionClient,
+) -> Result<String, tide::Error> {
+    let refresh_token = generate_refresh_token(username)?;
+    let now = Utc::now();
+    let refresh_info = RefreshTokenInfo {
+        username: username.to_string(),
+        expires_at: now + Duration::days(get_refresh_token_expiration_days()),
+        family_id: family_id.to_string(),
+        rotated_at: None,
+        issued_at,
+        last_used_at: now,
+        user_agent: client.user_agent.clone(),
+        ip: client.ip.clone(),
+    };
+    app_state.refresh_tokens.insert(refresh_token.clone(), refresh_info);
+    Ok(refresh_token)
+}
+
+// Ends one session: removes every refresh token of the family and revokes the
+// access tokens issued for it. Returns how many tokens were revoked.
+pub fn revoke_session(app_state: &mut AppStateInner, family_id: &str) -> usize {
+    let before = app_state.refresh_tokens.len();
+    app_state.refresh_tokens.retain(|_, info| info.family_id != family_id);
+    let mut revoked = before - app_state.refresh_tokens.len();
+
+    let jtis: Vec<(String, i64)> = app_state
+        .issued_access_tokens
+        .iter()
+        .filter(|(_, issued)| issued.session_id == family_id)
+        .map(|(jti, issued)| (jti.clone(), issued.expires_at.timestamp()))
+        .collect();
+    for (jti, exp) in jtis {
+        revoke_access_token(app_state, &jti, exp);
+        revoked += 1;
+    }
+    revoked
+}
+
+// The user agent (truncated) and address of the client making a request
+pub fn session_client<State>(req: &Request<State>) -> SessionClient {
+    SessionClient {
+        user_agent: req
+            .header("User-Agent")
+            .map(|value| value.as_str().chars().take(MAX_USER_AGENT_LENGTH).collect()),
+        ip: client_ip(req),
+    }
+}
+
+// Builds a 401 response carrying a machine-readable error code
+pub fn auth_error(code: &str, description: &str) -> tide::Response {
+    let mut response = tide::Response::new(401);
+    response.set_body(serde_json::json!({
+        "error": code,
+        "error_description": description,
+    }));
+    response
+}
+
+// Random identifier used for `jti` claims, refresh token families and API keys
+pub fn generate_token_id() -> String {
+    rand::random::<[u8; 16]>()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// Middleware for protected routes: rejects unauthenticated requests and stores the
+// caller as an `AuthenticatedUser` extension
+pub struct Authenticate;
+
+#[tide::utils::async_trait]
+impl tide::Middleware<AppState> for Authenticate {
+    async fn handle(&self, mut req: Request<AppState>, next: tide::Next<'_, AppState>) -> tide::Result {
+        let user = match bearer_token(&req)? {
+            // Tokens issued by the OIDC identity provider are verified against its JWKS
+            Some(token) if is_idp_token(&token) => authenticate_idp_token(req.state(), &token).await?,
+            _ => authenticate(&req)?,
         };
-        
+        if req.state().read().unwrap().disabled_users.contains(&user.username) {
+            return Err(bearer_error(401, Some("invalid_token"), "Account disabled"));
+        }
+        debug!(user = %user.username, token_id = %user.token_id, "Request authenticated");
+        req.set_ext(user);
+        Ok(next.run(req).await)
+    }
+}
+
+// The caller of a protected route, as stored by the `Authenticate` middleware
+pub fn authenticated_user(req: &Request<AppState>) -> Result<AuthenticatedUser, tide::Error> {
+    req.ext::<AuthenticatedUser>()
+        .cloned()
+        .ok_or_else(|| tide::Error::from_str(500, "Route is not behind the authentication middleware"))
+}
+
+// Checks the bearer credentials of a request (access token or API key)
+fn authenticate(req: &Request<AppState>) -> Result<AuthenticatedUser, tide::Error> {
+    if let Some(token) = bearer_token(req)? {
+        if token.starts_with(API_KEY_PREFIX) {
+            let scope = required_scope(req.method(), req.url().path());
+            return authenticate_api_key(&mut req.state().lock().unwrap(), &token, scope);
+        }
+
         // Decode and validate access token
         match decode_access_token(&token) {
             Ok(claims) => {
-                if claims.token_type == "access" {
-                    Ok(claims.sub)
-                } else {
-                    Err(tide::Error::from_str(401, "Invalid token type"))
+                // Reject tokens revoked by logout, logout-all or a password change
+                let app_state = req.state().read().unwrap();
+                if app_state.revoked_access_tokens.contains_key(&claims.jti) {
+                    return Err(bearer_error(401, Some("invalid_token"), "Token revoked"));
                 }
+                Ok(authenticated_user_for(&app_state, claims.sub, claims.jti))
             },
-            Err(_) => Err(tide::Error::from_str(401, "Invalid token")),
+            Err(TokenError::Expired) => Err(bearer_error(401, Some("invalid_token"), "Token expired")),
+            Err(TokenError::WrongType) => Err(bearer_error(401, Some("invalid_token"), "Invalid token type")),
+            Err(TokenError::Invalid) => Err(bearer_error(401, Some("invalid_token"), "Invalid token")),
         }
     } else {
-        Err(tide::Error::from_str(401, "Missing authorization header"))
+        Err(bearer_error(401, None, "Missing authorization header"))
+    }
+}
+
+// Extracts the bearer token from the Authorization header; Ok(None) if no header was sent
+fn bearer_token(req: &Request<AppState>) -> Result<Option<String>, tide::Error> {
+    let values: Vec<&str> = req
+        .header("Authorization")
+        .map(|values| values.iter().map(|value| value.as_str()).collect())
+        .unwrap_or_default();
+    parse_authorization(&values).map(|token| token.map(str::to_string))
+}
+
+// Parses `Authorization` values per RFC 6750 section 2.1: `Bearer 1*SP b64token`,
+// with the scheme matched case-insensitively
+fn parse_authorization<'a>(values: &[&'a str]) -> Result<Option<&'a str>, tide::Error> {
+    let value = match values {
+        [] => return Ok(None),
+        [value] => value.trim(),
+        _ => return Err(bearer_error(400, Some("invalid_request"), "Multiple Authorization headers")),
+    };
+
+    let (scheme, token) = value.split_once(' ').unwrap_or((value, ""));
+    if !scheme.eq_ignore_ascii_case("Bearer") {
+        return Err(bearer_error(401, None, "Unsupported authorization scheme"));
+    }
+    let token = token.trim_start_matches(' ');
+    let body = token.trim_end_matches('=');
+    if body.is_empty() || !body.chars().all(|c| c.is_ascii_alphanumeric() || "-._~+/".contains(c)) {
+        return Err(bearer_error(400, Some("invalid_request"), "Malformed bearer token"));
+    }
+    Ok(Some(token))
+}
+
+// A failed bearer authentication; `add_www_authenticate` turns it into a challenge
+#[derive(Debug)]
+pub struct BearerError {
+    code: Option<&'static str>,
+    description: String,
+}
+
+impl std::fmt::Display for BearerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.description)
     }
 }
 
+impl std::error::Error for BearerError {}
+
+impl BearerError {
+    // RFC 6750 section 3 challenge; the error code is omitted when no credentials were sent
+    fn challenge(&self) -> String {
+        match self.code {
+            Some(code) => {
+                // error_description may not contain `"` or `\`
+                let description: String = self.description.chars().filter(|c| !matches!(c, '"' | '\\')).collect();
+                format!("Bearer realm=\"{}\", error=\"{}\", error_description=\"{}\"", AUTH_REALM, code, description)
+            }
+            None => format!("Bearer realm=\"{}\"", AUTH_REALM),
+        }
+    }
+}
+
+// Builds an authentication error; `code` is an RFC 6750 error code such as "invalid_token"
+pub fn bearer_error(status: u16, code: Option<&'static str>, description: impl Into<String>) -> tide::Error {
+    tide::Error::new(status, BearerError { code, description: description.into() })
+}
+
+// Middleware: adds a `WWW-Authenticate` challenge to bearer failures and every other 401
+pub async fn add_www_authenticate(mut res: tide::Response) -> tide::Result {
+    let challenge = match res.downcast_error::<BearerError>() {
+        Some(error) => Some(error.challenge()),
+        None if res.status() == tide::StatusCode::Unauthorized => Some(format!("Bearer realm=\"{}\"", AUTH_REALM)),
+        None => None,
+    };
+    if let Some(challenge) = challenge {
+        res.insert_header("WWW-Authenticate", challenge);
+    }
+    Ok(res)
+}
+
 // Function to convert CreateDataRequest to DataEntry
 pub fn create_data_entry_from_request(req_data: CreateDataRequest, owner: String) -> DataEntry {
     DataEntry {
-        func_names: req_data.func_names,
+        func_names: req_data.func_names.unwrap_or_default(), // Set by resolve_source
         bytecode: req_data.bytecode,
         owner,
+        host_api: req_data.host_api,
+        wasi: req_data.wasi,
+        pure_functions: req_data.pure_functions,
     }
 }
 
+// Generate an access token for a session and remember it so it can be revoked later
+fn issue_access_token(app_state: &mut AppStateInner, username: &str, session_id: &str) -> Result<String, tide::Error> {
+    let jti = generate_token_id();
+    let access_token = generate_access_token(username, &jti)?;
+    app_state.issued_access_tokens.insert(jti, IssuedAccessToken {
+        username: username.to_string(),
+        expires_at: Utc::now() + Duration::hours(get_access_token_expiration_hours()),
+        session_id: session_id.to_string(),
+    });
+    Ok(access_token)
+}
+
 // Generate access JWT token
-fn generate_access_token(username: &str) -> Result<String, tide::Error> {
+fn generate_access_token(username: &str, jti: &str) -> Result<String, tide::Error> {
     let now = Utc::now();
     let expires_at = now + Duration::hours(get_access_token_expiration_hours());
     
@@ -159,14 +594,13 @@ fn generate_access_token(username: &str) -> Result<String, tide::Error> {
         exp: expires_at.timestamp(),
         iat: now.timestamp(),
         iss: get_jwt_issuer(),
+        aud: get_jwt_audience(),
         token_type: "access".to_string(),
+        jti: jti.to_string(),
     };
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(get_jwt_secret().as_slice()),
-    )
+    let keys = signing_keys();
+    encode(&keys.header(), &claims, keys.encoding_key())
     .map_err(|_| tide::Error::from_str(500, "Failed to generate access token"))
 }
 
@@ -180,28 +614,85 @@ fn generate_refresh_token(username: &str) -> Result<String, tide::Error> {
         exp: expires_at.timestamp(),
         iat: now.timestamp(),
         iss: get_jwt_issuer(),
+        aud: get_jwt_audience(),
         token_type: "refresh".to_string(),
+        jti: generate_token_id(),
     };
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(get_jwt_secret().as_slice()),
-    )
+    let keys = signing_keys();
+    encode(&keys.header(), &claims, keys.encoding_key())
     .map_err(|_| tide::Error::from_str(500, "Failed to generate refresh token"))
 }
 
+// Generate the intermediate token of a login that still needs its second factor
+fn generate_mfa_token(username: &str) -> Result<String, tide::Error> {
+    let now = Utc::now();
+    let claims = Claims {
+        sub: username.to_string(),
+        exp: (now + Duration::seconds(get_mfa_token_expiration_secs())).timestamp(),
+        iat: now.timestamp(),
+        iss: get_jwt_issuer(),
+        aud: get_jwt_audience(),
+        token_type: "mfa_pending".to_string(),
+        jti: generate_token_id(),
+    };
+
+    let keys = signing_keys();
+    encode(&keys.header(), &claims, keys.encoding_key())
+    .map_err(|_| tide::Error::from_str(500, "Failed to generate MFA token"))
+}
+
+// Decode an intermediate MFA token, returning the username whose password was accepted
+pub fn decode_mfa_token(token: &str) -> Result<String, tide::Error> {
+    match decode_token(token, "mfa_pending") {
+        Ok(claims) => Ok(claims.sub),
+        Err(TokenError::Expired) => Err(tide::Error::from_str(401, "MFA token expired; log in again")),
+        Err(_) => Err(tide::Error::from_str(401, "Invalid MFA token")),
+    }
+}
+
+// Why a token failed validation
+#[derive(Debug, PartialEq)]
+enum TokenError {
+    Expired,
+    WrongType,
+    Invalid,
+}
+
 // Decode and validate access JWT token
-fn decode_access_token(token: &str) -> Result<Claims, tide::Error> {
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(get_jwt_secret().as_slice()),
-        &Validation::default(),
-    )
-    .map_err(|_| tide::Error::from_str(401, "Invalid token"))?;
+fn decode_access_token(token: &str) -> Result<Claims, TokenError> {
+    decode_token(token, "access")
+}
 
-    Ok(token_data.claims)
-} 
+// Decode and validate refresh JWT token
+fn decode_refresh_token(token: &str) -> Result<Claims, TokenError> {
+    decode_token(token, "refresh")
+}
+
+// Verifies signature, `exp` (with leeway), `iss`, `aud` and the token type
+fn decode_token(token: &str, expected_type: &str) -> Result<Claims, TokenError> {
+    let keys = signing_keys();
+    let header = decode_header(token).map_err(|_| TokenError::Invalid)?;
+    // Tokens must be signed with our algorithm by a key we know (by `kid`)
+    let key = keys.decoding_key(header.kid.as_deref()).ok_or(TokenError::Invalid)?;
+
+    let mut validation = Validation::new(keys.algorithm);
+    validation.set_issuer(&[get_jwt_issuer()]);
+    validation.set_audience(&[get_jwt_audience()]);
+    validation.set_required_spec_claims(&["exp", "iat", "iss", "aud", "sub"]);
+    validation.leeway = get_jwt_leeway_secs();
+
+    let claims = decode::<Claims>(token, key, &validation)
+        .map_err(|e| match e.kind() {
+            jsonwebtoken::errors::ErrorKind::ExpiredSignature => TokenError::Expired,
+            _ => TokenError::Invalid,
+        })?
+        .claims;
+    if claims.token_type != expected_type {
+        return Err(TokenError::WrongType);
+    }
+    Ok(claims)
+}
 
 #[cfg(test)]
 mod tests {
@@ -209,28 +700,52 @@ mod tests {
     use crate::state::AppState;
     use std::collections::HashMap;
     use std::sync::Arc;
-    use std::sync::Mutex;
-
     fn create_test_state() -> AppState {
         let mut users = HashMap::new();
         users.insert("test_user".to_string(), "test_pass".to_string());
         users.insert("admin".to_string(), "admin123".to_string());
 
-        Arc::new(Mutex::new(crate::state::AppStateInner {
+        Arc::new(crate::state::SharedState::new(crate::state::AppStateInner {
             data: HashMap::new(),
+            trash: HashMap::new(),
+            next_id: 1,
             users,
+            disabled_users: std::collections::HashSet::new(),
             refresh_tokens: HashMap::new(),
-            wasm_cache: HashMap::new(),
-            metrics: crate::state::Metrics::default(),
+            issued_access_tokens: HashMap::new(),
+            revoked_access_tokens: HashMap::new(),
+            api_keys: HashMap::new(),
+            login_throttle: crate::state::LoginThrottle::default(),
+            oidc_logins: HashMap::new(),
+            external_users: HashMap::new(),
+            mfa: HashMap::new(),
+            wasm_features: HashMap::new(),
+            wasm_cache: crate::state::ModuleCache::default(),
+            results: Default::default(),
+            jobs: HashMap::new(),
+            job_queue: std::collections::VecDeque::new(),
+            instances: HashMap::new(),
             rate_limiter: crate::state::RateLimiter::default(),
         }))
     }
 
+    fn client() -> SessionClient {
+        SessionClient {
+            user_agent: Some("curl/8.0".to_string()),
+            ip: "10.0.0.1".to_string(),
+        }
+    }
+
     #[test]
     fn test_create_data_entry_from_request() {
         let request = CreateDataRequest {
-            func_names: vec!["add".to_string(), "mul".to_string()],
+            func_names: Some(vec!["add".to_string(), "mul".to_string()]),
             bytecode: vec![1, 2, 3, 4, 5],
+            host_api: None,
+            wasi: false,
+            pure_functions: vec![],
+            format: None,
+            source: None,
         };
 
         let entry = create_data_entry_from_request(request, "test_user".to_string());
@@ -243,7 +758,7 @@ mod tests {
     #[test]
     fn test_generate_access_token() {
         let username = "test_user".to_string();
-        let token = generate_access_token(&username).unwrap();
+        let token = generate_access_token(&username, "test_jti").unwrap();
 
         // Verify token is not empty
         assert!(!token.is_empty());
@@ -256,6 +771,7 @@ mod tests {
         assert_eq!(claims.sub, username);
         assert_eq!(claims.iss, get_jwt_issuer());
         assert_eq!(claims.token_type, "access");
+        assert_eq!(claims.jti, "test_jti");
     }
 
     #[test]
@@ -266,16 +782,64 @@ mod tests {
         // Verify token is not empty
         assert!(!token.is_empty());
 
-        // Verify token can be decoded
-        let decoded = decode_access_token(&token);
+        // Verify token can be decoded as a refresh token only
+        let decoded = decode_refresh_token(&token);
         assert!(decoded.is_ok());
+        assert_eq!(decode_access_token(&token).unwrap_err(), TokenError::WrongType);
 
         let claims = decoded.unwrap();
         assert_eq!(claims.sub, username);
         assert_eq!(claims.iss, get_jwt_issuer());
+        assert_eq!(claims.aud, get_jwt_audience());
         assert_eq!(claims.token_type, "refresh");
     }
 
+    #[test]
+    fn test_access_token_is_not_a_refresh_token() {
+        let token = generate_access_token("test_user", "test_jti").unwrap();
+        assert_eq!(decode_refresh_token(&token).unwrap_err(), TokenError::WrongType);
+    }
+
+    // Signs arbitrary claims with the server key
+    fn sign(claims: serde_json::Value) -> String {
+        let keys = signing_keys();
+        encode(&keys.header(), &claims, keys.encoding_key()).unwrap()
+    }
+
+    #[test]
+    fn test_decode_rejects_wrong_issuer_and_audience() {
+        let now = Utc::now().timestamp();
+        let valid = serde_json::json!({
+            "sub": "test_user", "exp": now + 60, "iat": now, "iss": get_jwt_issuer(),
+            "aud": get_jwt_audience(), "token_type": "access", "jti": "j",
+        });
+        assert!(decode_access_token(&sign(valid.clone())).is_ok());
+
+        let mut wrong_issuer = valid.clone();
+        wrong_issuer["iss"] = "someone-else".into();
+        assert_eq!(decode_access_token(&sign(wrong_issuer)).unwrap_err(), TokenError::Invalid);
+
+        let mut wrong_audience = valid.clone();
+        wrong_audience["aud"] = "another-api".into();
+        assert_eq!(decode_access_token(&sign(wrong_audience)).unwrap_err(), TokenError::Invalid);
+
+        let mut missing_audience = valid;
+        missing_audience.as_object_mut().unwrap().remove("aud");
+        assert_eq!(decode_access_token(&sign(missing_audience)).unwrap_err(), TokenError::Invalid);
+    }
+
+    #[test]
+    fn test_decode_applies_leeway() {
+        let now = Utc::now().timestamp();
+        let claims = |exp: i64| serde_json::json!({
+            "sub": "test_user", "exp": exp, "iat": now - 3600, "iss": get_jwt_issuer(),
+            "aud": get_jwt_audience(), "token_type": "access", "jti": "j",
+        });
+        // Within the default 60s leeway
+        assert!(decode_access_token(&sign(claims(now - 10))).is_ok());
+        assert_eq!(decode_access_token(&sign(claims(now - 120))).unwrap_err(), TokenError::Expired);
+    }
+
     #[test]
     fn test_decode_access_token_invalid() {
         let result = decode_access_token("invalid_token");
@@ -286,10 +850,157 @@ mod tests {
     fn test_environment_variables() {
         // Test default values
         assert_eq!(get_jwt_issuer(), "learn-rust-crud");
+        assert_eq!(get_jwt_audience(), "learn-rust-crud-api");
+        assert_eq!(get_jwt_leeway_secs(), 60);
         assert_eq!(get_access_token_expiration_hours(), 1);
         assert_eq!(get_refresh_token_expiration_days(), 30);
     }
 
+    #[test]
+    fn test_refresh_token_rotation() {
+        let state = create_test_state();
+        let mut app_state = state.lock().unwrap();
+        let started = Utc::now() - Duration::hours(1);
+        let first = issue_refresh_token(&mut app_state, "test_user", "family_1", started, &client()).unwrap();
+
+        let phone = SessionClient { user_agent: Some("phone".to_string()), ip: "10.0.0.2".to_string() };
+        let (username, family_id, second) = rotate_refresh_token(&mut app_state, &first, &phone).unwrap();
+        assert_eq!(username, "test_user");
+        assert_eq!(family_id, "family_1");
+        assert_ne!(first, second);
+        assert!(app_state.refresh_tokens.get(&first).unwrap().rotated_at.is_some());
+        // The new token keeps the session start and records the refreshing client
+        let rotated = app_state.refresh_tokens.get(&second).unwrap();
+        assert_eq!(rotated.family_id, "family_1");
+        assert_eq!(rotated.issued_at, started);
+        assert!(rotated.last_used_at > started);
+        assert_eq!(rotated.user_agent.as_deref(), Some("phone"));
+        assert_eq!(rotated.ip, "10.0.0.2");
+
+        let (_, _, third) = rotate_refresh_token(&mut app_state, &second, &client()).unwrap();
+        assert_ne!(second, third);
+    }
+
+    #[test]
+    fn test_refresh_token_reuse_revokes_family() {
+        let state = create_test_state();
+        let mut app_state = state.lock().unwrap();
+        let first = issue_refresh_token(&mut app_state, "test_user", "family_1", Utc::now(), &client()).unwrap();
+        let other = issue_refresh_token(&mut app_state, "test_user", "family_2", Utc::now(), &client()).unwrap();
+        let (_, _, second) = rotate_refresh_token(&mut app_state, &first, &client()).unwrap();
+
+        assert_eq!(rotate_refresh_token(&mut app_state, &first, &client()), Err(RefreshError::Reused));
+        assert_eq!(rotate_refresh_token(&mut app_state, &second, &client()), Err(RefreshError::Invalid));
+
+        // Sessions from other logins are untouched
+        assert!(rotate_refresh_token(&mut app_state, &other, &client()).is_ok());
+    }
+
+    #[test]
+    fn test_refresh_token_expired() {
+        let state = create_test_state();
+        let mut app_state = state.lock().unwrap();
+        let token = issue_refresh_token(&mut app_state, "test_user", "family_1", Utc::now(), &client()).unwrap();
+        app_state.refresh_tokens.get_mut(&token).unwrap().expires_at = Utc::now() - Duration::seconds(1);
+
+        assert_eq!(rotate_refresh_token(&mut app_state, &token, &client()), Err(RefreshError::Expired));
+        assert!(!app_state.refresh_tokens.contains_key(&token));
+        assert_eq!(rotate_refresh_token(&mut app_state, "unknown", &client()), Err(RefreshError::Invalid));
+    }
+
+    #[test]
+    fn test_revoke_all_sessions() {
+        let state = create_test_state();
+        let mut app_state = state.lock().unwrap();
+        issue_access_token(&mut app_state, "test_user", "family_1").unwrap();
+        issue_access_token(&mut app_state, "admin", "family_1").unwrap();
+        issue_refresh_token(&mut app_state, "test_user", "family_1", Utc::now(), &client()).unwrap();
+
+        assert_eq!(revoke_all_sessions(&mut app_state, "test_user"), 2);
+        assert_eq!(app_state.revoked_access_tokens.len(), 1);
+        assert_eq!(app_state.issued_access_tokens.len(), 1);
+        assert!(app_state.refresh_tokens.is_empty());
+    }
+
+    #[test]
+    fn test_revoke_session_only_touches_its_family() {
+        let state = create_test_state();
+        let mut app_state = state.lock().unwrap();
+        issue_access_token(&mut app_state, "test_user", "family_1").unwrap();
+        issue_access_token(&mut app_state, "test_user", "family_2").unwrap();
+        issue_refresh_token(&mut app_state, "test_user", "family_1", Utc::now(), &client()).unwrap();
+        let other = issue_refresh_token(&mut app_state, "test_user", "family_2", Utc::now(), &client()).unwrap();
+
+        assert_eq!(revoke_session(&mut app_state, "family_1"), 2);
+        assert_eq!(app_state.revoked_access_tokens.len(), 1);
+        assert!(app_state.issued_access_tokens.values().all(|issued| issued.session_id == "family_2"));
+        assert_eq!(app_state.refresh_tokens.keys().collect::<Vec<_>>(), vec![&other]);
+    }
+
+    #[test]
+    fn test_password_change_revokes_sessions() {
+        let state = create_test_state();
+        let mut app_state = state.lock().unwrap();
+        issue_access_token(&mut app_state, "test_user", "family_1").unwrap();
+
+        // Same password: nothing is revoked
+        set_user_password(&mut app_state, "test_user", "test_pass".to_string());
+        assert!(app_state.revoked_access_tokens.is_empty());
+
+        set_user_password(&mut app_state, "test_user", "new_pass".to_string());
+        assert_eq!(app_state.revoked_access_tokens.len(), 1);
+        assert_eq!(app_state.users["test_user"], "new_pass");
+    }
+
+    #[test]
+    fn test_purge_expired_tokens() {
+        let state = create_test_state();
+        let mut app_state = state.lock().unwrap();
+        let now = Utc::now();
+        let leeway = Duration::seconds(get_jwt_leeway_secs() as i64);
+        app_state.revoked_access_tokens.insert("old".to_string(), now - leeway - Duration::seconds(1));
+        app_state.revoked_access_tokens.insert("live".to_string(), now + Duration::hours(1));
+
+        assert_eq!(purge_expired_tokens(&mut app_state, now), 1);
+        assert!(app_state.revoked_access_tokens.contains_key("live"));
+    }
+
+    #[test]
+    fn test_purge_keeps_revocations_within_the_leeway() {
+        let state = create_test_state();
+        let mut app_state = state.lock().unwrap();
+        let now = Utc::now();
+        // Expired a second ago, but validation still accepts it for the leeway
+        let exp = (now - Duration::seconds(1)).timestamp();
+        revoke_access_token(&mut app_state, "jti-1", exp);
+
+        assert_eq!(purge_expired_tokens(&mut app_state, now), 0);
+        assert!(app_state.revoked_access_tokens.contains_key("jti-1"));
+        let past_leeway = now + Duration::seconds(get_jwt_leeway_secs() as i64);
+        assert_eq!(purge_expired_tokens(&mut app_state, past_leeway), 1);
+    }
+
+    #[test]
+    fn test_is_admin_default() {
+        assert!(is_admin("admin"));
+        assert!(!is_admin("user1"));
+        assert!(!is_admin(""));
+    }
+
+    #[test]
+    fn test_authenticated_user_roles() {
+        let state = create_test_state();
+        let app_state = state.lock().unwrap();
+        let admin = authenticated_user_for(&app_state, "admin".to_string(), "jti-1".to_string());
+        assert!(admin.has_role(ROLE_USER));
+        assert!(admin.has_role(ROLE_ADMIN));
+        assert_eq!(admin.token_id, "jti-1");
+
+        let user = authenticated_user_for(&app_state, "user1".to_string(), "jti-2".to_string());
+        assert!(user.has_role(ROLE_USER));
+        assert!(!user.has_role(ROLE_ADMIN));
+    }
+
     #[test]
     fn test_claims_creation() {
         let username = "test_user".to_string();
@@ -298,9 +1009,11 @@ mod tests {
         let claims = Claims {
             sub: username.clone(),
             iss: get_jwt_issuer(),
+            aud: get_jwt_audience(),
             exp: (now + chrono::Duration::hours(1)).timestamp(),
             iat: now.timestamp(),
             token_type: "access".to_string(),
+            jti: generate_token_id(),
         };
 
         assert_eq!(claims.sub, username);
@@ -308,4 +1021,30 @@ mod tests {
         assert_eq!(claims.token_type, "access");
         assert!(claims.exp > claims.iat);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_parse_authorization() {
+        assert_eq!(parse_authorization(&[]).unwrap(), None);
+        assert_eq!(parse_authorization(&["Bearer abc.def-ghi"]).unwrap(), Some("abc.def-ghi"));
+        assert_eq!(parse_authorization(&["bearer   abc=="]).unwrap(), Some("abc=="));
+        assert_eq!(parse_authorization(&["BEARER abc"]).unwrap(), Some("abc"));
+
+        // Malformed headers are rejected instead of being patched up
+        for value in ["Bearer", "Bearer ", "Bearer a b", "Bearer \"abc\"", "Bearer a=b", "[\"Bearer abc\"]"] {
+            assert!(parse_authorization(&[value]).is_err(), "{}", value);
+        }
+        assert_eq!(parse_authorization(&["Basic dXNlcjpwdw=="]).unwrap_err().status(), 401);
+        assert_eq!(parse_authorization(&["Bearer a", "Bearer b"]).unwrap_err().status(), 400);
+    }
+
+    #[test]
+    fn test_www_authenticate_challenge() {
+        let error = BearerError { code: Some("invalid_token"), description: "Token \"expired\"".to_string() };
+        assert_eq!(
+            error.challenge(),
+            "Bearer realm=\"learn-rust-crud\", error=\"invalid_token\", error_description=\"Token expired\""
+        );
+        let missing = BearerError { code: None, description: "Missing authorization header".to_string() };
+        assert_eq!(missing.challenge(), "Bearer realm=\"learn-rust-crud\"");
+    }
+}
@@ -0,0 +1,108 @@
+// Command-line client for the admin export/import endpoints.
+//
+//   learn-rust-crud export [--server URL] [--token TOKEN] [--output FILE]
+//   learn-rust-crud import FILE [--server URL] [--token TOKEN] [--conflict skip|overwrite|renumber]
+//
+// The token defaults to the ADMIN_TOKEN environment variable and the server
+// to SERVER_ADDR, so the same .env works for both the server and the CLI.
+use std::env;
+use std::fs;
+use std::io::Write;
+
+const USAGE: &str = "Usage:
+  learn-rust-crud export [--server URL] [--token TOKEN] [--output FILE]
+  learn-rust-crud import FILE [--server URL] [--token TOKEN] [--conflict skip|overwrite|renumber]";
+
+struct CliOptions {
+    server: String,
+    token: String,
+    output: Option<String>,
+    conflict: String,
+    positional: Vec<String>,
+}
+
+// Returns true if `command` is a CLI subcommand rather than a server start
+pub fn is_subcommand(command: &str) -> bool {
+    matches!(command, "export" | "import")
+}
+
+pub fn run(command: &str, args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    match command {
+        "export" => export(&options),
+        "import" => import(&options),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<CliOptions, String> {
+    let default_addr = env::var("SERVER_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".to_string());
+    let mut options = CliOptions {
+        server: format!("http://{}", default_addr),
+        token: env::var("ADMIN_TOKEN").unwrap_or_default(),
+        output: None,
+        conflict: "skip".to_string(),
+        positional: Vec::new(),
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = |flag: &str| {
+            iter.next()
+                .cloned()
+                .ok_or_else(|| format!("Missing value for {}\n{}", flag, USAGE))
+        };
+        match arg.as_str() {
+            "--server" => options.server = value("--server")?,
+            "--token" => options.token = value("--token")?,
+            "--output" => options.output = Some(value("--output")?),
+            "--conflict" => options.conflict = value("--conflict")?,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}\n{}", flag, USAGE)),
+            _ => options.positional.push(arg.clone()),
+        }
+    }
+
+    if options.token.is_empty() {
+        return Err("Missing admin token: pass --token or set ADMIN_TOKEN".to_string());
+    }
+    Ok(options)
+}
+
+fn export(options: &CliOptions) -> Result<(), String> {
+    let archive = ureq::get(&format!("{}/admin/export", options.server.trim_end_matches('/')))
+        .set("Authorization", &format!("Bearer {}", options.token))
+        .call()
+        .map_err(|e| format!("Export failed: {}", e))?
+        .into_string()
+        .map_err(|e| format!("Failed to read export: {}", e))?;
+
+    match &options.output {
+        Some(path) => fs::write(path, &archive).map_err(|e| format!("Failed to write {}: {}", path, e)),
+        None => std::io::stdout()
+            .write_all(archive.as_bytes())
+            .map_err(|e| format!("Failed to write archive: {}", e)),
+    }
+}
+
+fn import(options: &CliOptions) -> Result<(), String> {
+    let path = options
+        .positional
+        .first()
+        .ok_or_else(|| format!("Missing archive file\n{}", USAGE))?;
+    let archive = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+    let report = ureq::post(&format!(
+        "{}/admin/import?conflict={}",
+        options.server.trim_end_matches('/'),
+        options.conflict
+    ))
+    .set("Authorization", &format!("Bearer {}", options.token))
+    .set("Content-Type", "application/x-ndjson")
+    .send_string(&archive)
+    .map_err(|e| format!("Import failed: {}", e))?
+    .into_string()
+    .map_err(|e| format!("Failed to read import report: {}", e))?;
+
+    println!("{}", report);
+    Ok(())
+}
@@ -0,0 +1,297 @@
+use crate::auth::{authenticated_user, set_user_disabled, set_user_password, ROLE_ADMIN};
+use crate::models::{ArchiveLine, ConflictPolicy, ImportQuery, ImportReport};
+use crate::state::{AppState, AppStateInner};
+use chrono::Utc;
+use std::time::Instant;
+use tide::Request;
+use tracing::info;
+
+pub const ARCHIVE_FORMAT: &str = "learn-rust-crud-archive";
+pub const ARCHIVE_VERSION: u32 = 1;
+
+// Streams users, records and trash as a JSONL archive (admin only)
+pub async fn export_data(req: Request<AppState>) -> tide::Result {
+    let start_time = Instant::now();
+    let user = authenticated_user(&req)?;
+    if !user.has_role(ROLE_ADMIN) {
+        return Err(tide::Error::from_str(403, "Access denied: export requires admin privileges"));
+    }
+    let username = user.username;
+    info!(user = %username, "Export started");
+
+    let archive = {
+        let app_state = req.state().read().unwrap();
+        export_archive(&app_state)?
+    };
+
+    let execution_time = start_time.elapsed();
+    info!(user = %username, archive_bytes = archive.len(), execution_time_ms = execution_time.as_millis(), "Export completed successfully");
+    let filename = format!("export-{}.jsonl", Utc::now().format("%Y%m%dT%H%M%SZ"));
+    Ok(tide::Response::builder(200)
+        .body(archive)
+        .content_type("application/x-ndjson")
+        .header("Content-Disposition", format!("attachment; filename=\"{}\"", filename))
+        .build())
+}
+
+// Loads a JSONL archive produced by `export_data` (admin only)
+pub async fn import_data(mut req: Request<AppState>) -> tide::Result {
+    let start_time = Instant::now();
+    let user = authenticated_user(&req)?;
+    if !user.has_role(ROLE_ADMIN) {
+        return Err(tide::Error::from_str(403, "Access denied: import requires admin privileges"));
+    }
+    let username = user.username;
+    let query: ImportQuery = req
+        .query()
+        .map_err(|_| tide::Error::from_str(400, "Invalid query: expected ?conflict=skip|overwrite|renumber"))?;
+    info!(user = %username, conflict = ?query.conflict, "Import started");
+
+    let body = req.body_string().await?;
+    let lines = parse_archive(&body).map_err(|e| tide::Error::from_str(400, e))?;
+
+    let report = {
+        let mut app_state = req.state().lock().unwrap();
+        import_archive(&mut app_state, lines, query.conflict)
+    };
+
+    let execution_time = start_time.elapsed();
+    info!(
+        user = %username,
+        records_imported = report.records_imported,
+        records_skipped = report.records_skipped,
+        users_imported = report.users_imported,
+        execution_time_ms = execution_time.as_millis(),
+        "Import completed successfully"
+    );
+    Ok(tide::Body::from_json(&report)?.into())
+}
+
+// Serializes the whole state, one JSON object per line, ordered by id
+pub fn export_archive(app_state: &AppStateInner) -> serde_json::Result<String> {
+    let mut lines = vec![ArchiveLine::Header {
+        format: ARCHIVE_FORMAT.to_string(),
+        version: ARCHIVE_VERSION,
+        exported_at: Utc::now(),
+        next_id: app_state.next_id,
+    }];
+
+    let mut users: Vec<_> = app_state.users.iter().collect();
+    users.sort();
+    lines.extend(users.into_iter().map(|(username, password)| ArchiveLine::User {
+        username: username.clone(),
+        password: password.clone(),
+        disabled: app_state.disabled_users.contains(username),
+    }));
+
+    let mut records: Vec<_> = app_state.data.iter().collect();
+    records.sort_by_key(|(id, _)| **id);
+    lines.extend(records.into_iter().map(|(id, entry)| ArchiveLine::Record { id: *id, entry: entry.clone() }));
+
+    let mut trashed: Vec<_> = app_state.trash.iter().collect();
+    trashed.sort_by_key(|(id, _)| **id);
+    lines.extend(trashed.into_iter().map(|(id, trashed)| ArchiveLine::Trashed { id: *id, trashed: trashed.clone() }));
+
+    let mut archive = String::new();
+    for line in &lines {
+        archive.push_str(&serde_json::to_string(line)?);
+        archive.push('\n');
+    }
+    Ok(archive)
+}
+
+// Parses and validates an archive without touching the state
+pub fn parse_archive(body: &str) -> Result<Vec<ArchiveLine>, String> {
+    let mut lines = Vec::new();
+    for (number, raw) in body.lines().enumerate().filter(|(_, raw)| !raw.trim().is_empty()) {
+        let line: ArchiveLine = serde_json::from_str(raw)
+            .map_err(|e| format!("Invalid archive line {}: {}", number + 1, e))?;
+        lines.push(line);
+    }
+
+    match lines.first() {
+        Some(ArchiveLine::Header { format, version, .. }) => {
+            if format != ARCHIVE_FORMAT {
+                return Err(format!("Unsupported archive format '{}'", format));
+            }
+            if *version != ARCHIVE_VERSION {
+                return Err(format!("Unsupported archive version {} (expected {})", version, ARCHIVE_VERSION));
+            }
+        }
+        _ => return Err("Archive must start with a header line".to_string()),
+    }
+    if lines.iter().skip(1).any(|line| matches!(line, ArchiveLine::Header { .. })) {
+        return Err("Archive contains more than one header".to_string());
+    }
+    Ok(lines)
+}
+
+// Applies parsed archive lines using the given conflict policy
+pub fn import_archive(app_state: &mut AppStateInner, lines: Vec<ArchiveLine>, policy: ConflictPolicy) -> ImportReport {
+    let mut report = ImportReport::default();
+
+    // Renumbered records get ids past every id in the archive, so they never
+    // land on one that a later line claims
+    let highest_id = lines
+        .iter()
+        .filter_map(|line| match line {
+            ArchiveLine::Record { id, .. } | ArchiveLine::Trashed { id, .. } => Some(*id),
+            _ => None,
+        })
+        .max();
+    if let Some(highest_id) = highest_id {
+        app_state.next_id = app_state.next_id.max(highest_id + 1);
+    }
+
+    for line in lines {
+        match line {
+            ArchiveLine::Header { .. } => {}
+            ArchiveLine::User { username, password, disabled } => {
+                if app_state.users.contains_key(&username) && policy != ConflictPolicy::Overwrite {
+                    report.users_skipped += 1;
+                } else {
+                    set_user_password(app_state, &username, password);
+                    set_user_disabled(app_state, &username, disabled);
+                    report.users_imported += 1;
+                }
+            }
+            ArchiveLine::Record { id, entry } => {
+                if let Some(id) = claim_id(app_state, id, policy, &mut report) {
+                    app_state.data.insert(id, entry);
+                }
+            }
+            ArchiveLine::Trashed { id, trashed } => {
+                if let Some(id) = claim_id(app_state, id, policy, &mut report) {
+                    app_state.trash.insert(id, trashed);
+                }
+            }
+        }
+    }
+    report
+}
+
+// Decides which id an imported record lands on, or None if it is skipped
+fn claim_id(app_state: &mut AppStateInner, id: u32, policy: ConflictPolicy, report: &mut ImportReport) -> Option<u32> {
+    let taken = app_state.data.contains_key(&id) || app_state.trash.contains_key(&id);
+    if !taken {
+        report.records_imported += 1;
+        return Some(id);
+    }
+    match policy {
+        ConflictPolicy::Skip => {
+            report.records_skipped += 1;
+            None
+        }
+        ConflictPolicy::Overwrite => {
+            if let Some(previous) = app_state.data.remove(&id) {
+                app_state.results.invalidate(&previous.bytecode);
+            }
+            app_state.trash.remove(&id);
+            app_state.wasm_cache.remove(&id);
+            report.records_imported += 1;
+            report.records_overwritten += 1;
+            Some(id)
+        }
+        ConflictPolicy::Renumber => {
+            let new_id = app_state.allocate_id();
+            report.records_imported += 1;
+            report.renumbered.insert(id, new_id);
+            Some(new_id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DataEntry;
+    use crate::state::new_state;
+
+    fn entry(owner: &str, func: &str) -> DataEntry {
+        DataEntry {
+            func_names: vec![func.to_string()],
+            bytecode: vec![0, 97, 115, 109],
+            owner: owner.to_string(),
+            host_api: None,
+            wasi: false,
+            pure_functions: vec![],
+        }
+    }
+
+    fn exported_archive() -> String {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        for func in ["add", "mul"] {
+            let id = app_state.allocate_id();
+            app_state.data.insert(id, entry("admin", func));
+        }
+        export_archive(&app_state).unwrap()
+    }
+
+    #[test]
+    fn test_export_then_import_roundtrip() {
+        let archive = exported_archive();
+        let lines = parse_archive(&archive).unwrap();
+        assert!(matches!(lines[0], ArchiveLine::Header { version: ARCHIVE_VERSION, .. }));
+
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        app_state.users.clear();
+        let report = import_archive(&mut app_state, lines, ConflictPolicy::Skip);
+
+        assert_eq!(report.records_imported, 2);
+        assert_eq!(report.users_imported, 3);
+        assert_eq!(app_state.data.get(&2).unwrap().func_names, vec!["mul"]);
+        assert_eq!(app_state.next_id, 3);
+    }
+
+    #[test]
+    fn test_import_conflict_policies() {
+        let archive = exported_archive();
+
+        for (policy, expected_func, renumbered) in [
+            (ConflictPolicy::Skip, "sub", 0),
+            (ConflictPolicy::Overwrite, "add", 0),
+            (ConflictPolicy::Renumber, "sub", 1),
+        ] {
+            let state = new_state();
+            let mut app_state = state.lock().unwrap();
+            let id = app_state.allocate_id();
+            app_state.data.insert(id, entry("user1", "sub"));
+
+            let report = import_archive(&mut app_state, parse_archive(&archive).unwrap(), policy);
+
+            assert_eq!(app_state.data.get(&1).unwrap().func_names, vec![expected_func], "{:?}", policy);
+            assert_eq!(report.renumbered.len(), renumbered, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_renumber_never_replaces_an_imported_record() {
+        // Server holds 1; the archive lists record 2 before record 1
+        let mut lines = parse_archive(&exported_archive()).unwrap();
+        lines[1..].reverse();
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        let id = app_state.allocate_id();
+        app_state.data.insert(id, entry("user1", "sub"));
+
+        let report = import_archive(&mut app_state, lines, ConflictPolicy::Renumber);
+
+        assert_eq!(report.records_imported, 2);
+        assert_eq!(report.renumbered, [(1, 3)].into());
+        let funcs: Vec<&str> = (1..=3).map(|id| app_state.data[&id].func_names[0].as_str()).collect();
+        assert_eq!(funcs, ["sub", "mul", "add"]);
+        assert_eq!(app_state.next_id, 4);
+    }
+
+    #[test]
+    fn test_parse_archive_rejects_bad_input() {
+        assert!(parse_archive("").is_err());
+        assert!(parse_archive("{\"kind\":\"user\",\"username\":\"a\",\"password\":\"b\"}").is_err());
+        assert!(parse_archive("not json").unwrap_err().contains("line 1"));
+
+        let future = exported_archive().replacen("\"version\":1", "\"version\":99", 1);
+        assert!(parse_archive(&future).unwrap_err().contains("version 99"));
+    }
+}
@@ -0,0 +1,286 @@
+use crate::auth::{authenticated_user, create_data_entry_from_request};
+use crate::handlers::trash::move_to_trash;
+use crate::memo::check_pure_functions;
+use crate::models::{BulkItemResult, BulkMode, BulkOperation, BulkRequest, BulkResponse, CreateDataRequest};
+use crate::state::{AppState, AppStateInner};
+use crate::wasm::features::{allowed_features, FeatureSet};
+use crate::wasm::text::resolve_source;
+use crate::wasm::validate_upload;
+use std::env;
+use std::time::Instant;
+use tide::Request;
+use tracing::info;
+
+// Upper bound on operations accepted in a single bulk request
+fn get_bulk_max_operations() -> usize {
+    env::var("BULK_MAX_OPERATIONS")
+        .unwrap_or_else(|_| "1000".to_string())
+        .parse()
+        .unwrap_or(1000)
+}
+
+pub async fn bulk_data(mut req: Request<AppState>) -> tide::Result {
+    let start_time = Instant::now();
+    let username = authenticated_user(&req)?.username;
+    let mut bulk_req: BulkRequest = req.body_json().await?;
+    info!(user = %username, mode = ?bulk_req.mode, total_operations = bulk_req.operations.len(), "Bulk operation started");
+
+    let max_operations = get_bulk_max_operations();
+    if bulk_req.operations.len() > max_operations {
+        return Err(tide::Error::from_str(
+            413,
+            format!("Too many operations: {} (max {})", bulk_req.operations.len(), max_operations),
+        ));
+    }
+
+    // Modules are compiled and validated before the write lock is taken, so
+    // other requests only wait for the changes themselves
+    let state = req.state();
+    let allowed = allowed_features(&state.read().unwrap(), &username);
+    let checks = check_modules(&mut bulk_req.operations, &allowed);
+    let mut app_state = state.lock().unwrap();
+    let response = apply_bulk(&mut app_state, bulk_req, checks, &username);
+
+    let execution_time = start_time.elapsed();
+    info!(
+        user = %username,
+        total_operations = response.total_operations,
+        successful_operations = response.successful_operations,
+        committed = response.committed,
+        execution_time_ms = execution_time.as_millis(),
+        "Bulk operation completed"
+    );
+    Ok(tide::Body::from_json(&response)?.into())
+}
+
+// Checks the module of every create and update, one result per operation
+fn check_modules(operations: &mut [BulkOperation], allowed: &FeatureSet) -> Vec<Result<(), (u16, String)>> {
+    operations
+        .iter_mut()
+        .map(|operation| match operation {
+            BulkOperation::Create { data } | BulkOperation::Update { data, .. } => check_module(data, allowed),
+            BulkOperation::Delete { .. } => Ok(()),
+        })
+        .collect()
+}
+
+// Applies every operation in order, given the results of `check_modules`. In
+// all-or-nothing mode the first failure restores the state as it was before
+// the request.
+fn apply_bulk(app_state: &mut AppStateInner, bulk_req: BulkRequest, checks: Vec<Result<(), (u16, String)>>, username: &str) -> BulkResponse {
+    let total_operations = bulk_req.operations.len();
+    let snapshot = match bulk_req.mode {
+        BulkMode::AllOrNothing => Some((
+            app_state.data.clone(),
+            app_state.trash.clone(),
+            app_state.next_id,
+        )),
+        BulkMode::BestEffort => None,
+    };
+
+    let mut results = Vec::with_capacity(total_operations);
+    let mut failed = false;
+    for (index, (operation, checked)) in bulk_req.operations.into_iter().zip(checks).enumerate() {
+        let op = operation_name(&operation);
+        if failed {
+            results.push(item_error(index, op, operation_id(&operation), 424, "Not executed: an earlier operation failed"));
+            continue;
+        }
+        let target = operation_id(&operation);
+        match apply_operation(app_state, operation, checked, username) {
+            Ok((id, status)) => results.push(BulkItemResult {
+                index,
+                op: op.to_string(),
+                id: Some(id),
+                success: true,
+                status,
+                error: None,
+            }),
+            Err((status, message)) => {
+                results.push(item_error(index, op, target, status, message));
+                failed = snapshot.is_some();
+            }
+        }
+    }
+
+    let committed = !failed;
+    // Cache entries dropped on the way stay dropped; they are refilled from
+    // the restored records
+    if let Some((data, trash, next_id)) = snapshot.filter(|_| failed) {
+        app_state.data = data;
+        app_state.trash = trash;
+        app_state.next_id = next_id;
+        for result in results.iter_mut().filter(|result| result.success) {
+            result.success = false;
+            result.status = 409;
+            result.error = Some("Rolled back: another operation failed".to_string());
+        }
+    }
+
+    let successful_operations = results.iter().filter(|result| result.success).count();
+    BulkResponse {
+        results,
+        total_operations,
+        successful_operations,
+        mode: bulk_req.mode,
+        committed,
+    }
+}
+
+// Applies one operation, returning the affected id and its HTTP-style status.
+// `checked` is the outcome of its module check.
+fn apply_operation(
+    app_state: &mut AppStateInner,
+    operation: BulkOperation,
+    checked: Result<(), (u16, String)>,
+    username: &str,
+) -> Result<(u32, u16), (u16, String)> {
+    match operation {
+        BulkOperation::Create { data } => {
+            checked?;
+            let id = app_state.allocate_id();
+            app_state.data.insert(id, create_data_entry_from_request(data, username.to_string()));
+            Ok((id, 201))
+        }
+        BulkOperation::Update { id, data } => {
+            check_owner(app_state, id, username)?;
+            checked?;
+            if let Some(previous) = app_state.data.insert(id, create_data_entry_from_request(data, username.to_string())) {
+                app_state.results.invalidate(&previous.bytecode);
+            }
+            app_state.wasm_cache.remove(&id);
+            Ok((id, 200))
+        }
+        BulkOperation::Delete { id } => {
+            check_owner(app_state, id, username)?;
+            move_to_trash(app_state, id, username);
+            Ok((id, 204))
+        }
+    }
+}
+
+fn check_owner(app_state: &AppStateInner, id: u32, username: &str) -> Result<(), (u16, String)> {
+    match app_state.data.get(&id) {
+        Some(entry) if entry.owner == username => Ok(()),
+        Some(_) => Err((403, "Access denied: not the owner".to_string())),
+        None => Err((404, "Record not found".to_string())),
+    }
+}
+
+fn check_module(data: &mut CreateDataRequest, allowed: &FeatureSet) -> Result<(), (u16, String)> {
+    resolve_source(data).map_err(|e| (e.status() as u16, e.to_string()))?;
+    validate_upload(&data.bytecode, data.host_api.as_deref(), data.wasi, allowed).map_err(|e| (e.status() as u16, e.to_string()))?;
+    check_pure_functions(&data.bytecode, &data.pure_functions).map_err(|e| (e.status() as u16, e.to_string()))
+}
+
+fn operation_name(operation: &BulkOperation) -> &'static str {
+    match operation {
+        BulkOperation::Create { .. } => "create",
+        BulkOperation::Update { .. } => "update",
+        BulkOperation::Delete { .. } => "delete",
+    }
+}
+
+fn operation_id(operation: &BulkOperation) -> Option<u32> {
+    match operation {
+        BulkOperation::Create { .. } => None,
+        BulkOperation::Update { id, .. } | BulkOperation::Delete { id } => Some(*id),
+    }
+}
+
+fn item_error(index: usize, op: &str, id: Option<u32>, status: u16, message: impl Into<String>) -> BulkItemResult {
+    BulkItemResult {
+        index,
+        op: op.to_string(),
+        id,
+        success: false,
+        status,
+        error: Some(message.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DataEntry;
+    use crate::state::new_state;
+
+    fn create_op(func: &str) -> BulkOperation {
+        BulkOperation::Create {
+            data: CreateDataRequest {
+                func_names: Some(vec![func.to_string()]),
+                bytecode: vec![1, 2, 3],
+                host_api: None,
+                wasi: false,
+                pure_functions: vec![],
+                format: None,
+                source: None,
+            },
+        }
+    }
+
+    fn seed(app_state: &mut AppStateInner, owner: &str) -> u32 {
+        let id = app_state.allocate_id();
+        app_state.data.insert(id, DataEntry {
+            func_names: vec!["add".to_string()],
+            bytecode: vec![0],
+            owner: owner.to_string(),
+            host_api: None,
+            wasi: false,
+            pure_functions: vec![],
+        });
+        id
+    }
+
+    fn run_bulk(app_state: &mut AppStateInner, mut bulk_req: BulkRequest, username: &str) -> BulkResponse {
+        let checks = check_modules(&mut bulk_req.operations, &allowed_features(app_state, username));
+        apply_bulk(app_state, bulk_req, checks, username)
+    }
+
+    #[test]
+    fn test_bulk_best_effort_keeps_successes() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        let foreign = seed(&mut app_state, "user2");
+
+        let response = run_bulk(&mut app_state, BulkRequest {
+            mode: BulkMode::BestEffort,
+            operations: vec![create_op("add"), BulkOperation::Delete { id: foreign }, create_op("mul")],
+        }, "user1");
+
+        assert!(response.committed);
+        assert_eq!(response.total_operations, 3);
+        assert_eq!(response.successful_operations, 2);
+        assert_eq!(response.results[1].status, 403);
+        assert_eq!(app_state.data.len(), 3);
+    }
+
+    #[test]
+    fn test_bulk_all_or_nothing_rolls_back() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        let own = seed(&mut app_state, "user1");
+
+        let response = run_bulk(&mut app_state, BulkRequest {
+            mode: BulkMode::AllOrNothing,
+            operations: vec![
+                create_op("add"),
+                BulkOperation::Delete { id: own },
+                BulkOperation::Delete { id: 999 },
+                create_op("mul"),
+            ],
+        }, "user1");
+
+        assert!(!response.committed);
+        assert_eq!(response.successful_operations, 0);
+        assert_eq!(response.results[0].status, 409);
+        assert_eq!(response.results[2].status, 404);
+        assert_eq!(response.results[3].status, 424);
+
+        // State is exactly as before the request
+        assert_eq!(app_state.data.len(), 1);
+        assert!(app_state.data.contains_key(&own));
+        assert!(app_state.trash.is_empty());
+        assert_eq!(app_state.next_id, own + 1);
+    }
+}
@@ -1,23 +1,73 @@
-use crate::auth::{create_data_entry_from_request, get_authenticated_user};
+use crate::auth::{authenticated_user, create_data_entry_from_request};
 use crate::models::CreateDataRequest;
+use crate::memo::check_pure_functions;
 use crate::state::AppState;
+use crate::wasm::features::allowed_features;
+use crate::wasm::text::{resolve_source, FORMAT_WAT, WAT_CONTENT_TYPES};
+use crate::wasm::validate_upload;
+use serde::Deserialize;
 use tide::Request;
 use tracing::info;
 use std::time::Instant;
 
 pub async fn create_data(mut req: Request<AppState>) -> tide::Result {
     let start_time = Instant::now();
-    let username = get_authenticated_user(&req)?;
+    let username = authenticated_user(&req)?.username;
     info!(user = %username, "Data creation started");
-    let req_data: CreateDataRequest = req.body_json().await?;
+    let req_data = read_upload(&mut req).await?;
     info!(user = %username, func_names = ?req_data.func_names, bytecode_length = req_data.bytecode.len(), "Request data parsed successfully");
+    let allowed = allowed_features(&req.state().read().unwrap(), &username);
+    validate_upload(&req_data.bytecode, req_data.host_api.as_deref(), req_data.wasi, &allowed)?;
+    check_pure_functions(&req_data.bytecode, &req_data.pure_functions)?;
     let entry = create_data_entry_from_request(req_data, username.clone());
     let state = req.state();
     let mut app_state = state.lock().unwrap();
-    let new_id = app_state.data.len() as u32 + 1;
+    let new_id = app_state.allocate_id();
     info!(user = %username, new_id = %new_id, total_records = app_state.data.len(), "Generated new record ID");
     app_state.data.insert(new_id, entry);
     let execution_time = start_time.elapsed();
     info!(user = %username, record_id = %new_id, execution_time_ms = execution_time.as_millis(), "Data creation completed successfully");
     Ok(tide::Body::from_json(&serde_json::json!({ "id": new_id }))?.into())
+}
+
+// Query string of a module sent as a text/wat body
+#[derive(Deserialize)]
+struct TextUploadQuery {
+    #[serde(default)]
+    func_names: Option<String>, // Comma-separated
+    #[serde(default)]
+    host_api: Option<String>,
+    #[serde(default)]
+    wasi: bool,
+    #[serde(default)]
+    pure_functions: Option<String>, // Comma-separated
+}
+
+// Reads the module of POST /data and PUT /data/:id: JSON as before, or module
+// text sent with a text/wat content type. Text is compiled to binary here.
+pub async fn read_upload(req: &mut Request<AppState>) -> tide::Result<CreateDataRequest> {
+    let is_text = req
+        .content_type()
+        .is_some_and(|mime| WAT_CONTENT_TYPES.contains(&mime.essence()));
+    let mut upload = if is_text {
+        let query: TextUploadQuery = req
+            .query()
+            .map_err(|_| tide::Error::from_str(400, "Invalid query: expected ?func_names=a,b&host_api=...&wasi=true&pure_functions=a"))?;
+        let list = |names: Option<String>| -> Option<Vec<String>> {
+            names.map(|names| names.split(',').map(str::trim).filter(|name| !name.is_empty()).map(String::from).collect())
+        };
+        CreateDataRequest {
+            func_names: list(query.func_names),
+            bytecode: Vec::new(),
+            host_api: query.host_api,
+            wasi: query.wasi,
+            pure_functions: list(query.pure_functions).unwrap_or_default(),
+            format: Some(FORMAT_WAT.to_string()),
+            source: Some(req.body_string().await?),
+        }
+    } else {
+        req.body_json().await?
+    };
+    resolve_source(&mut upload)?;
+    Ok(upload)
 }
\ No newline at end of file
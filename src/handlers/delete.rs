@@ -1,4 +1,6 @@
-use crate::auth::get_authenticated_user;
+use crate::auth::{authenticated_user, ROLE_ADMIN};
+use crate::handlers::trash::move_to_trash;
+use crate::models::{AuthenticatedUser, DeleteQuery};
 use crate::state::AppState;
 use tide::Request;
 use tracing::info;
@@ -6,22 +8,33 @@ use std::time::Instant;
 
 pub async fn delete_data(req: Request<AppState>) -> tide::Result {
     let start_time = Instant::now();
-    
+
     // Check if user is authenticated
-    let username = get_authenticated_user(&req)?;
+    let user = authenticated_user(&req)?;
+    let username = user.username.as_str();
 
     // Extract id from URL (e.g., /data/:id)
     let id: u32 = match req.param("id")?.parse() {
         Ok(val) => val,
         Err(_) => return Err(tide::Error::from_str(400, "Invalid id")),
     };
-    
+
+    // Soft delete unless ?hard=true is given
+    let query: DeleteQuery = req
+        .query()
+        .map_err(|_| tide::Error::from_str(400, "Invalid query: expected ?hard=true|false"))?;
+
     info!(
         user = %username,
         record_id = %id,
+        hard = query.hard,
         "Data deletion started"
     );
 
+    if query.hard {
+        return hard_delete(&req, &user, id, start_time);
+    }
+
     // Get global state
     let state = req.state();
     let mut app_state = state.lock().unwrap();
@@ -34,7 +47,7 @@ pub async fn delete_data(req: Request<AppState>) -> tide::Result {
             current_owner = %entry.owner,
             "Record found, checking ownership"
         );
-        
+
         // Check if user is the owner
         if entry.owner != username {
             let execution_time = start_time.elapsed();
@@ -48,15 +61,16 @@ pub async fn delete_data(req: Request<AppState>) -> tide::Result {
             return Err(tide::Error::from_str(403, "Access denied: not the owner"));
         }
 
-        // Remove the record
-        app_state.data.remove(&id);
-        
+        // Move the record to the trash
+        let purge_at = move_to_trash(&mut app_state, id, username).unwrap();
+
         let execution_time = start_time.elapsed();
         info!(
             user = %username,
             record_id = %id,
+            purge_at = %purge_at,
             execution_time_ms = execution_time.as_millis(),
-            "Data deletion completed successfully"
+            "Data moved to trash successfully"
         );
         Ok(tide::Response::new(204))
     } else {
@@ -69,4 +83,42 @@ pub async fn delete_data(req: Request<AppState>) -> tide::Result {
         );
         Ok(tide::Response::new(404))
     }
-}
\ No newline at end of file
+}
+
+// Permanently removes a record, whether it is live or already in the trash
+fn hard_delete(req: &Request<AppState>, user: &AuthenticatedUser, id: u32, start_time: Instant) -> tide::Result {
+    let username = user.username.as_str();
+    if !user.has_role(ROLE_ADMIN) {
+        let execution_time = start_time.elapsed();
+        info!(
+            user = %username,
+            record_id = %id,
+            execution_time_ms = execution_time.as_millis(),
+            "Hard delete failed - admin privileges required"
+        );
+        return Err(tide::Error::from_str(403, "Access denied: hard delete requires admin privileges"));
+    }
+
+    let mut app_state = req.state().lock().unwrap();
+    let removed = app_state.data.remove(&id).is_some() | app_state.trash.remove(&id).is_some();
+    app_state.wasm_cache.remove(&id);
+
+    let execution_time = start_time.elapsed();
+    if removed {
+        info!(
+            user = %username,
+            record_id = %id,
+            execution_time_ms = execution_time.as_millis(),
+            "Hard delete completed successfully"
+        );
+        Ok(tide::Response::new(204))
+    } else {
+        info!(
+            user = %username,
+            record_id = %id,
+            execution_time_ms = execution_time.as_millis(),
+            "Hard delete failed - record not found"
+        );
+        Ok(tide::Response::new(404))
+    }
+}
@@ -1,33 +1,117 @@
-use crate::auth::get_authenticated_user;
-use crate::state::AppState;
+use crate::auth::authenticated_user;
+use crate::instances::Session;
+use crate::memo::{CallKey, ResultCache};
+use crate::state::{AppState, AppStateInner, Metrics};
+use crate::wasm::{self, abi::ByteCall};
+use crate::wasm::deterministic;
+use crate::wasm::features::{self, allowed_features, Feature, FeatureSet};
+use crate::wasm::host::{self, HostState};
+use crate::wasm::wasi::WasiCtx;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
 use serde::{Deserialize, Serialize};
 use tide::{Request, Response, StatusCode};
-use wasmi::{Engine, Instance, Module, Store, TypedFunc};
-use tracing::info;
+use wasmi::core::ValType;
+use wasmi::{Func, Instance, Module, Store, Val};
+use tracing::{debug, info};
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 use std::time::Instant;
 
 #[derive(Deserialize)]
-struct ExecRequest {
+pub struct ExecRequest {
     #[serde(rename = "fn")]
-    func: String,
+    pub func: String,
+    #[serde(default)]
     arg: [i32; 2],
+    #[serde(default)]
+    input: Option<String>, // Readable through the host API's input_len/input_read
+    #[serde(default)]
+    payload: Option<serde_json::Value>, // Passed as JSON text through memory
+    #[serde(default)]
+    payload_base64: Option<String>, // Passed as raw bytes through memory
+    #[serde(default)]
+    wasi: Option<WasiRequest>, // Only for records with wasi enabled
+    #[serde(default)]
+    instance: Option<String>, // Instance session to call instead of a fresh instance
+    #[serde(default)]
+    deterministic: bool, // Reproducible run; see wasm/deterministic.rs
+}
+
+// What a WASI module sees; the filesystem starts with `files` (path -> contents)
+#[derive(Serialize, Deserialize, Default)]
+pub struct WasiRequest {
+    #[serde(default)]
+    stdin: Option<String>,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    files: BTreeMap<String, String>,
 }
 
 #[derive(Serialize)]
-struct ExecResponse {
+pub struct ExecResponse {
     success: bool,
     result: Option<i32>,
     error: Option<String>,
     function: String,
     operands: [i32; 2],
     owner: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<serde_json::Value>, // Payload calls whose output is JSON
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output_base64: Option<String>, // Payload calls
+    #[serde(flatten)]
+    wasi: Option<WasiReport>, // Records with wasi enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deterministic: Option<DeterminismReport>, // Deterministic runs
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    cached: bool, // Answered from an earlier call to a pure function
+}
+
+#[derive(Serialize)]
+pub struct WasiReport {
+    stdout: String,
+    stderr: String,
+    exit_code: i32,
+}
+
+// Identifies a deterministic run: equal digests and hashes mean the same
+// module was called the same way, and so gave the same response
+#[derive(Serialize)]
+pub struct DeterminismReport {
+    module_digest: String, // SHA-256 of the record's bytecode
+    input_hash: String,    // SHA-256 of the call's inputs and settings
+    fuel_used: u64,
+}
+
+// A call whose record has been checked, ready to run without the state lock
+pub struct Execution {
+    pub record_id: u32,
+    pub username: String,
+    bytecode: Arc<[u8]>,
+    host_api: Option<String>,
+    with_wasi: bool,
+    features: FeatureSet, // The owner's feature policy
+    builtin: bool,
+    payload: Option<Vec<u8>>,
+    session: Option<Arc<Session>>,
+    memo: Option<Arc<ResultCache>>, // Calls whose result may be memoized
+    request: ExecRequest,
+}
+
+impl Execution {
+    pub fn function(&self) -> &str {
+        &self.request.func
+    }
 }
 
 pub async fn execute_fn(mut req: Request<AppState>) -> tide::Result {
-    let start_time = Instant::now();
-    
     // Verifica autenticação JWT
-    let username = get_authenticated_user(&req)?;
+    let username = authenticated_user(&req)?.username;
     
     // Log execution start
     info!(
@@ -38,52 +122,23 @@ pub async fn execute_fn(mut req: Request<AppState>) -> tide::Result {
     // Lê e valida o JSON do body
     info!("DEBUG: Reading JSON body...");
     let exec_req: ExecRequest = req.body_json().await.map_err(|_| {
-        update_failed_metrics(&req.state());
-        tide::Error::from_str(400, "Invalid JSON: expected { fn: string, arg: [i32; 2] }")
+        update_failed_metrics(req.state());
+        tide::Error::from_str(400, "Invalid JSON: expected { fn: string, arg: [i32; 2] } or { fn: string, payload: any }")
     })?;
     info!("DEBUG: JSON body read successfully: fn={}, arg={:?}", exec_req.func, exec_req.arg);
     
     // Update metrics
-    info!("DEBUG: Updating metrics...");
-    {
-        let mut state = req.state().lock().unwrap();
-        state.metrics.total_executions.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        state.metrics.function_counts
-            .entry(exec_req.func.clone())
-            .or_insert_with(|| std::sync::atomic::AtomicU64::new(0))
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-    }
-    info!("DEBUG: Metrics updated successfully");
+    count_execution(&req.state().metrics, &exec_req.func);
     
     // Simple rate limiting check (using the fields to avoid warnings)
     info!("DEBUG: Checking rate limiting...");
     {
-        let _rate_limiter = &req.state().lock().unwrap().rate_limiter;
+        let _rate_limiter = &req.state().read().unwrap().rate_limiter;
         // This is a placeholder to use the rate_limiter fields and avoid warnings
         // In a real implementation, you would implement proper rate limiting here
     }
     info!("DEBUG: Rate limiting check completed");
 
-    // Valida se a função é uma das permitidas
-    info!("DEBUG: Validating function name...");
-    let allowed_functions = ["add", "mul", "sub", "div", "rem", "abs", "max", "min", "pow"];
-    if !allowed_functions.contains(&exec_req.func.as_str()) {
-        update_failed_metrics(&req.state());
-        return Err(tide::Error::from_str(
-            400, 
-            format!("Function '{}' not allowed. Available functions: {:?}", exec_req.func, allowed_functions)
-        ));
-    }
-    info!("DEBUG: Function name validated: {}", exec_req.func);
-
-    // Validate arguments
-    info!("DEBUG: Validating arguments...");
-    validate_arguments(&exec_req.arg, &exec_req.func).map_err(|e| {
-        update_failed_metrics(&req.state());
-        e
-    })?;
-    info!("DEBUG: Arguments validated successfully");
-
     // Busca o registro no estado global
     info!("DEBUG: Getting ID parameter...");
     let id: u32 = match req.param("id") {
@@ -94,156 +149,449 @@ pub async fn execute_fn(mut req: Request<AppState>) -> tide::Result {
     };
     info!("DEBUG: ID parameter: {}", id);
 
-    info!("DEBUG: Getting state and finding record...");
-    let state = req.state();
-    info!("DEBUG: State obtained, attempting to lock...");
-    let mut map = state.lock().unwrap();
-    info!("DEBUG: State locked successfully, searching for record ID: {}", id);
-    info!("DEBUG: Available records in state: {:?}", map.data.keys().collect::<Vec<_>>());
-    let entry = match map.data.get(&id) {
-        Some(e) => {
-            info!("DEBUG: Record found successfully");
-            e
-        },
-        None => {
-            info!("DEBUG: Record not found for ID: {}", id);
-            return Err(tide::Error::from_str(404, "Record not found"))
-        },
-    };
-    info!("DEBUG: Record found, owner: {}", entry.owner);
+    // Only the checks need the state; the module runs on the blocking pool
+    // without any lock, so other requests proceed in the meantime
+    let execution = prepare(&req.state().read().unwrap(), id, &username, exec_req);
+    let execution = execution.inspect_err(|_| update_failed_metrics(req.state()))?;
+    let response = async_std::task::spawn_blocking(move || run(execution, None))
+        .await
+        .inspect_err(|_| update_failed_metrics(req.state()))?;
+    req.state().metrics.successful_executions.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    Ok(Response::builder(StatusCode::Ok).body(tide::Body::from_json(&response)?).build())
+}
+
+// Counts a requested execution in the metrics
+pub fn count_execution(metrics: &Metrics, func: &str) {
+    metrics.total_executions.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    *metrics.function_counts.lock().unwrap().entry(func.to_string()).or_insert(0) += 1;
+}
+
+// Checks a request against the record it targets: arguments, ownership and
+// the allowed functions. The returned execution needs no further state.
+pub fn prepare(map: &AppStateInner, id: u32, username: &str, exec_req: ExecRequest) -> tide::Result<Execution> {
+    let payload = payload_bytes(&exec_req)?;
+
+    // The math functions are always allowed and have their arguments checked;
+    // any other function must be declared in the record's func_names
+    let builtin = ALLOWED_FUNCTIONS.contains(&exec_req.func.as_str());
+    if builtin && payload.is_none() {
+        validate_arguments(&exec_req.arg, &exec_req.func)?;
+    }
+
+    let entry = map.data.get(&id).ok_or_else(|| tide::Error::from_str(404, "Record not found"))?;
 
     // Verifica se o usuário é o proprietário do registro
-    info!("DEBUG: Checking ownership...");
     if entry.owner != username {
         return Err(tide::Error::from_str(403, "Access denied: you can only execute your own WASM modules"));
     }
-    info!("DEBUG: Ownership verified");
+
+    // Valida se a função é uma das permitidas
+    if !builtin && !entry.func_names.contains(&exec_req.func) {
+        let mut available: Vec<&str> = ALLOWED_FUNCTIONS.to_vec();
+        available.extend(entry.func_names.iter().map(String::as_str).filter(|name| !ALLOWED_FUNCTIONS.contains(name)));
+        let message = format!("Function '{}' not allowed. Available functions: {:?}", exec_req.func, available);
+        return Err(tide::Error::from_str(400, message));
+    }
+    let host_api = entry.host_api.clone();
+    let with_wasi = entry.wasi;
+    if exec_req.wasi.is_some() && !with_wasi {
+        return Err(tide::Error::from_str(400, "WASI settings need a record with wasi enabled"));
+    }
+    if exec_req.deterministic && exec_req.instance.is_some() {
+        return Err(tide::Error::from_str(400, "Deterministic runs need a fresh instance; omit instance"));
+    }
+    // Only plain calls to pure functions depend on nothing but the arguments
+    let memoizable = entry.pure_functions.contains(&exec_req.func)
+        && !with_wasi
+        && payload.is_none()
+        && exec_req.input.is_none()
+        && exec_req.instance.is_none()
+        && !exec_req.deterministic;
+    let mut features = allowed_features(map, &entry.owner);
+    if exec_req.deterministic {
+        features.remove(&Feature::Simd);
+    }
 
     // Check WASM cache first
-    info!("DEBUG: Checking WASM cache...");
-    info!("DEBUG: Cache keys: {:?}", map.wasm_cache.keys().collect::<Vec<_>>());
-    let wasm_bytes = if let Some(cached_bytes) = map.wasm_cache.get(&id) {
-        info!("DEBUG: WASM found in cache, length: {}", cached_bytes.len());
-        cached_bytes.clone()
+    let wasm_bytes = if let Some(cached_bytes) = map.wasm_cache.get(id) {
+        debug!(record_id = id, bytes = cached_bytes.len(), "Module bytes taken from the cache");
+        cached_bytes
     } else {
         // Cache miss - store the bytes for future use
-        info!("DEBUG: WASM not in cache, storing...");
-        info!("DEBUG: Entry bytecode length: {}", entry.bytecode.len());
-        let bytes = entry.bytecode.clone();
-        info!("DEBUG: Inserting into cache...");
+        debug!(record_id = id, "Module bytes not cached yet; caching them");
+        let bytes: Arc<[u8]> = Arc::from(entry.bytecode.as_slice());
         map.wasm_cache.insert(id, bytes.clone());
-        info!("DEBUG: Inserted into cache successfully");
         bytes
     };
-    info!("DEBUG: WASM bytes length: {}", wasm_bytes.len());
 
     // Verifica se o bytecode está vazio
     if wasm_bytes.is_empty() {
         return Err(tide::Error::from_str(400, "WASM bytecode is empty"));
     }
-    info!("DEBUG: WASM bytes are not empty");
+
+    // An instance session runs the module it was created from
+    let session = match exec_req.instance.as_deref() {
+        Some(instance_id) => {
+            let session = map
+                .instances
+                .get(instance_id)
+                .filter(|session| session.owner == username)
+                .ok_or_else(|| tide::Error::from_str(404, "Instance not found"))?;
+            if session.record_id != id {
+                return Err(tide::Error::from_str(400, format!("Instance belongs to record {}", session.record_id)));
+            }
+            if !session.runs(&wasm_bytes) {
+                return Err(tide::Error::from_str(409, "The record has changed since the instance was created; create a new instance"));
+            }
+            if exec_req.wasi.is_some() {
+                return Err(tide::Error::from_str(400, "WASI settings are fixed when the instance is created"));
+            }
+            Some(session.clone())
+        }
+        None => None,
+    };
+
+    Ok(Execution {
+        record_id: id,
+        username: username.to_string(),
+        bytecode: wasm_bytes,
+        host_api,
+        with_wasi,
+        features,
+        builtin,
+        payload,
+        session,
+        memo: memoizable.then(|| map.results.clone()),
+        request: exec_req,
+    })
+}
+
+// Instantiates the module and calls the function, or calls the instance
+// session the request targets. With `cancel`, the run is metered and stops at
+// the next fuel slice once the flag is set.
+pub fn run(execution: Execution, cancel: Option<&AtomicBool>) -> tide::Result<ExecResponse> {
+    let start_time = Instant::now();
+    let Execution { record_id: id, username, bytecode, host_api, with_wasi, features, builtin, payload, session, memo, request: mut exec_req } = execution;
+
+    if let Some(session) = session {
+        // Session engines are always metered, so every call on them is too
+        let not_cancelled = AtomicBool::new(false);
+        let mut live = session.live.lock().unwrap();
+        session.touch();
+        let host_state = live.store.data_mut();
+        host_state.input = exec_req.input.take().unwrap_or_default().into_bytes();
+        host_state.wasi.stdout.clear();
+        host_state.wasi.stderr.clear();
+        let response = call_instance(&mut live, username, builtin, payload, exec_req, cancel.or(Some(&not_cancelled)), start_time);
+        session.touch();
+        return response;
+    }
+
+    let memo = memo.map(|results| (results, CallKey::new(&bytecode, &features, &exec_req.func, exec_req.arg)));
+    if let Some(result) = memo.as_ref().and_then(|(results, key)| results.get(key)) {
+        info!(user = %username, function = %exec_req.func, result = ?result, "WASM execution answered from the result cache");
+        return Ok(ExecResponse {
+            success: true,
+            result,
+            error: None,
+            function: exec_req.func,
+            operands: exec_req.arg,
+            owner: username,
+            output: None,
+            output_base64: None,
+            wasi: None,
+            deterministic: None,
+            cached: true,
+        });
+    }
+
+    // Deterministic runs are always metered, with a fixed budget
+    let deterministic = exec_req.deterministic;
+    let (fuel, input_hash) = if deterministic {
+        let fuel = deterministic::get_deterministic_fuel();
+        (Some(fuel), Some(input_hash(&exec_req, payload.as_deref(), host_api.as_deref(), with_wasi, fuel)?))
+    } else {
+        (cancel.is_some().then_some(u64::MAX), None)
+    };
+    let not_cancelled = AtomicBool::new(false);
+    let cancel = if deterministic { cancel.or(Some(&not_cancelled)) } else { cancel };
+
+    // WASI reactors set up their runtime in _initialize; commands do it in _start
+    let initialize = exec_req.func != "_start";
+    let input = exec_req.input.take().unwrap_or_default().into_bytes();
+    let wasi = exec_req.wasi.take();
+    let mut live = instantiate_record(id, &username, &bytecode, host_api.as_deref(), with_wasi, &features, wasi, input, initialize, fuel, deterministic)?;
+    let mut response = call_instance(&mut live, username, builtin, payload, exec_req, cancel, start_time)?;
+    if let Some((results, key)) = memo {
+        results.insert(key, response.result);
+    }
+    if let (Some(fuel), Some(input_hash)) = (fuel, input_hash) {
+        response.deterministic = Some(DeterminismReport {
+            module_digest: deterministic::digest(&bytecode),
+            input_hash,
+            fuel_used: fuel - live.store.get_fuel().unwrap_or(0),
+        });
+    }
+    Ok(response)
+}
+
+// Hash of everything besides the module that a deterministic run's result
+// depends on. JSON objects serialize with sorted keys, so the text is stable.
+fn input_hash(exec_req: &ExecRequest, payload: Option<&[u8]>, host_api: Option<&str>, with_wasi: bool, fuel: u64) -> tide::Result<String> {
+    let inputs = serde_json::json!({
+        "function": exec_req.func,
+        "arg": exec_req.arg,
+        "input": exec_req.input,
+        "payload_base64": payload.map(|payload| STANDARD.encode(payload)),
+        "wasi": exec_req.wasi,
+        "host_api": host_api,
+        "wasi_enabled": with_wasi,
+        "fuel": fuel,
+    });
+    Ok(deterministic::digest(&serde_json::to_vec(&inputs)?))
+}
+
+// A record's module, instantiated with its host functions. Instance sessions
+// keep one across calls.
+pub struct LiveInstance {
+    store: Store<HostState>,
+    instance: Instance,
+    with_wasi: bool,
+}
+
+// Compiles and instantiates a record's module under the owner's feature
+// policy; WASI reactors are initialized when `initialize` is set. With `fuel`
+// the instance is metered, runs on that budget and can be cancelled on any
+// call. A deterministic instance runs the NaN-canonicalized module with
+// deterministic host functions.
+#[allow(clippy::too_many_arguments)]
+pub fn instantiate_record(
+    id: u32,
+    username: &str,
+    bytecode: &[u8],
+    host_api: Option<&str>,
+    with_wasi: bool,
+    allowed: &FeatureSet,
+    wasi: Option<WasiRequest>,
+    input: Vec<u8>,
+    initialize: bool,
+    fuel: Option<u64>,
+    deterministic: bool,
+) -> tide::Result<LiveInstance> {
+    let canonical;
+    let bytecode = if deterministic {
+        canonical = deterministic::canonicalize_nans(bytecode)
+            .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, format!("Invalid WASM: {e}")))?;
+        &canonical[..]
+    } else {
+        bytecode
+    };
 
     // Carrega e instancia o wasm
-    info!("DEBUG: Creating WASM engine...");
-    let engine = Engine::default();
-    info!("DEBUG: Creating WASM module...");
-    let module = Module::new(&engine, wasm_bytes)
-        .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, format!("Invalid WASM: {e}")))?;
-    info!("DEBUG: WASM module created successfully");
-    
-    info!("DEBUG: Creating WASM store...");
-    let mut store = Store::new(&engine, ());
-    info!("DEBUG: Creating WASM instance...");
-    let instance = Instance::new(&mut store, &module, &[]).map_err(|e| {
-        tide::Error::from_str(
-            StatusCode::InternalServerError,
-            format!("WASM instantiation error: {e}"),
-        )
+    let engine = wasm::engine(fuel.is_some(), allowed);
+    let module = Module::new(&engine, bytecode).map_err(|e| {
+        features::violation(bytecode, allowed)
+            .unwrap_or_else(|| tide::Error::from_str(StatusCode::BadRequest, format!("Invalid WASM: {e}")))
     })?;
-    info!("DEBUG: WASM instance created successfully");
+    if deterministic {
+        deterministic::check_imports(&module)?;
+    }
+
+    let wasi = match wasi {
+        Some(settings) => WasiCtx::new(
+            settings.args,
+            &settings.env,
+            settings.stdin.unwrap_or_default().into_bytes(),
+            &settings.files,
+        )
+        .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, e))?,
+        None => WasiCtx::default(),
+    };
+    let mut wasi = wasi;
+    if deterministic {
+        wasi.make_deterministic();
+    }
+    let mut store = Store::new(&engine, HostState {
+        record_id: id,
+        user: username.to_string(),
+        input,
+        wasi,
+    });
+    let linker = host::linker(&engine, host_api, with_wasi)
+        .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, format!("Host API error: {e}")))?;
+    let instance = wasm::instantiate(&linker, &mut store, &module, fuel)
+        .map_err(|e| {
+            tide::Error::from_str(
+                StatusCode::InternalServerError,
+                format!("WASM instantiation error: {e}"),
+            )
+        })?;
+
+    if with_wasi && initialize {
+        if let Some(initialize) = instance.get_func(&store, "_initialize") {
+            let not_cancelled = AtomicBool::new(false);
+            wasm::call(&mut store, initialize, &[], &mut [], fuel.is_some().then_some(&not_cancelled)).map_err(|e| {
+                tide::Error::from_str(StatusCode::InternalServerError, format!("WASI initialization error: {e}"))
+            })?;
+        }
+    }
+    Ok(LiveInstance { store, instance, with_wasi })
+}
+
+// Calls the requested function on an instance
+fn call_instance(
+    live: &mut LiveInstance,
+    username: String,
+    builtin: bool,
+    payload: Option<Vec<u8>>,
+    exec_req: ExecRequest,
+    cancel: Option<&AtomicBool>,
+    start_time: Instant,
+) -> tide::Result<ExecResponse> {
+    let LiveInstance { store, instance, with_wasi } = live;
+    let with_wasi = *with_wasi;
 
     // Busca a função exportada
-    info!("DEBUG: Getting exported function: {}", exec_req.func);
     let func = instance
-        .get_func(&mut store, &exec_req.func)
+        .get_func(&mut *store, &exec_req.func)
         .ok_or_else(|| {
             tide::Error::from_str(
                 StatusCode::BadRequest,
                 format!("Function '{}' not found in WASM module", exec_req.func),
             )
         })?;
-    info!("DEBUG: Function found successfully");
 
     // Executa a função com detecção dinâmica de assinatura
-    info!("DEBUG: Executing function with dynamic signature detection...");
-    let result = match exec_req.func.as_str() {
-        "abs" => {
-            info!("DEBUG: Using unary function signature for abs");
-            // Função unária: (i32) -> i32
-            let typed: TypedFunc<(i32,), i32> = func.typed(&store).map_err(|e| {
-                tide::Error::from_str(StatusCode::BadRequest, format!("Function signature error for abs: {e}"))
-            })?;
-            info!("DEBUG: Calling abs with argument: {}", exec_req.arg[0]);
-            typed.call(&mut store, (exec_req.arg[0],))
-        }
-        _ => {
-            info!("DEBUG: Using binary function signature for {}", exec_req.func);
-            // Funções binárias: (i32, i32) -> i32
-            let typed: TypedFunc<(i32, i32), i32> = func.typed(&store).map_err(|e| {
-                tide::Error::from_str(StatusCode::BadRequest, format!("Function signature error: {e}"))
-            })?;
-            info!("DEBUG: Calling {} with arguments: {}, {}", exec_req.func, exec_req.arg[0], exec_req.arg[1]);
-            typed.call(&mut store, (exec_req.arg[0], exec_req.arg[1]))
+    if let Some(payload) = payload {
+        let call = ByteCall::new(&mut *store, instance, func)
+            .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, format!("Payload ABI error: {e}")))?;
+        let (output, exit_code) = split_exit(call.call(store, &payload, cancel)).map_err(|e| {
+            tide::Error::from_str(StatusCode::InternalServerError, format!("WASM execution error: {e}"))
+        })?;
+        info!(
+            user = %username,
+            function = %exec_req.func,
+            input_bytes = payload.len(),
+            output_bytes = output.as_ref().map(Vec::len),
+            execution_time_ms = start_time.elapsed().as_millis(),
+            "WASM execution completed successfully"
+        );
+        return Ok(ExecResponse {
+            success: true,
+            result: None,
+            error: None,
+            function: exec_req.func,
+            operands: exec_req.arg,
+            owner: username,
+            output: output.as_deref().and_then(|output| serde_json::from_slice(output).ok()),
+            output_base64: output.map(|output| STANDARD.encode(output)),
+            wasi: with_wasi.then(|| wasi_report(store, exit_code)),
+            deterministic: None,
+            cached: false,
+        });
+    }
+
+    if builtin {
+        // Funções binárias (i32, i32) -> i32, exceto abs: (i32) -> i32
+        let params = if exec_req.func == "abs" { 1 } else { 2 };
+        let ty = func.ty(&*store);
+        if ty.params() != vec![ValType::I32; params].as_slice() || ty.results() != [ValType::I32] {
+            return Err(tide::Error::from_str(
+                StatusCode::BadRequest,
+                format!("Function signature error for {}: expected {} i32 parameters and an i32 result", exec_req.func, params),
+            ));
         }
-    }.map_err(|e| {
+    }
+    debug!(function = %exec_req.func, args = ?exec_req.arg, "Calling function");
+    let (result, exit_code) = split_exit(call_declared(store, func, exec_req.arg, cancel)).map_err(|e| {
         tide::Error::from_str(StatusCode::InternalServerError, format!("WASM execution error: {e}"))
     })?;
-    info!("DEBUG: Function executed successfully, result: {}", result);
+    let result = result.flatten();
+    debug!(function = %exec_req.func, result = ?result, "Function returned");
 
     let execution_time = start_time.elapsed();
     
-    // Update successful execution metrics (temporarily disabled for debugging)
-    info!("DEBUG: Skipping metrics update for now...");
-    
     // Log successful execution
     info!(
         user = %username,
         function = %exec_req.func,
-        result = %result,
+        result = ?result,
         execution_time_ms = execution_time.as_millis(),
         "WASM execution completed successfully"
     );
 
-    info!("DEBUG: Building response...");
-    let response = ExecResponse {
+    Ok(ExecResponse {
         success: true,
-        result: Some(result),
+        result,
         error: None,
         function: exec_req.func,
         operands: exec_req.arg,
         owner: username,
+        output: None,
+        output_base64: None,
+        wasi: with_wasi.then(|| wasi_report(store, exit_code)),
+        deterministic: None,
+        cached: false,
+    })
+}
+
+// Functions every math module provides; they take (i32, i32) -> i32, except abs
+const ALLOWED_FUNCTIONS: [&str; 9] = ["add", "mul", "sub", "div", "rem", "abs", "max", "min", "pow"];
+
+// Calls a function declared in the record's func_names. It may take up to two
+// i32 parameters (filled from `arg` in order) and return nothing or one i32.
+fn call_declared(store: &mut Store<HostState>, func: Func, arg: [i32; 2], cancel: Option<&AtomicBool>) -> Result<Option<i32>, wasmi::Error> {
+    let ty = func.ty(&*store);
+    if ty.params().len() > arg.len() || ty.params().iter().any(|param| *param != ValType::I32) {
+        return Err(wasmi::Error::new(format!("unsupported parameters {:?}: expected up to two i32", ty.params())));
+    }
+    let mut results = match ty.results() {
+        [] => vec![],
+        [ValType::I32] => vec![Val::I32(0)],
+        other => return Err(wasmi::Error::new(format!("unsupported results {:?}: expected none or one i32", other))),
     };
-    info!("DEBUG: Response struct created");
+    let params: Vec<Val> = arg[..ty.params().len()].iter().map(|value| Val::I32(*value)).collect();
+    wasm::call(store, func, &params, &mut results, cancel)?;
+    Ok(results.first().and_then(Val::i32))
+}
 
-    info!("DEBUG: Serializing response to JSON...");
-    let json_body = serde_json::to_string(&response)?;
-    info!("DEBUG: JSON serialized: {}", json_body);
+// A WASI proc_exit ends the run with an exit code rather than failing it
+fn split_exit<T>(outcome: Result<T, wasmi::Error>) -> Result<(Option<T>, Option<i32>), wasmi::Error> {
+    match outcome {
+        Ok(value) => Ok((Some(value), None)),
+        Err(e) => match e.i32_exit_status() {
+            Some(code) => Ok((None, Some(code))),
+            None => Err(e),
+        },
+    }
+}
 
-    info!("DEBUG: Building HTTP response...");
-    let http_response = Response::builder(StatusCode::Ok)
-        .body(json_body)
-        .content_type(tide::http::mime::JSON)
-        .build();
-    info!("DEBUG: HTTP response built successfully");
+// Captured output of a WASI run; returning normally counts as exit code 0
+fn wasi_report(store: &Store<HostState>, exit_code: Option<i32>) -> WasiReport {
+    let wasi = &store.data().wasi;
+    WasiReport {
+        stdout: String::from_utf8_lossy(&wasi.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&wasi.stderr).into_owned(),
+        exit_code: exit_code.unwrap_or(0),
+    }
+}
 
-    info!("DEBUG: Returning response...");
-    Ok(http_response)
+// The bytes of a request's payload, if it has one
+fn payload_bytes(exec_req: &ExecRequest) -> tide::Result<Option<Vec<u8>>> {
+    match (&exec_req.payload, &exec_req.payload_base64) {
+        (Some(_), Some(_)) => Err(tide::Error::from_str(400, "Send either payload or payload_base64, not both")),
+        (Some(json), None) => Ok(Some(serde_json::to_vec(json)?)),
+        (None, Some(encoded)) => STANDARD
+            .decode(encoded)
+            .map(Some)
+            .map_err(|e| tide::Error::from_str(400, format!("Invalid payload_base64: {e}"))),
+        (None, None) => Ok(None),
+    }
 }
 
 // Helper function to update failed execution metrics
 fn update_failed_metrics(state: &AppState) {
-    let state_guard = state.lock().unwrap();
-    state_guard.metrics.failed_executions.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    state.metrics.failed_executions.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
 }
 
 // Validation functions
@@ -253,7 +601,7 @@ fn validate_arguments(args: &[i32; 2], func: &str) -> tide::Result<()> {
 
     // Check argument ranges
     for (i, &arg) in args.iter().enumerate() {
-        if arg < MIN_ARGUMENT || arg > MAX_ARGUMENT {
+        if !(MIN_ARGUMENT..=MAX_ARGUMENT).contains(&arg) {
             return Err(tide::Error::from_str(
                 400,
                 format!("Argument {} ({}) out of range [{}, {}]", i, arg, MIN_ARGUMENT, MAX_ARGUMENT)
@@ -263,15 +611,11 @@ fn validate_arguments(args: &[i32; 2], func: &str) -> tide::Result<()> {
 
     // Function-specific validations
     match func {
-        "div" | "rem" => {
-            if args[1] == 0 {
-                return Err(tide::Error::from_str(400, "Division by zero"));
-            }
+        "div" | "rem" if args[1] == 0 => {
+            return Err(tide::Error::from_str(400, "Division by zero"));
         }
-        "pow" => {
-            if args[1] < 0 || args[1] > 10 {
-                return Err(tide::Error::from_str(400, "Power exponent must be 0-10"));
-            }
+        "pow" if !(0..=10).contains(&args[1]) => {
+            return Err(tide::Error::from_str(400, "Power exponent must be 0-10"));
         }
         _ => {}
     }
@@ -1,5 +1,9 @@
+pub mod archive;
+pub mod bulk;
 pub mod create;
 pub mod delete;
 pub mod read;
 pub mod update;
-pub mod execute;
\ No newline at end of file
+pub mod execute;
+pub mod trash;
+pub mod users;
@@ -1,4 +1,4 @@
-use crate::auth::get_authenticated_user;
+use crate::auth::authenticated_user;
 use crate::state::AppState;
 use tide::Request;
 use tracing::info;
@@ -6,10 +6,10 @@ use std::time::Instant;
 
 pub async fn read_all_data(req: Request<AppState>) -> tide::Result {
     let start_time = Instant::now();
-    let username = get_authenticated_user(&req)?;
+    let username = authenticated_user(&req)?.username;
     info!(user = %username, "Read all data started");
     let state = req.state();
-    let app_state = state.lock().unwrap();
+    let app_state = state.read().unwrap();
     let record_count = app_state.data.len();
     info!(user = %username, record_count = %record_count, "Retrieved all records from state");
     let execution_time = start_time.elapsed();
@@ -19,14 +19,14 @@ pub async fn read_all_data(req: Request<AppState>) -> tide::Result {
 
 pub async fn read_data(req: Request<AppState>) -> tide::Result {
     let start_time = Instant::now();
-    let username = get_authenticated_user(&req)?;
+    let username = authenticated_user(&req)?.username;
     let id: u32 = match req.param("id")?.parse() {
         Ok(val) => val,
         Err(_) => return Err(tide::Error::from_str(400, "Invalid id")),
     };
     info!(user = %username, record_id = %id, "Read single data started");
     let state = req.state();
-    let app_state = state.lock().unwrap();
+    let app_state = state.read().unwrap();
     if let Some(entry) = app_state.data.get(&id) {
         let execution_time = start_time.elapsed();
         info!(user = %username, record_id = %id, owner = %entry.owner, func_count = entry.func_names.len(), execution_time_ms = execution_time.as_millis(), "Read single data completed successfully");
@@ -0,0 +1,167 @@
+use crate::auth::{authenticated_user, ROLE_ADMIN};
+use crate::models::TrashedEntry;
+use crate::state::{AppState, AppStateInner};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, Instant};
+use tide::Request;
+use tracing::info;
+
+// How long soft-deleted records stay restorable
+pub fn get_trash_retention_days() -> i64 {
+    env::var("TRASH_RETENTION_DAYS")
+        .unwrap_or_else(|_| "30".to_string())
+        .parse()
+        .unwrap_or(30)
+}
+
+// How often the background task purges expired trash
+fn get_trash_purge_interval_secs() -> u64 {
+    env::var("TRASH_PURGE_INTERVAL_SECS")
+        .unwrap_or_else(|_| "3600".to_string())
+        .parse()
+        .unwrap_or(3600)
+}
+
+// Moves a live record into the trash, returning when it will be purged
+pub fn move_to_trash(app_state: &mut AppStateInner, id: u32, deleted_by: &str) -> Option<DateTime<Utc>> {
+    let entry = app_state.data.remove(&id)?;
+    app_state.wasm_cache.remove(&id);
+    let deleted_at = Utc::now();
+    let purge_at = deleted_at + chrono::Duration::days(get_trash_retention_days());
+    app_state.trash.insert(id, TrashedEntry {
+        entry,
+        deleted_by: deleted_by.to_string(),
+        deleted_at,
+        purge_at,
+    });
+    Some(purge_at)
+}
+
+// Lists the caller's soft-deleted records (admins see everyone's)
+pub async fn list_trash(req: Request<AppState>) -> tide::Result {
+    let start_time = Instant::now();
+    let user = authenticated_user(&req)?;
+    let username = user.username.as_str();
+    info!(user = %username, "List trash started");
+    let state = req.state();
+    let app_state = state.read().unwrap();
+    let admin = user.has_role(ROLE_ADMIN);
+    let trash: HashMap<u32, TrashedEntry> = app_state
+        .trash
+        .iter()
+        .filter(|(_, trashed)| admin || trashed.entry.owner == username)
+        .map(|(id, trashed)| (*id, trashed.clone()))
+        .collect();
+    let execution_time = start_time.elapsed();
+    info!(user = %username, record_count = trash.len(), execution_time_ms = execution_time.as_millis(), "List trash completed successfully");
+    Ok(tide::Body::from_json(&trash)?.into())
+}
+
+// Moves a soft-deleted record back under its original id
+pub async fn restore_data(req: Request<AppState>) -> tide::Result {
+    let start_time = Instant::now();
+    let user = authenticated_user(&req)?;
+    let username = user.username.as_str();
+    let id: u32 = match req.param("id")?.parse() {
+        Ok(val) => val,
+        Err(_) => return Err(tide::Error::from_str(400, "Invalid id")),
+    };
+    info!(user = %username, record_id = %id, "Data restore started");
+    let state = req.state();
+    let mut app_state = state.lock().unwrap();
+
+    let owner = match app_state.trash.get(&id) {
+        Some(trashed) => trashed.entry.owner.clone(),
+        None => {
+            let execution_time = start_time.elapsed();
+            info!(user = %username, record_id = %id, execution_time_ms = execution_time.as_millis(), "Data restore failed - record not in trash");
+            return Ok(tide::Response::new(404));
+        }
+    };
+    if owner != username && !user.has_role(ROLE_ADMIN) {
+        let execution_time = start_time.elapsed();
+        info!(user = %username, record_id = %id, current_owner = %owner, execution_time_ms = execution_time.as_millis(), "Data restore failed - access denied");
+        return Err(tide::Error::from_str(403, "Access denied: not the owner"));
+    }
+    if app_state.data.contains_key(&id) {
+        return Err(tide::Error::from_str(409, "A live record already uses this id"));
+    }
+
+    let trashed = app_state.trash.remove(&id).unwrap();
+    app_state.data.insert(id, trashed.entry);
+    let execution_time = start_time.elapsed();
+    info!(user = %username, record_id = %id, execution_time_ms = execution_time.as_millis(), "Data restore completed successfully");
+    Ok(tide::Body::from_json(&serde_json::json!({ "id": id }))?.into())
+}
+
+// Drops every trashed record whose retention period ended before `now`
+pub fn purge_expired_trash(app_state: &mut AppStateInner, now: DateTime<Utc>) -> usize {
+    let before = app_state.trash.len();
+    app_state.trash.retain(|_, trashed| trashed.purge_at > now);
+    before - app_state.trash.len()
+}
+
+// Background task that periodically purges expired trash
+pub async fn run_trash_purge(state: AppState) {
+    let interval = Duration::from_secs(get_trash_purge_interval_secs());
+    info!(
+        retention_days = get_trash_retention_days(),
+        interval_secs = interval.as_secs(),
+        "Trash purge task started"
+    );
+    loop {
+        async_std::task::sleep(interval).await;
+        let purged = purge_expired_trash(&mut state.lock().unwrap(), Utc::now());
+        if purged > 0 {
+            info!(purged = purged, "Expired trash purged");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DataEntry;
+    use crate::state::new_state;
+
+    fn trashed(owner: &str, purge_at: DateTime<Utc>) -> TrashedEntry {
+        TrashedEntry {
+            entry: DataEntry {
+                func_names: vec!["add".to_string()],
+                bytecode: vec![1, 2, 3],
+                owner: owner.to_string(),
+                host_api: None,
+                wasi: false,
+                pure_functions: vec![],
+            },
+            deleted_by: owner.to_string(),
+            deleted_at: purge_at - chrono::Duration::days(get_trash_retention_days()),
+            purge_at,
+        }
+    }
+
+    #[test]
+    fn test_purge_expired_trash() {
+        let state = new_state();
+        let mut state_guard = state.lock().unwrap();
+        let now = Utc::now();
+
+        state_guard.trash.insert(1, trashed("user1", now - chrono::Duration::seconds(1)));
+        state_guard.trash.insert(2, trashed("user1", now + chrono::Duration::days(1)));
+
+        assert_eq!(purge_expired_trash(&mut state_guard, now), 1);
+        assert!(!state_guard.trash.contains_key(&1));
+        assert!(state_guard.trash.contains_key(&2));
+
+        // Nothing left to purge yet
+        assert_eq!(purge_expired_trash(&mut state_guard, now), 0);
+    }
+
+    #[test]
+    fn test_trash_retention_default() {
+        assert_eq!(get_trash_retention_days(), 30);
+        assert_eq!(get_trash_purge_interval_secs(), 3600);
+    }
+}
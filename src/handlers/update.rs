@@ -1,6 +1,9 @@
-use crate::auth::{create_data_entry_from_request, get_authenticated_user};
-use crate::models::CreateDataRequest;
+use crate::auth::{authenticated_user, create_data_entry_from_request};
+use crate::handlers::create::read_upload;
+use crate::memo::check_pure_functions;
 use crate::state::AppState;
+use crate::wasm::features::allowed_features;
+use crate::wasm::validate_upload;
 use tide::Request;
 use tracing::info;
 use std::time::Instant;
@@ -9,7 +12,7 @@ pub async fn update_data(mut req: Request<AppState>) -> tide::Result {
     let start_time = Instant::now();
     
     // Check if user is authenticated
-    let username = get_authenticated_user(&req)?;
+    let username = authenticated_user(&req)?.username;
     let username_clone = username.clone();
 
     // Extract id from URL (e.g., /data/:id)
@@ -24,8 +27,8 @@ pub async fn update_data(mut req: Request<AppState>) -> tide::Result {
         "Data update started"
     );
 
-    // Read request body as JSON
-    let req_data: CreateDataRequest = req.body_json().await?;
+    // Read request body as JSON, or as module text
+    let req_data = read_upload(&mut req).await?;
     info!(
         user = %username_clone,
         record_id = %id,
@@ -33,6 +36,9 @@ pub async fn update_data(mut req: Request<AppState>) -> tide::Result {
         bytecode_length = req_data.bytecode.len(),
         "Request data parsed successfully"
     );
+    let allowed = allowed_features(&req.state().read().unwrap(), &username);
+    validate_upload(&req_data.bytecode, req_data.host_api.as_deref(), req_data.wasi, &allowed)?;
+    check_pure_functions(&req_data.bytecode, &req_data.pure_functions)?;
 
     // Get global state
     let state = req.state();
@@ -63,8 +69,11 @@ pub async fn update_data(mut req: Request<AppState>) -> tide::Result {
         // Create new DataEntry with owner
         let updated_entry = create_data_entry_from_request(req_data, username);
 
-        // Update the record
-        app_state.data.insert(id, updated_entry);
+        // Update the record; the cached bytecode and results are stale now
+        if let Some(previous) = app_state.data.insert(id, updated_entry) {
+            app_state.results.invalidate(&previous.bytecode);
+        }
+        app_state.wasm_cache.remove(&id);
         
         let execution_time = start_time.elapsed();
         info!(
@@ -0,0 +1,381 @@
+use crate::auth::{authenticated_user, is_admin, revoke_all_sessions, set_user_disabled, set_user_password, ROLE_ADMIN};
+use crate::handlers::trash::move_to_trash;
+use crate::jobs::remove_user_jobs;
+use crate::mfa::mfa_enabled;
+use crate::models::{
+    AuthenticatedUser, ChangePasswordRequest, CreateUserRequest, CurrentUserResponse, DeleteUserQuery, DeleteUserReport,
+    OwnedRecordsPolicy, ResetPasswordRequest, UserSummary, WasmFeaturesRequest, WasmFeaturesResponse,
+};
+use crate::state::{AppState, AppStateInner};
+use crate::wasm::features::{allowed_features, get_server_features, Feature};
+use tide::Request;
+use tracing::info;
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+const MAX_USERNAME_LENGTH: usize = 64;
+
+// Lists local and OIDC users (admin only)
+pub async fn list_users(req: Request<AppState>) -> tide::Result {
+    require_admin(&req)?;
+    let app_state = req.state().read().unwrap();
+    let mut usernames: Vec<&String> = app_state.users.keys().chain(app_state.external_users.keys()).collect();
+    usernames.sort();
+    usernames.dedup();
+
+    let users: Vec<UserSummary> = usernames
+        .into_iter()
+        .map(|username| UserSummary {
+            username: username.clone(),
+            admin: is_admin(username) || app_state.external_users.get(username).is_some_and(|external| external.admin),
+            disabled: app_state.disabled_users.contains(username),
+            external: app_state.external_users.contains_key(username),
+            mfa_enabled: mfa_enabled(&app_state, username),
+            record_count: app_state.data.values().filter(|entry| &entry.owner == username).count(),
+        })
+        .collect();
+    Ok(tide::Body::from_json(&users)?.into())
+}
+
+// Creates a local user with a password (admin only)
+pub async fn create_user(mut req: Request<AppState>) -> tide::Result {
+    let admin = require_admin(&req)?;
+    let create_req: CreateUserRequest = req.body_json().await?;
+    validate_username(&create_req.username)?;
+    validate_password(&create_req.password)?;
+
+    let mut app_state = req.state().lock().unwrap();
+    if user_exists(&app_state, &create_req.username) {
+        return Err(tide::Error::from_str(409, "User already exists"));
+    }
+    set_user_password(&mut app_state, &create_req.username, create_req.password);
+    info!(user = %admin.username, target_user = %create_req.username, "User created");
+    let mut response = tide::Response::new(201);
+    response.set_body(serde_json::json!({ "username": create_req.username }));
+    Ok(response)
+}
+
+// Disables a user: signing in is refused and every session and API key is revoked (admin only)
+pub async fn disable_user(req: Request<AppState>) -> tide::Result {
+    set_disabled(req, true)
+}
+
+// Re-enables a disabled user (admin only)
+pub async fn enable_user(req: Request<AppState>) -> tide::Result {
+    set_disabled(req, false)
+}
+
+// Sets a new password for a user, revoking their sessions (admin only)
+pub async fn reset_password(mut req: Request<AppState>) -> tide::Result {
+    let admin = require_admin(&req)?;
+    let username = req.param("username")?.to_string();
+    let reset_req: ResetPasswordRequest = req.body_json().await?;
+    validate_password(&reset_req.password)?;
+
+    let mut app_state = req.state().lock().unwrap();
+    if !app_state.users.contains_key(&username) {
+        return Ok(tide::Response::new(404));
+    }
+    set_user_password(&mut app_state, &username, reset_req.password);
+    info!(user = %admin.username, target_user = %username, "Password reset");
+    Ok(tide::Response::new(204))
+}
+
+// Deletes a user and applies `?records=trash|delete|transfer` to the records they own (admin only)
+pub async fn delete_user(req: Request<AppState>) -> tide::Result {
+    let admin = require_admin(&req)?;
+    let username = req.param("username")?.to_string();
+    let query: DeleteUserQuery = req
+        .query()
+        .map_err(|_| tide::Error::from_str(400, "Invalid query: expected ?records=trash|delete|transfer&transfer_to=USER"))?;
+    if username == admin.username {
+        return Err(tide::Error::from_str(409, "Admins cannot delete their own account"));
+    }
+
+    let mut app_state = req.state().lock().unwrap();
+    if !user_exists(&app_state, &username) {
+        return Ok(tide::Response::new(404));
+    }
+    let report = remove_user(&mut app_state, &username, &query, &admin.username)?;
+    info!(
+        user = %admin.username,
+        target_user = %username,
+        policy = ?query.records,
+        records_trashed = report.records_trashed,
+        records_deleted = report.records_deleted,
+        records_transferred = report.records_transferred,
+        "User deleted"
+    );
+    Ok(tide::Body::from_json(&report)?.into())
+}
+
+// Returns the caller's account details
+pub async fn current_user(req: Request<AppState>) -> tide::Result {
+    let user = authenticated_user(&req)?;
+    let app_state = req.state().read().unwrap();
+    let response = CurrentUserResponse {
+        record_count: app_state.data.values().filter(|entry| entry.owner == user.username).count(),
+        api_key_count: app_state.api_keys.values().filter(|key| key.owner == user.username).count(),
+        external: app_state.external_users.get(&user.username).cloned(),
+        mfa_enabled: mfa_enabled(&app_state, &user.username),
+        wasm_features: allowed_features(&app_state, &user.username),
+        username: user.username,
+        roles: user.roles,
+    };
+    Ok(tide::Body::from_json(&response)?.into())
+}
+
+// Changes the caller's password; every session, including this one, is revoked
+pub async fn change_password(mut req: Request<AppState>) -> tide::Result {
+    let username = authenticated_user(&req)?.username;
+    let change_req: ChangePasswordRequest = req.body_json().await?;
+    validate_password(&change_req.new_password)?;
+
+    let mut app_state = req.state().lock().unwrap();
+    match app_state.users.get(&username) {
+        None => return Err(tide::Error::from_str(400, "Account has no local password")),
+        Some(current) if current != &change_req.current_password => {
+            return Err(tide::Error::from_str(403, "Current password is incorrect"));
+        }
+        Some(_) => {}
+    }
+    set_user_password(&mut app_state, &username, change_req.new_password);
+    info!(user = %username, "Password changed by user");
+    Ok(tide::Response::new(204))
+}
+
+// Removes every trace of a user; owned records follow `query.records`
+fn remove_user(app_state: &mut AppStateInner, username: &str, query: &DeleteUserQuery, deleted_by: &str) -> Result<DeleteUserReport, tide::Error> {
+    let transfer_to = match (query.records, query.transfer_to.as_deref()) {
+        (OwnedRecordsPolicy::Transfer, Some(target)) if target != username && user_exists(app_state, target) => Some(target.to_string()),
+        (OwnedRecordsPolicy::Transfer, _) => {
+            return Err(tide::Error::from_str(400, "records=transfer requires transfer_to naming another existing user"));
+        }
+        _ => None,
+    };
+
+    let mut report = DeleteUserReport {
+        username: username.to_string(),
+        ..Default::default()
+    };
+    let owned: Vec<u32> = app_state.data.iter().filter(|(_, entry)| entry.owner == username).map(|(id, _)| *id).collect();
+    let trashed: Vec<u32> = app_state.trash.iter().filter(|(_, trashed)| trashed.entry.owner == username).map(|(id, _)| *id).collect();
+    match query.records {
+        OwnedRecordsPolicy::Trash => {
+            for id in owned {
+                move_to_trash(app_state, id, deleted_by);
+                report.records_trashed += 1;
+            }
+        }
+        OwnedRecordsPolicy::Delete => {
+            for id in owned.iter().chain(trashed.iter()) {
+                app_state.data.remove(id);
+                app_state.trash.remove(id);
+                app_state.wasm_cache.remove(id);
+                report.records_deleted += 1;
+            }
+        }
+        OwnedRecordsPolicy::Transfer => {
+            let target = transfer_to.unwrap_or_default();
+            for id in &owned {
+                if let Some(entry) = app_state.data.get_mut(id) {
+                    entry.owner = target.clone();
+                }
+            }
+            for id in &trashed {
+                if let Some(trashed) = app_state.trash.get_mut(id) {
+                    trashed.entry.owner = target.clone();
+                }
+            }
+            report.records_transferred = owned.len() + trashed.len();
+        }
+    }
+
+    report.revoked_tokens = revoke_all_sessions(app_state, username);
+    let keys_before = app_state.api_keys.len();
+    app_state.api_keys.retain(|_, key| key.owner != username);
+    report.revoked_api_keys = keys_before - app_state.api_keys.len();
+    // Instances and jobs are found by owner name, so a new account with the
+    // same name must not find them
+    app_state.instances.retain(|_, session| session.owner != username);
+    remove_user_jobs(app_state, username);
+    app_state.users.remove(username);
+    app_state.external_users.remove(username);
+    app_state.mfa.remove(username);
+    app_state.disabled_users.remove(username);
+    app_state.wasm_features.remove(username);
+    app_state.login_throttle.by_username.remove(username);
+    Ok(report)
+}
+
+// Shows the WASM features a user's modules may use (admin only)
+pub async fn get_wasm_features(req: Request<AppState>) -> tide::Result {
+    require_admin(&req)?;
+    let username = req.param("username")?;
+    let app_state = req.state().read().unwrap();
+    if !user_exists(&app_state, username) {
+        return Ok(tide::Response::new(404));
+    }
+    Ok(tide::Body::from_json(&wasm_features_of(&app_state, username))?.into())
+}
+
+// Narrows the WASM features a user's modules may use; features the server
+// does not allow stay disallowed (admin only)
+pub async fn set_wasm_features(mut req: Request<AppState>) -> tide::Result {
+    let admin = require_admin(&req)?;
+    let username = req.param("username")?.to_string();
+    let features_req: WasmFeaturesRequest = req.body_json().await.map_err(|_| {
+        let names: Vec<&str> = Feature::ALL.iter().map(|feature| feature.name()).collect();
+        tide::Error::from_str(400, format!("Invalid JSON: expected {{ allowed: [...] }} with features from {:?}", names))
+    })?;
+
+    let mut app_state = req.state().lock().unwrap();
+    if !user_exists(&app_state, &username) {
+        return Ok(tide::Response::new(404));
+    }
+    info!(user = %admin.username, target_user = %username, allowed = ?features_req.allowed, "WASM feature policy set");
+    app_state.wasm_features.insert(username.clone(), features_req.allowed);
+    Ok(tide::Body::from_json(&wasm_features_of(&app_state, &username))?.into())
+}
+
+// Removes a user's feature policy so the server's applies (admin only)
+pub async fn clear_wasm_features(req: Request<AppState>) -> tide::Result {
+    let admin = require_admin(&req)?;
+    let username = req.param("username")?.to_string();
+    let mut app_state = req.state().lock().unwrap();
+    if !user_exists(&app_state, &username) {
+        return Ok(tide::Response::new(404));
+    }
+    app_state.wasm_features.remove(&username);
+    info!(user = %admin.username, target_user = %username, "WASM feature policy cleared");
+    Ok(tide::Response::new(204))
+}
+
+fn wasm_features_of(app_state: &AppStateInner, username: &str) -> WasmFeaturesResponse {
+    WasmFeaturesResponse {
+        server: get_server_features(),
+        user: app_state.wasm_features.get(username).cloned(),
+        effective: allowed_features(app_state, username),
+    }
+}
+
+fn set_disabled(req: Request<AppState>, disabled: bool) -> tide::Result {
+    let admin = require_admin(&req)?;
+    let username = req.param("username")?.to_string();
+    if disabled && username == admin.username {
+        return Err(tide::Error::from_str(409, "Admins cannot disable their own account"));
+    }
+    let mut app_state = req.state().lock().unwrap();
+    if !user_exists(&app_state, &username) {
+        return Ok(tide::Response::new(404));
+    }
+    set_user_disabled(&mut app_state, &username, disabled);
+    info!(user = %admin.username, target_user = %username, disabled = disabled, "User status changed");
+    Ok(tide::Response::new(204))
+}
+
+fn require_admin(req: &Request<AppState>) -> Result<AuthenticatedUser, tide::Error> {
+    let user = authenticated_user(req)?;
+    if !user.has_role(ROLE_ADMIN) {
+        return Err(tide::Error::from_str(403, "Access denied: user administration requires admin privileges"));
+    }
+    Ok(user)
+}
+
+fn user_exists(app_state: &AppStateInner, username: &str) -> bool {
+    app_state.users.contains_key(username) || app_state.external_users.contains_key(username)
+}
+
+fn validate_username(username: &str) -> Result<(), tide::Error> {
+    let valid_chars = username.chars().all(|c| c.is_ascii_alphanumeric() || "._-@".contains(c));
+    if username.is_empty() || username.len() > MAX_USERNAME_LENGTH || !valid_chars {
+        return Err(tide::Error::from_str(
+            400,
+            format!("Invalid username: use 1-{} letters, digits or . _ - @", MAX_USERNAME_LENGTH),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_password(password: &str) -> Result<(), tide::Error> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(tide::Error::from_str(400, format!("Password must be at least {} characters", MIN_PASSWORD_LENGTH)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DataEntry;
+    use crate::state::new_state;
+
+    fn seed(app_state: &mut AppStateInner, owner: &str) -> u32 {
+        let id = app_state.allocate_id();
+        app_state.data.insert(id, DataEntry {
+            func_names: vec!["add".to_string()],
+            bytecode: vec![0],
+            owner: owner.to_string(),
+            host_api: None,
+            wasi: false,
+            pure_functions: vec![],
+        });
+        id
+    }
+
+    fn query(records: OwnedRecordsPolicy, transfer_to: Option<&str>) -> DeleteUserQuery {
+        DeleteUserQuery { records, transfer_to: transfer_to.map(str::to_string) }
+    }
+
+    #[test]
+    fn test_remove_user_trashes_records_by_default() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        let owned = seed(&mut app_state, "user1");
+        let other = seed(&mut app_state, "user2");
+
+        let report = remove_user(&mut app_state, "user1", &query(OwnedRecordsPolicy::Trash, None), "admin").unwrap();
+
+        assert_eq!(report.records_trashed, 1);
+        assert!(!app_state.users.contains_key("user1"));
+        assert_eq!(app_state.trash[&owned].deleted_by, "admin");
+        assert!(app_state.data.contains_key(&other));
+    }
+
+    #[test]
+    fn test_remove_user_delete_and_transfer() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        let live = seed(&mut app_state, "user1");
+        let trashed = seed(&mut app_state, "user1");
+        move_to_trash(&mut app_state, trashed, "user1");
+
+        let report = remove_user(&mut app_state, "user1", &query(OwnedRecordsPolicy::Transfer, Some("user2")), "admin").unwrap();
+        assert_eq!(report.records_transferred, 2);
+        assert_eq!(app_state.data[&live].owner, "user2");
+        assert_eq!(app_state.trash[&trashed].entry.owner, "user2");
+
+        let report = remove_user(&mut app_state, "user2", &query(OwnedRecordsPolicy::Delete, None), "admin").unwrap();
+        assert_eq!(report.records_deleted, 2);
+        assert!(app_state.data.is_empty() && app_state.trash.is_empty());
+    }
+
+    #[test]
+    fn test_remove_user_transfer_requires_existing_target() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        for target in [None, Some("nobody"), Some("user1")] {
+            let result = remove_user(&mut app_state, "user1", &query(OwnedRecordsPolicy::Transfer, target), "admin");
+            assert_eq!(result.unwrap_err().status(), 400);
+        }
+        assert!(app_state.users.contains_key("user1"));
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(validate_username("new.user-1@corp").is_ok());
+        assert!(validate_username("").is_err());
+        assert!(validate_username("has space").is_err());
+        assert!(validate_password("12345678").is_ok());
+        assert!(validate_password("short").is_err());
+    }
+}
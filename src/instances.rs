@@ -0,0 +1,267 @@
+// Stateful instances.
+//
+// POST /data/:id/instances instantiates a record's module once and keeps it.
+// Executes that name the instance (`"instance": "<id>"`) call it instead of a
+// fresh instance, so memory and globals carry over from one call to the next;
+// calls on one instance run one at a time. Each user keeps at most
+// INSTANCE_LIMIT_PER_USER instances. An instance unused for
+// INSTANCE_IDLE_TIMEOUT_SECS is torn down, and DELETE /instances/:id tears it
+// down at once.
+use crate::auth::{authenticated_user, generate_token_id};
+use crate::handlers::execute::{instantiate_record, LiveInstance, WasiRequest};
+use crate::models::InstanceSummary;
+use crate::wasm::features::allowed_features;
+use crate::state::{AppState, AppStateInner};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::env;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use tide::{Request, Response};
+use tracing::info;
+
+fn get_instance_limit_per_user() -> usize {
+    env::var("INSTANCE_LIMIT_PER_USER")
+        .unwrap_or_else(|_| "5".to_string())
+        .parse()
+        .unwrap_or(5)
+}
+
+fn get_instance_idle_timeout_secs() -> i64 {
+    env::var("INSTANCE_IDLE_TIMEOUT_SECS")
+        .unwrap_or_else(|_| "300".to_string())
+        .parse()
+        .unwrap_or(300)
+}
+
+pub struct Session {
+    pub id: String,
+    pub owner: String,
+    pub record_id: u32,
+    pub created_at: DateTime<Utc>,
+    last_used: AtomicI64, // Unix time in milliseconds
+    bytecode: Arc<[u8]>,  // The module the instance was created from
+    pub live: Mutex<LiveInstance>,
+}
+
+impl Session {
+    // Marks the instance as used now
+    pub fn touch(&self) {
+        self.last_used.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    fn last_used_at(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(self.last_used.load(Ordering::Relaxed)).unwrap_or(self.created_at)
+    }
+
+    // Whether the instance was created from these module bytes
+    pub fn runs(&self, bytecode: &[u8]) -> bool {
+        *self.bytecode == *bytecode
+    }
+
+    fn summary(&self) -> InstanceSummary {
+        let last_used_at = self.last_used_at();
+        InstanceSummary {
+            id: self.id.clone(),
+            record_id: self.record_id,
+            created_at: self.created_at,
+            last_used_at,
+            expires_at: last_used_at + Duration::seconds(get_instance_idle_timeout_secs()),
+        }
+    }
+}
+
+// Optional body of POST /data/:id/instances
+#[derive(Deserialize, Default)]
+struct InstanceRequest {
+    #[serde(default)]
+    wasi: Option<WasiRequest>, // Fixed for the life of the instance
+}
+
+// Instantiates a record's module and keeps it for later executes (201)
+pub async fn create_instance(mut req: Request<AppState>) -> tide::Result {
+    let username = authenticated_user(&req)?.username;
+    let id: u32 = req
+        .param("id")?
+        .parse()
+        .map_err(|_| tide::Error::from_str(400, "Invalid id"))?;
+    let body = req.body_string().await?;
+    let instance_req: InstanceRequest = if body.trim().is_empty() {
+        InstanceRequest::default()
+    } else {
+        serde_json::from_str(&body).map_err(|_| tide::Error::from_str(400, "Invalid JSON: expected { wasi?: { stdin, args, env, files } }"))?
+    };
+
+    let state = req.state().clone();
+    let (bytecode, host_api, with_wasi, features) = {
+        let mut app_state = state.lock().unwrap();
+        reap_idle_instances(&mut app_state, Utc::now());
+        check_instance_limit(&app_state, &username)?;
+        let entry = app_state
+            .data
+            .get(&id)
+            .ok_or_else(|| tide::Error::from_str(404, "Record not found"))?;
+        if entry.owner != username {
+            return Err(tide::Error::from_str(403, "Access denied: you can only instantiate your own WASM modules"));
+        }
+        if instance_req.wasi.is_some() && !entry.wasi {
+            return Err(tide::Error::from_str(400, "WASI settings need a record with wasi enabled"));
+        }
+        if entry.bytecode.is_empty() {
+            return Err(tide::Error::from_str(400, "WASM bytecode is empty"));
+        }
+        let features = allowed_features(&app_state, &username);
+        (Arc::<[u8]>::from(entry.bytecode.as_slice()), entry.host_api.clone(), entry.wasi, features)
+    };
+
+    // Compiling and running the start function happen without the state lock
+    let (owner, module) = (username.clone(), bytecode.clone());
+    let live = async_std::task::spawn_blocking(move || {
+        instantiate_record(id, &owner, &module, host_api.as_deref(), with_wasi, &features, instance_req.wasi, Vec::new(), true, Some(u64::MAX), false)
+    })
+    .await?;
+
+    let now = Utc::now();
+    let session = Arc::new(Session {
+        id: generate_token_id(),
+        owner: username.clone(),
+        record_id: id,
+        created_at: now,
+        last_used: AtomicI64::new(now.timestamp_millis()),
+        bytecode,
+        live: Mutex::new(live),
+    });
+    let mut app_state = state.lock().unwrap();
+    // Another request may have taken the last slot in the meantime
+    check_instance_limit(&app_state, &username)?;
+    app_state.instances.insert(session.id.clone(), session.clone());
+    info!(user = %username, instance_id = %session.id, record_id = id, "Instance created");
+    Ok(Response::builder(201)
+        .header("Location", format!("/instances/{}", session.id))
+        .body(tide::Body::from_json(&session.summary())?)
+        .build())
+}
+
+// Lists the caller's instances, oldest first
+pub async fn list_instances(req: Request<AppState>) -> tide::Result {
+    let username = authenticated_user(&req)?.username;
+    let app_state = req.state().read().unwrap();
+    let mut instances: Vec<InstanceSummary> = app_state
+        .instances
+        .values()
+        .filter(|session| session.owner == username)
+        .map(|session| session.summary())
+        .collect();
+    instances.sort_by_key(|instance| instance.created_at);
+    Ok(tide::Body::from_json(&instances)?.into())
+}
+
+// Tears down one of the caller's instances; a call still running on it
+// finishes first
+pub async fn delete_instance(req: Request<AppState>) -> tide::Result {
+    let username = authenticated_user(&req)?.username;
+    let id = req.param("id")?;
+    let mut app_state = req.state().lock().unwrap();
+    if app_state.instances.get(id).is_none_or(|session| session.owner != username) {
+        return Err(tide::Error::from_str(404, "Instance not found"));
+    }
+    app_state.instances.remove(id);
+    info!(user = %username, instance_id = %id, "Instance deleted");
+    Ok(Response::new(204))
+}
+
+fn check_instance_limit(app_state: &AppStateInner, owner: &str) -> tide::Result<()> {
+    let limit = get_instance_limit_per_user();
+    let count = app_state.instances.values().filter(|session| session.owner == owner).count();
+    if count >= limit {
+        return Err(tide::Error::from_str(429, format!("Too many instances (limit {}); delete one first", limit)));
+    }
+    Ok(())
+}
+
+// Tears down instances idle for longer than INSTANCE_IDLE_TIMEOUT_SECS.
+// Instances with a call in flight are kept.
+fn reap_idle_instances(app_state: &mut AppStateInner, now: DateTime<Utc>) -> usize {
+    let cutoff = now - Duration::seconds(get_instance_idle_timeout_secs());
+    let before = app_state.instances.len();
+    app_state
+        .instances
+        .retain(|_, session| Arc::strong_count(session) > 1 || session.last_used_at() > cutoff);
+    before - app_state.instances.len()
+}
+
+// Background task that tears down idle instances
+pub async fn run_instance_reaper(state: AppState) {
+    let idle_timeout = get_instance_idle_timeout_secs();
+    let interval = std::time::Duration::from_secs(idle_timeout.clamp(1, 60) as u64);
+    info!(idle_timeout_secs = idle_timeout, interval_secs = interval.as_secs(), "Instance reaper started");
+    loop {
+        async_std::task::sleep(interval).await;
+        let reaped = reap_idle_instances(&mut state.lock().unwrap(), Utc::now());
+        if reaped > 0 {
+            info!(reaped = reaped, "Idle instances torn down");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::new_state;
+    use crate::wasm::features::Feature;
+
+    fn session(owner: &str, last_used: DateTime<Utc>) -> Arc<Session> {
+        let bytecode: Arc<[u8]> = Arc::from(wat::parse_str("(module)").unwrap());
+        let live = instantiate_record(1, owner, &bytecode, None, false, &Feature::ALL.into(), None, Vec::new(), true, Some(u64::MAX), false).unwrap();
+        Arc::new(Session {
+            id: generate_token_id(),
+            owner: owner.to_string(),
+            record_id: 1,
+            created_at: last_used,
+            last_used: AtomicI64::new(last_used.timestamp_millis()),
+            bytecode,
+            live: Mutex::new(live),
+        })
+    }
+
+    #[test]
+    fn test_reap_idle_instances() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        let now = Utc::now();
+        let stale = now - Duration::seconds(get_instance_idle_timeout_secs() + 1);
+        let idle = session("user1", stale);
+        let busy = session("user1", stale);
+        let fresh = session("user1", now);
+        for session in [&idle, &busy, &fresh] {
+            app_state.instances.insert(session.id.clone(), session.clone());
+        }
+        let (idle_id, busy_id, fresh_id) = (idle.id.clone(), busy.id.clone(), fresh.id.clone());
+        drop((idle, fresh));
+
+        // `busy` is still referenced, as by a call in flight
+        assert_eq!(reap_idle_instances(&mut app_state, now), 1);
+        assert!(!app_state.instances.contains_key(&idle_id));
+        assert!(app_state.instances.contains_key(&busy_id));
+        assert!(app_state.instances.contains_key(&fresh_id));
+    }
+
+    #[test]
+    fn test_instance_limit_is_per_user() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        for _ in 0..get_instance_limit_per_user() {
+            let session = session("user1", Utc::now());
+            app_state.instances.insert(session.id.clone(), session);
+        }
+        assert_eq!(check_instance_limit(&app_state, "user1").unwrap_err().status(), 429);
+        assert!(check_instance_limit(&app_state, "user2").is_ok());
+    }
+
+    #[test]
+    fn test_runs_compares_module_bytes() {
+        let session = session("user1", Utc::now());
+        assert!(session.runs(&wat::parse_str("(module)").unwrap()));
+        assert!(!session.runs(&[0, 97, 115, 109]));
+    }
+}
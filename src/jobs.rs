@@ -0,0 +1,344 @@
+// Asynchronous executions.
+//
+// POST /jobs checks a call the way /execute/:id does, queues it and returns
+// at once. Workers run jobs on the blocking thread pool without holding the
+// state lock. Each user has at most JOB_CONCURRENCY_PER_USER jobs running and
+// at most JOB_QUEUE_LIMIT_PER_USER waiting; queued jobs start in submission
+// order. DELETE /jobs/:id drops a queued job and stops a running one at its
+// next fuel slice. Finished jobs are kept for JOB_RETENTION_SECS.
+use crate::auth::{authenticated_user, generate_token_id};
+use crate::handlers::execute::{count_execution, prepare, run, ExecRequest, Execution};
+use crate::models::{JobStatus, JobSummary};
+use crate::state::{AppState, AppStateInner};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tide::{Request, Response};
+use tracing::info;
+
+fn get_job_concurrency_per_user() -> usize {
+    env::var("JOB_CONCURRENCY_PER_USER")
+        .unwrap_or_else(|_| "2".to_string())
+        .parse()
+        .unwrap_or(2)
+}
+
+fn get_job_queue_limit_per_user() -> usize {
+    env::var("JOB_QUEUE_LIMIT_PER_USER")
+        .unwrap_or_else(|_| "20".to_string())
+        .parse()
+        .unwrap_or(20)
+}
+
+fn get_job_retention_secs() -> i64 {
+    env::var("JOB_RETENTION_SECS")
+        .unwrap_or_else(|_| "3600".to_string())
+        .parse()
+        .unwrap_or(3600)
+}
+
+pub struct Job {
+    pub id: String,
+    pub owner: String,
+    pub record_id: u32,
+    pub function: String,
+    pub status: JobStatus,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+    pending: Option<Execution>, // Taken by the worker that runs the job
+    cancel: Arc<AtomicBool>,
+}
+
+impl Job {
+    fn summary(&self) -> JobSummary {
+        JobSummary {
+            id: self.id.clone(),
+            record_id: self.record_id,
+            function: self.function.clone(),
+            status: self.status,
+            cancel_requested: self.cancel.load(Ordering::Relaxed),
+            created_at: self.created_at,
+            started_at: self.started_at,
+            finished_at: self.finished_at,
+            result: self.result.clone(),
+            error: self.error.clone(),
+        }
+    }
+}
+
+// Body of POST /jobs: the record plus the fields /execute/:id takes
+#[derive(Deserialize)]
+struct JobRequest {
+    record_id: u32,
+    #[serde(flatten)]
+    call: ExecRequest,
+}
+
+// Queues an execution and returns the job (202)
+pub async fn create_job(mut req: Request<AppState>) -> tide::Result {
+    let username = authenticated_user(&req)?.username;
+    let job_req: JobRequest = req.body_json().await.map_err(|_| {
+        tide::Error::from_str(400, "Invalid JSON: expected { record_id: u32, fn: string, ... } with the fields of /execute/:id")
+    })?;
+
+    // The checks only need to read the state; the write lock is taken to queue
+    let state = req.state().clone();
+    let execution = {
+        let app_state = state.read().unwrap();
+        check_queue_limit(&app_state, &username)?;
+        count_execution(&state.metrics, &job_req.call.func);
+        prepare(&app_state, job_req.record_id, &username, job_req.call)
+    };
+    let execution = execution.inspect_err(|_| {
+        state.metrics.failed_executions.fetch_add(1, Ordering::Relaxed);
+    })?;
+
+    let mut app_state = state.lock().unwrap();
+    prune_finished_jobs(&mut app_state, Utc::now());
+    // Other requests may have queued jobs in between
+    check_queue_limit(&app_state, &username)?;
+    let id = generate_token_id();
+    app_state.jobs.insert(id.clone(), Job {
+        id: id.clone(),
+        owner: username.clone(),
+        record_id: execution.record_id,
+        function: execution.function().to_string(),
+        status: JobStatus::Queued,
+        created_at: Utc::now(),
+        started_at: None,
+        finished_at: None,
+        result: None,
+        error: None,
+        pending: Some(execution),
+        cancel: Arc::new(AtomicBool::new(false)),
+    });
+    app_state.job_queue.push_back(id.clone());
+    dispatch(&mut app_state, &state, &username);
+
+    let summary = app_state.jobs[&id].summary();
+    info!(user = %username, job_id = %id, record_id = summary.record_id, function = %summary.function, "Job queued");
+    Ok(Response::builder(202)
+        .header("Location", format!("/jobs/{}", id))
+        .body(tide::Body::from_json(&summary)?)
+        .build())
+}
+
+// Reports a job's status, and its result once finished
+pub async fn get_job(req: Request<AppState>) -> tide::Result {
+    let username = authenticated_user(&req)?.username;
+    let id = req.param("id")?;
+    let app_state = req.state().read().unwrap();
+    match app_state.jobs.get(id) {
+        // Other users' jobs look the same as unknown ones
+        Some(job) if job.owner == username => Ok(tide::Body::from_json(&job.summary())?.into()),
+        _ => Err(tide::Error::from_str(404, "Job not found")),
+    }
+}
+
+// Cancels a job: at once when queued (200), at the next fuel slice when
+// running (202)
+pub async fn cancel_job(req: Request<AppState>) -> tide::Result {
+    let username = authenticated_user(&req)?.username;
+    let id = req.param("id")?.to_string();
+    let mut app_state = req.state().lock().unwrap();
+    let Some(job) = app_state.jobs.get_mut(&id).filter(|job| job.owner == username) else {
+        return Err(tide::Error::from_str(404, "Job not found"));
+    };
+    let status = match job.status {
+        JobStatus::Queued => {
+            job.status = JobStatus::Cancelled;
+            job.finished_at = Some(Utc::now());
+            job.pending = None;
+            200
+        }
+        JobStatus::Running => {
+            job.cancel.store(true, Ordering::Relaxed);
+            202
+        }
+        _ => return Err(tide::Error::from_str(409, "Job already finished")),
+    };
+    let summary = job.summary();
+    app_state.job_queue.retain(|queued| *queued != id);
+    info!(user = %username, job_id = %id, "Job cancelled");
+    Ok(Response::builder(status).body(tide::Body::from_json(&summary)?).build())
+}
+
+// Forgets every job of a user; running ones are told to stop at the next
+// fuel slice and find their job gone when they finish
+pub fn remove_user_jobs(app_state: &mut AppStateInner, owner: &str) {
+    app_state.jobs.retain(|_, job| {
+        if job.owner == owner {
+            job.cancel.store(true, Ordering::Relaxed);
+        }
+        job.owner != owner
+    });
+    let jobs = &app_state.jobs;
+    app_state.job_queue.retain(|id| jobs.contains_key(id));
+}
+
+fn check_queue_limit(app_state: &AppStateInner, owner: &str) -> tide::Result<()> {
+    let limit = get_job_queue_limit_per_user();
+    if queued_jobs(app_state, owner) >= limit {
+        return Err(tide::Error::from_str(429, format!("Too many queued jobs (limit {})", limit)));
+    }
+    Ok(())
+}
+
+fn queued_jobs(app_state: &AppStateInner, owner: &str) -> usize {
+    app_state.jobs.values().filter(|job| job.owner == owner && job.status == JobStatus::Queued).count()
+}
+
+fn running_jobs(app_state: &AppStateInner, owner: &str) -> usize {
+    app_state.jobs.values().filter(|job| job.owner == owner && job.status == JobStatus::Running).count()
+}
+
+// Starts the owner's oldest queued jobs while they have free slots
+fn dispatch(app_state: &mut AppStateInner, state: &AppState, owner: &str) {
+    while running_jobs(app_state, owner) < get_job_concurrency_per_user() {
+        let Some(position) = app_state
+            .job_queue
+            .iter()
+            .position(|id| app_state.jobs.get(id).is_some_and(|job| job.owner == owner))
+        else {
+            return;
+        };
+        let id = app_state.job_queue.remove(position).unwrap();
+        let Some(job) = app_state.jobs.get_mut(&id) else { continue };
+        let Some(execution) = job.pending.take() else { continue };
+        job.status = JobStatus::Running;
+        job.started_at = Some(Utc::now());
+        async_std::task::spawn(run_job(state.clone(), id, execution, job.cancel.clone()));
+    }
+}
+
+async fn run_job(state: AppState, id: String, execution: Execution, cancel: Arc<AtomicBool>) {
+    let flag = cancel.clone();
+    let outcome = async_std::task::spawn_blocking(move || run(execution, Some(&flag))).await;
+
+    let mut app_state = state.lock().unwrap();
+    let Some(job) = app_state.jobs.get_mut(&id) else { return };
+    job.finished_at = Some(Utc::now());
+    match outcome.and_then(|response| Ok(serde_json::to_value(response)?)) {
+        Ok(result) => {
+            state.metrics.successful_executions.fetch_add(1, Ordering::Relaxed);
+            job.status = JobStatus::Succeeded;
+            job.result = Some(result);
+        }
+        Err(_) if cancel.load(Ordering::Relaxed) => job.status = JobStatus::Cancelled,
+        Err(e) => {
+            state.metrics.failed_executions.fetch_add(1, Ordering::Relaxed);
+            job.status = JobStatus::Failed;
+            job.error = Some(e.to_string());
+        }
+    }
+    info!(user = %job.owner, job_id = %id, status = ?job.status, "Job finished");
+    let owner = job.owner.clone();
+    dispatch(&mut app_state, &state, &owner);
+}
+
+// Forgets jobs that finished more than JOB_RETENTION_SECS ago
+fn prune_finished_jobs(app_state: &mut AppStateInner, now: DateTime<Utc>) {
+    let cutoff = now - Duration::seconds(get_job_retention_secs());
+    app_state
+        .jobs
+        .retain(|_, job| !job.status.is_finished() || job.finished_at.is_some_and(|finished| finished > cutoff));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DataEntry;
+    use crate::state::new_state;
+
+    // Record 1, owned by user1, listing a `spin` its empty module does not export
+    fn insert_spin_record(app_state: &mut AppStateInner) {
+        app_state.data.insert(1, DataEntry {
+            func_names: vec!["spin".to_string()],
+            bytecode: vec![0, 97, 115, 109, 1, 0, 0, 0],
+            owner: "user1".to_string(),
+            host_api: None,
+            wasi: false,
+            pure_functions: vec![],
+        });
+    }
+
+    fn queue_job(app_state: &mut AppStateInner, owner: &str) -> String {
+        let call: ExecRequest = serde_json::from_value(serde_json::json!({"fn": "spin"})).unwrap();
+        let execution = prepare(app_state, 1, owner, call).unwrap();
+        let id = generate_token_id();
+        app_state.jobs.insert(id.clone(), Job {
+            id: id.clone(),
+            owner: owner.to_string(),
+            record_id: 1,
+            function: "spin".to_string(),
+            status: JobStatus::Queued,
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+            result: None,
+            error: None,
+            pending: Some(execution),
+            cancel: Arc::new(AtomicBool::new(false)),
+        });
+        app_state.job_queue.push_back(id.clone());
+        id
+    }
+
+    #[test]
+    fn test_dispatch_respects_the_per_user_limit() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        insert_spin_record(&mut app_state);
+        let ids: Vec<String> = (0..4).map(|_| queue_job(&mut app_state, "user1")).collect();
+
+        // Spawned runs execute on the blocking pool without the lock, but they
+        // record their outcome under it, so the statuses below hold until the
+        // lock held here is released
+        dispatch(&mut app_state, &state, "user1");
+        let limit = get_job_concurrency_per_user();
+        assert_eq!(running_jobs(&app_state, "user1"), limit);
+        assert_eq!(queued_jobs(&app_state, "user1"), ids.len() - limit);
+        // Oldest first
+        assert!(ids[..limit].iter().all(|id| app_state.jobs[id].status == JobStatus::Running));
+        assert_eq!(app_state.job_queue.front(), Some(&ids[limit]));
+    }
+
+    #[test]
+    fn test_remove_user_jobs_cancels_and_forgets_them() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        insert_spin_record(&mut app_state);
+        let running = queue_job(&mut app_state, "user1");
+        let queued = queue_job(&mut app_state, "user1");
+        let job = app_state.jobs.get_mut(&running).unwrap();
+        job.status = JobStatus::Running;
+        let cancel = job.cancel.clone();
+
+        remove_user_jobs(&mut app_state, "user1");
+        assert!(cancel.load(Ordering::Relaxed));
+        assert!(!app_state.jobs.contains_key(&running) && !app_state.jobs.contains_key(&queued));
+        assert!(app_state.job_queue.is_empty());
+    }
+
+    #[test]
+    fn test_prune_finished_jobs() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        insert_spin_record(&mut app_state);
+        let old = queue_job(&mut app_state, "user1");
+        let queued = queue_job(&mut app_state, "user1");
+        let now = Utc::now();
+        let job = app_state.jobs.get_mut(&old).unwrap();
+        job.status = JobStatus::Succeeded;
+        job.finished_at = Some(now - Duration::seconds(get_job_retention_secs() + 1));
+
+        prune_finished_jobs(&mut app_state, now);
+        assert!(!app_state.jobs.contains_key(&old));
+        assert!(app_state.jobs.contains_key(&queued));
+    }
+}
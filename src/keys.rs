@@ -0,0 +1,336 @@
+// JWT signing key management.
+//
+// Tokens are signed with HS256 and JWT_SECRET by default. Setting JWT_ALGORITHM
+// to RS256 or EdDSA switches to a key pair loaded from JWT_PRIVATE_KEY_PATH,
+// published with its key id (`kid`) at GET /.well-known/jwks.json. Retired keys
+// listed in JWT_PREVIOUS_PUBLIC_KEYS keep verifying tokens until they expire.
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+    OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header};
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair};
+use simple_asn1::ASN1Block;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::sync::OnceLock;
+use tide::Request;
+
+pub const DEFAULT_JWT_SECRET: &str = "your-secret-key-change-in-production";
+
+static SIGNING_KEYS: OnceLock<SigningKeys> = OnceLock::new();
+
+pub struct SigningKeys {
+    pub algorithm: Algorithm,
+    current_kid: Option<String>, // None for HS256, which has no published key
+    encoding_key: EncodingKey,
+    decoding_keys: HashMap<String, DecodingKey>, // kid -> key ("" for HS256)
+    jwks: JwkSet,
+}
+
+impl SigningKeys {
+    // Loads keys from the environment
+    pub fn from_env() -> Result<Self, String> {
+        let algorithm = match env::var("JWT_ALGORITHM").unwrap_or_else(|_| "HS256".to_string()).as_str() {
+            "HS256" => Algorithm::HS256,
+            "RS256" => Algorithm::RS256,
+            "EdDSA" => Algorithm::EdDSA,
+            other => return Err(format!("Unsupported JWT_ALGORITHM '{}' (expected HS256, RS256 or EdDSA)", other)),
+        };
+        if algorithm == Algorithm::HS256 {
+            let secret = env::var("JWT_SECRET").unwrap_or_else(|_| DEFAULT_JWT_SECRET.to_string());
+            return Ok(Self::from_secret(secret.as_bytes()));
+        }
+
+        let path = env::var("JWT_PRIVATE_KEY_PATH")
+            .map_err(|_| format!("JWT_PRIVATE_KEY_PATH is required when JWT_ALGORITHM={:?}", algorithm))?;
+        let kid = env::var("JWT_KEY_ID").unwrap_or_else(|_| "primary".to_string());
+        let private_pem = fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+        let mut previous = Vec::new();
+        let previous_keys = env::var("JWT_PREVIOUS_PUBLIC_KEYS").unwrap_or_default();
+        for item in previous_keys.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let (previous_kid, previous_path) = item
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid JWT_PREVIOUS_PUBLIC_KEYS entry '{}' (expected kid=path)", item))?;
+            let pem = fs::read(previous_path).map_err(|e| format!("Failed to read {}: {}", previous_path, e))?;
+            previous.push((previous_kid.to_string(), pem));
+        }
+
+        Self::from_key_pair(algorithm, &kid, &private_pem, &previous)
+    }
+
+    // Symmetric HS256 signing; nothing is published in the JWKS
+    pub fn from_secret(secret: &[u8]) -> Self {
+        let mut decoding_keys = HashMap::new();
+        decoding_keys.insert(String::new(), DecodingKey::from_secret(secret));
+        Self {
+            algorithm: Algorithm::HS256,
+            current_kid: None,
+            encoding_key: EncodingKey::from_secret(secret),
+            decoding_keys,
+            jwks: JwkSet { keys: Vec::new() },
+        }
+    }
+
+    // Asymmetric signing with a private key PEM, plus retired public keys that still verify
+    pub fn from_key_pair(algorithm: Algorithm, kid: &str, private_pem: &[u8], previous: &[(String, Vec<u8>)]) -> Result<Self, String> {
+        let encoding_key = match algorithm {
+            Algorithm::RS256 => EncodingKey::from_rsa_pem(private_pem),
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(private_pem),
+            _ => return Err(format!("{:?} is not an asymmetric algorithm", algorithm)),
+        }
+        .map_err(|e| format!("Invalid private key for key id '{}': {}", kid, e))?;
+
+        let mut jwks = vec![public_jwk(algorithm, kid, private_pem)?];
+        for (previous_kid, pem) in previous {
+            if previous_kid == kid || jwks.iter().any(|jwk| jwk.common.key_id.as_deref() == Some(previous_kid)) {
+                return Err(format!("Duplicate JWT key id '{}'", previous_kid));
+            }
+            jwks.push(public_jwk(algorithm, previous_kid, pem)?);
+        }
+
+        let mut decoding_keys = HashMap::new();
+        for jwk in &jwks {
+            let key = DecodingKey::from_jwk(jwk).map_err(|e| format!("Invalid public key: {}", e))?;
+            decoding_keys.insert(jwk.common.key_id.clone().unwrap_or_default(), key);
+        }
+
+        Ok(Self {
+            algorithm,
+            current_kid: Some(kid.to_string()),
+            encoding_key,
+            decoding_keys,
+            jwks: JwkSet { keys: jwks },
+        })
+    }
+
+    // Header for newly signed tokens, carrying the current key id
+    pub fn header(&self) -> Header {
+        let mut header = Header::new(self.algorithm);
+        header.kid = self.current_kid.clone();
+        header
+    }
+
+    pub fn encoding_key(&self) -> &EncodingKey {
+        &self.encoding_key
+    }
+
+    // Picks the verification key named by the token's `kid` header
+    pub fn decoding_key(&self, kid: Option<&str>) -> Option<&DecodingKey> {
+        match (&self.current_kid, kid) {
+            (None, _) => self.decoding_keys.get(""),
+            (Some(_), Some(kid)) => self.decoding_keys.get(kid),
+            (Some(_), None) => None,
+        }
+    }
+
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+}
+
+// Loads the signing keys and checks that a real secret is configured. Called once at startup.
+pub fn init() -> Result<(), String> {
+    let keys = SigningKeys::from_env()?;
+    if keys.algorithm == Algorithm::HS256 && !is_dev_mode() {
+        let secret = env::var("JWT_SECRET").unwrap_or_default();
+        if secret.is_empty() || secret == DEFAULT_JWT_SECRET {
+            return Err("Refusing to start with the default JWT secret: set JWT_SECRET, \
+                        use JWT_ALGORITHM=RS256/EdDSA, or set DEV_MODE=true for local development"
+                .to_string());
+        }
+    }
+    let _ = SIGNING_KEYS.set(keys);
+    Ok(())
+}
+
+// The active signing keys (loaded from the environment on first use)
+pub fn signing_keys() -> &'static SigningKeys {
+    SIGNING_KEYS.get_or_init(|| SigningKeys::from_env().expect("Invalid JWT key configuration"))
+}
+
+fn is_dev_mode() -> bool {
+    matches!(env::var("DEV_MODE").as_deref(), Ok("true") | Ok("1"))
+}
+
+// Publishes the public keys used to verify our tokens
+pub async fn jwks(_req: Request<crate::state::AppState>) -> tide::Result {
+    Ok(tide::Body::from_json(signing_keys().jwks())?.into())
+}
+
+// Builds the public JWK for a private (PKCS#8/PKCS#1) or public (SPKI) PEM
+fn public_jwk(algorithm: Algorithm, kid: &str, pem_bytes: &[u8]) -> Result<Jwk, String> {
+    let parsed = pem::parse(pem_bytes).map_err(|e| format!("Invalid PEM for key id '{}': {}", kid, e))?;
+    let der = parsed.contents();
+    let invalid = |e: String| format!("Invalid {:?} key for key id '{}': {}", algorithm, kid, e);
+
+    let (key_algorithm, parameters) = match algorithm {
+        Algorithm::RS256 => {
+            let public_der = match parsed.tag() {
+                "PRIVATE KEY" => RsaKeyPair::from_pkcs8(der).map_err(|e| invalid(e.to_string()))?.public_key().as_ref().to_vec(),
+                "RSA PRIVATE KEY" => RsaKeyPair::from_der(der).map_err(|e| invalid(e.to_string()))?.public_key().as_ref().to_vec(),
+                "PUBLIC KEY" => spki_key_bytes(der).map_err(invalid)?,
+                "RSA PUBLIC KEY" => der.to_vec(),
+                tag => return Err(invalid(format!("unexpected PEM block '{}'", tag))),
+            };
+            let (n, e) = rsa_components(&public_der).map_err(invalid)?;
+            (KeyAlgorithm::RS256, AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: URL_SAFE_NO_PAD.encode(n),
+                e: URL_SAFE_NO_PAD.encode(e),
+            }))
+        }
+        Algorithm::EdDSA => {
+            let public_key = match parsed.tag() {
+                "PRIVATE KEY" => Ed25519KeyPair::from_pkcs8_maybe_unchecked(der)
+                    .map_err(|e| invalid(e.to_string()))?
+                    .public_key()
+                    .as_ref()
+                    .to_vec(),
+                "PUBLIC KEY" => spki_key_bytes(der).map_err(invalid)?,
+                tag => return Err(invalid(format!("unexpected PEM block '{}'", tag))),
+            };
+            (KeyAlgorithm::EdDSA, AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(public_key),
+            }))
+        }
+        _ => return Err(invalid("not an asymmetric algorithm".to_string())),
+    };
+
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(key_algorithm),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    })
+}
+
+// Extracts the key bytes from a SubjectPublicKeyInfo structure
+fn spki_key_bytes(der: &[u8]) -> Result<Vec<u8>, String> {
+    let blocks = simple_asn1::from_der(der).map_err(|e| e.to_string())?;
+    match blocks.first() {
+        Some(ASN1Block::Sequence(_, items)) => match items.get(1) {
+            Some(ASN1Block::BitString(_, _, bytes)) => Ok(bytes.clone()),
+            _ => Err("missing public key bit string".to_string()),
+        },
+        _ => Err("not a SubjectPublicKeyInfo".to_string()),
+    }
+}
+
+// Splits a PKCS#1 RSAPublicKey into big-endian modulus and exponent
+fn rsa_components(der: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let blocks = simple_asn1::from_der(der).map_err(|e| e.to_string())?;
+    match blocks.first() {
+        Some(ASN1Block::Sequence(_, items)) => match (items.first(), items.get(1)) {
+            (Some(ASN1Block::Integer(_, n)), Some(ASN1Block::Integer(_, e))) => {
+                Ok((n.to_bytes_be().1, e.to_bytes_be().1))
+            }
+            _ => Err("malformed RSAPublicKey".to_string()),
+        },
+        _ => Err("not an RSAPublicKey".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{decode, decode_header, encode, Validation};
+    use serde_json::json;
+
+    const RSA_PRIVATE: &str = include_str!("../tests/fixtures/keys/rsa_private.pem");
+    const RSA_PUBLIC: &str = include_str!("../tests/fixtures/keys/rsa_public.pem");
+    const ED25519_PRIVATE: &str = include_str!("../tests/fixtures/keys/ed25519_private.pem");
+    const ED25519_PUBLIC: &str = include_str!("../tests/fixtures/keys/ed25519_public.pem");
+
+    fn roundtrip(keys: &SigningKeys) -> serde_json::Value {
+        let claims = json!({ "sub": "test_user", "exp": chrono::Utc::now().timestamp() + 60 });
+        let token = encode(&keys.header(), &claims, keys.encoding_key()).unwrap();
+        let kid = decode_header(&token).unwrap().kid;
+        let key = keys.decoding_key(kid.as_deref()).unwrap();
+        decode::<serde_json::Value>(&token, key, &Validation::new(keys.algorithm)).unwrap().claims
+    }
+
+    #[test]
+    fn test_rs256_sign_verify_and_jwks() {
+        let keys = SigningKeys::from_key_pair(Algorithm::RS256, "rsa-1", RSA_PRIVATE.as_bytes(), &[]).unwrap();
+
+        assert_eq!(roundtrip(&keys)["sub"], "test_user");
+        assert_eq!(keys.header().kid.as_deref(), Some("rsa-1"));
+
+        let jwks = serde_json::to_value(keys.jwks()).unwrap();
+        assert_eq!(jwks["keys"][0]["kty"], "RSA");
+        assert_eq!(jwks["keys"][0]["kid"], "rsa-1");
+        assert_eq!(jwks["keys"][0]["alg"], "RS256");
+        assert_eq!(jwks["keys"][0]["e"], "AQAB");
+        assert!(jwks["keys"][0].get("d").is_none(), "private exponent must never be published");
+    }
+
+    #[test]
+    fn test_eddsa_sign_verify_and_jwks() {
+        let keys = SigningKeys::from_key_pair(Algorithm::EdDSA, "ed-1", ED25519_PRIVATE.as_bytes(), &[]).unwrap();
+
+        assert_eq!(roundtrip(&keys)["sub"], "test_user");
+        let jwks = serde_json::to_value(keys.jwks()).unwrap();
+        assert_eq!(jwks["keys"][0]["kty"], "OKP");
+        assert_eq!(jwks["keys"][0]["crv"], "Ed25519");
+    }
+
+    #[test]
+    fn test_public_pem_matches_private_pem() {
+        let from_private = public_jwk(Algorithm::RS256, "k", RSA_PRIVATE.as_bytes()).unwrap();
+        let from_public = public_jwk(Algorithm::RS256, "k", RSA_PUBLIC.as_bytes()).unwrap();
+        assert_eq!(from_private, from_public);
+
+        let from_private = public_jwk(Algorithm::EdDSA, "k", ED25519_PRIVATE.as_bytes()).unwrap();
+        let from_public = public_jwk(Algorithm::EdDSA, "k", ED25519_PUBLIC.as_bytes()).unwrap();
+        assert_eq!(from_private, from_public);
+    }
+
+    #[test]
+    fn test_key_rotation_keeps_previous_keys() {
+        let old = SigningKeys::from_key_pair(Algorithm::RS256, "old", RSA_PRIVATE.as_bytes(), &[]).unwrap();
+        let claims = json!({ "sub": "test_user", "exp": chrono::Utc::now().timestamp() + 60 });
+        let old_token = encode(&old.header(), &claims, old.encoding_key()).unwrap();
+
+        // The old key is retired to the verification list, but not used for signing
+        let rotated = SigningKeys::from_key_pair(
+            Algorithm::RS256,
+            "new",
+            RSA_PRIVATE.as_bytes(),
+            &[("old".to_string(), RSA_PUBLIC.as_bytes().to_vec())],
+        )
+        .unwrap();
+        assert_eq!(rotated.jwks().keys.len(), 2);
+        assert_eq!(rotated.header().kid.as_deref(), Some("new"));
+        assert!(rotated.decoding_key(Some("old")).is_some());
+        assert!(rotated.decoding_key(Some("unknown")).is_none());
+        assert!(rotated.decoding_key(None).is_none());
+
+        let key = rotated.decoding_key(decode_header(&old_token).unwrap().kid.as_deref()).unwrap();
+        assert!(decode::<serde_json::Value>(&old_token, key, &Validation::new(Algorithm::RS256)).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_key_material_is_rejected() {
+        assert!(SigningKeys::from_key_pair(Algorithm::RS256, "k", b"not a pem", &[]).is_err());
+        assert!(SigningKeys::from_key_pair(Algorithm::EdDSA, "k", RSA_PRIVATE.as_bytes(), &[]).is_err());
+        assert!(SigningKeys::from_key_pair(Algorithm::HS256, "k", RSA_PRIVATE.as_bytes(), &[]).is_err());
+    }
+
+    #[test]
+    fn test_hs256_publishes_no_keys() {
+        let keys = SigningKeys::from_secret(b"secret");
+        assert!(keys.jwks().keys.is_empty());
+        assert!(keys.header().kid.is_none());
+        assert_eq!(roundtrip(&keys)["sub"], "test_user");
+    }
+}
@@ -0,0 +1,187 @@
+// Brute-force protection for /auth/login.
+//
+// Failed logins are counted per username and per client IP. Once a counter
+// reaches its limit, further attempts are refused for a lockout period that
+// doubles with every additional failure, up to a maximum. Unknown usernames
+// are tracked exactly like real ones so lockouts do not reveal which exist.
+use crate::auth::{authenticated_user, ROLE_ADMIN};
+use crate::models::FailedLogins;
+use crate::state::{AppState, LoginThrottle};
+use chrono::{DateTime, Duration, Utc};
+use std::env;
+use std::net::SocketAddr;
+use tide::Request;
+use tracing::info;
+
+// Failed attempts allowed for one username before it is locked
+fn get_login_max_attempts() -> u32 {
+    env::var("LOGIN_MAX_ATTEMPTS")
+        .unwrap_or_else(|_| "5".to_string())
+        .parse()
+        .unwrap_or(5)
+}
+
+// Failed attempts allowed from one client IP (across usernames) before it is locked
+fn get_login_max_attempts_per_ip() -> u32 {
+    env::var("LOGIN_MAX_ATTEMPTS_PER_IP")
+        .unwrap_or_else(|_| "20".to_string())
+        .parse()
+        .unwrap_or(20)
+}
+
+// First lockout period; each further failure doubles it
+fn get_login_lockout_secs() -> i64 {
+    env::var("LOGIN_LOCKOUT_SECS")
+        .unwrap_or_else(|_| "60".to_string())
+        .parse()
+        .unwrap_or(60)
+}
+
+// Longest lockout period, also how long failures are remembered
+fn get_login_lockout_max_secs() -> i64 {
+    env::var("LOGIN_LOCKOUT_MAX_SECS")
+        .unwrap_or_else(|_| "3600".to_string())
+        .parse()
+        .unwrap_or(3600)
+}
+
+// Admin endpoint: clears the failed-login counter of a username
+pub async fn unlock_user(req: Request<AppState>) -> tide::Result {
+    let user = authenticated_user(&req)?;
+    if !user.has_role(ROLE_ADMIN) {
+        return Err(tide::Error::from_str(403, "Access denied: unlocking accounts requires admin privileges"));
+    }
+    let username = req.param("username")?.to_string();
+    let was_locked = req.state().lock().unwrap().login_throttle.by_username.remove(&username).is_some();
+    info!(user = %user.username, target_user = %username, was_locked = was_locked, "Account unlocked");
+    Ok(tide::Response::new(204))
+}
+
+// The client address used for per-IP counting. Forwarding headers are ignored
+// because clients can set them freely.
+pub fn client_ip<State>(req: &Request<State>) -> String {
+    req.peer_addr()
+        .map(|addr| match addr.parse::<SocketAddr>() {
+            Ok(socket) => socket.ip().to_string(),
+            Err(_) => addr.to_string(),
+        })
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+// Returns the number of seconds to wait if either the username or the IP is locked
+pub fn check_login_allowed(throttle: &LoginThrottle, username: &str, ip: &str, now: DateTime<Utc>) -> Result<(), i64> {
+    let locked_until = [throttle.by_username.get(username), throttle.by_ip.get(ip)]
+        .into_iter()
+        .flatten()
+        .filter_map(|failed| failed.locked_until)
+        .filter(|until| *until > now)
+        .max();
+    match locked_until {
+        Some(until) => Err((until - now).num_seconds().max(1)),
+        None => Ok(()),
+    }
+}
+
+pub fn record_login_failure(throttle: &mut LoginThrottle, username: &str, ip: &str, now: DateTime<Utc>) {
+    record_failure(&mut throttle.by_username, username, get_login_max_attempts(), now);
+    record_failure(&mut throttle.by_ip, ip, get_login_max_attempts_per_ip(), now);
+}
+
+// A successful login resets the username counter. The IP counter is kept so an
+// attacker cannot reset it by logging into their own account.
+pub fn record_login_success(throttle: &mut LoginThrottle, username: &str) {
+    throttle.by_username.remove(username);
+}
+
+// Forgets counters that are unlocked and have seen no failure for the longest lockout period
+pub fn purge_stale_login_attempts(throttle: &mut LoginThrottle, now: DateTime<Utc>) -> usize {
+    let cutoff = now - Duration::seconds(get_login_lockout_max_secs());
+    let before = throttle.by_username.len() + throttle.by_ip.len();
+    let stale = |failed: &FailedLogins| failed.last_failure < cutoff && failed.locked_until.is_none_or(|until| until <= now);
+    throttle.by_username.retain(|_, failed| !stale(failed));
+    throttle.by_ip.retain(|_, failed| !stale(failed));
+    before - throttle.by_username.len() - throttle.by_ip.len()
+}
+
+fn record_failure(counters: &mut std::collections::HashMap<String, FailedLogins>, key: &str, max_attempts: u32, now: DateTime<Utc>) {
+    let failed = counters.entry(key.to_string()).or_insert(FailedLogins {
+        count: 0,
+        last_failure: now,
+        locked_until: None,
+    });
+    failed.count += 1;
+    failed.last_failure = now;
+    if failed.count >= max_attempts {
+        failed.locked_until = Some(now + lockout_duration(failed.count - max_attempts));
+    }
+}
+
+// Base lockout doubled `excess` times, capped at the maximum
+fn lockout_duration(excess: u32) -> Duration {
+    let secs = get_login_lockout_secs().saturating_mul(1i64 << excess.min(32));
+    Duration::seconds(secs.min(get_login_lockout_max_secs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_username_locks_after_max_attempts() {
+        let mut throttle = LoginThrottle::default();
+        let now = Utc::now();
+        for _ in 0..get_login_max_attempts() - 1 {
+            record_login_failure(&mut throttle, "user1", "10.0.0.1", now);
+            assert!(check_login_allowed(&throttle, "user1", "10.0.0.1", now).is_ok());
+        }
+        record_login_failure(&mut throttle, "user1", "10.0.0.1", now);
+
+        // Locked from any IP, other users unaffected
+        assert_eq!(check_login_allowed(&throttle, "user1", "10.0.0.2", now), Err(get_login_lockout_secs()));
+        assert!(check_login_allowed(&throttle, "user2", "10.0.0.2", now).is_ok());
+        // Lock expires
+        let later = now + Duration::seconds(get_login_lockout_secs());
+        assert!(check_login_allowed(&throttle, "user1", "10.0.0.2", later).is_ok());
+    }
+
+    #[test]
+    fn test_lockout_backoff_is_exponential_and_capped() {
+        assert_eq!(lockout_duration(0).num_seconds(), get_login_lockout_secs());
+        assert_eq!(lockout_duration(1).num_seconds(), get_login_lockout_secs() * 2);
+        assert_eq!(lockout_duration(3).num_seconds(), get_login_lockout_secs() * 8);
+        assert_eq!(lockout_duration(40).num_seconds(), get_login_lockout_max_secs());
+    }
+
+    #[test]
+    fn test_ip_locks_across_usernames() {
+        let mut throttle = LoginThrottle::default();
+        let now = Utc::now();
+        for attempt in 0..get_login_max_attempts_per_ip() {
+            record_login_failure(&mut throttle, &format!("guess{}", attempt), "10.0.0.1", now);
+        }
+        assert!(check_login_allowed(&throttle, "user1", "10.0.0.1", now).is_err());
+        assert!(check_login_allowed(&throttle, "user1", "10.0.0.2", now).is_ok());
+    }
+
+    #[test]
+    fn test_success_resets_username_only() {
+        let mut throttle = LoginThrottle::default();
+        let now = Utc::now();
+        record_login_failure(&mut throttle, "user1", "10.0.0.1", now);
+        record_login_success(&mut throttle, "user1");
+        assert!(!throttle.by_username.contains_key("user1"));
+        assert_eq!(throttle.by_ip["10.0.0.1"].count, 1);
+    }
+
+    #[test]
+    fn test_purge_stale_login_attempts() {
+        let mut throttle = LoginThrottle::default();
+        let long_ago = Utc::now() - Duration::seconds(get_login_lockout_max_secs() + 1);
+        record_login_failure(&mut throttle, "user1", "10.0.0.1", long_ago);
+        record_login_failure(&mut throttle, "user2", "10.0.0.2", Utc::now());
+
+        assert_eq!(purge_stale_login_attempts(&mut throttle, Utc::now()), 2);
+        assert!(throttle.by_username.contains_key("user2"));
+        assert!(throttle.by_ip.contains_key("10.0.0.2"));
+    }
+}
@@ -1,14 +1,32 @@
+mod api_keys;
 mod auth;
+mod cli;
 mod handlers;
+mod instances;
+mod jobs;
+mod keys;
+mod lockout;
+mod memo;
+mod mfa;
 mod models;
+mod oidc;
+mod sessions;
 mod state;
+mod wasm;
 
-use auth::{login, logout, refresh};
+use auth::{login, logout, logout_all, refresh, run_token_cleanup};
+use handlers::archive::{export_data, import_data};
+use handlers::bulk::bulk_data;
 use handlers::create::create_data;
 use handlers::delete::delete_data;
 use handlers::read::{read_all_data, read_data};
 use handlers::update::update_data;
 use handlers::execute::execute_fn;
+use handlers::trash::{list_trash, restore_data, run_trash_purge};
+use handlers::users::{
+    change_password, clear_wasm_features, create_user, current_user, delete_user, disable_user, enable_user, get_wasm_features, list_users,
+    reset_password, set_wasm_features,
+};
 use std::env;
 use std::sync::atomic::{AtomicU64, Ordering};
 use tracing::info;
@@ -23,11 +41,34 @@ async fn main() -> tide::Result<()> {
     // Load environment variables from .env file (if it exists)
     dotenv::dotenv().ok();
 
+    // Run a CLI subcommand (export/import) instead of the server if one was given
+    let args: Vec<String> = env::args().collect();
+    if let Some(command) = args.get(1).filter(|command| cli::is_subcommand(command)) {
+        if let Err(e) = cli::run(command, &args[2..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Load JWT signing keys; refuses the default secret outside dev mode
+    if let Err(e) = keys::init() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
     // Create the global application state
     let state = state::new_state();
 
+    // Purge soft-deleted records once their retention period ends
+    async_std::task::spawn(run_trash_purge(state.clone()));
+    // Forget revoked and refresh tokens once they have expired
+    async_std::task::spawn(run_token_cleanup(state.clone()));
+    // Tear down instances that have been idle too long
+    async_std::task::spawn(instances::run_instance_reaper(state.clone()));
+
     // Create the Tide app and associate the state
-    let mut app = tide::with_state(state);
+    let mut app = tide::with_state(state.clone());
 
     // Adiciona um middleware para logar a rota chamada e o contador
     app.with(tide::utils::Before(|req: tide::Request<_>| async move {
@@ -41,18 +82,71 @@ async fn main() -> tide::Result<()> {
         req
     }));
 
-    // Define authentication routes
+    // Attach RFC 6750 WWW-Authenticate challenges to authentication failures
+    app.with(tide::utils::After(auth::add_www_authenticate));
+    // Send module errors (WAT parse positions, disallowed features) as JSON
+    app.with(tide::utils::After(wasm::add_module_error_details));
+
+    // Public routes: the only ones reachable without credentials
     app.at("/auth/login").post(login);
     app.at("/auth/refresh").post(refresh);
     app.at("/auth/logout").post(logout);
+    app.at("/auth/mfa/login").post(mfa::mfa_login); // Second login step: mfa_token + code
+    app.at("/auth/oidc/login").get(oidc::oidc_login); // Redirect to the identity provider
+    app.at("/auth/oidc/callback").get(oidc::oidc_callback); // Identity provider redirects back here
+    app.at("/.well-known/jwks.json").get(keys::jwks); // Public keys for token verification
+
+    // Protected routes: everything on this server runs behind the authentication
+    // middleware, and it is nested at the root so any path not declared public
+    // above requires credentials
+    let mut protected = tide::with_state(state);
+    protected.with(auth::Authenticate);
+
+    protected.at("/auth/logout-all").post(logout_all);
+    protected.at("/auth/me").get(current_user); // Caller's account details
+    protected.at("/auth/me/password").put(change_password); // Change own password
+    protected.at("/auth/sessions").get(sessions::list_sessions); // Where the caller is signed in
+    protected.at("/auth/sessions/:id").delete(sessions::delete_session); // End one session
+    protected.at("/auth/mfa/enroll").post(mfa::enroll); // Start TOTP enrollment
+    protected.at("/auth/mfa/verify").post(mfa::verify); // Confirm enrollment with a code
+    protected.at("/auth/mfa/disable").post(mfa::disable); // Turn MFA off (code or recovery code)
+    protected.at("/auth/api-keys").post(api_keys::create_api_key); // Create API key
+    protected.at("/auth/api-keys").get(api_keys::list_api_keys); // List own API keys
+    protected.at("/auth/api-keys/:id").delete(api_keys::revoke_api_key); // Revoke API key
+
+    // Define CRUD routes
+    protected.at("/data").post(create_data); // Create
+    protected.at("/data").get(read_all_data); // Read all
+    protected.at("/data/:id").get(read_data); // Read one
+    protected.at("/data/:id").put(update_data); // Update
+    protected.at("/data/:id").delete(delete_data); // Delete (soft; ?hard=true for admins)
+    protected.at("/data/bulk").post(bulk_data); // Bulk create/update/delete
+    protected.at("/data/trash").get(list_trash); // List soft-deleted records
+    protected.at("/data/:id/restore").post(restore_data); // Restore from trash
+    protected.at("/data/:id/instances").post(instances::create_instance); // Start a stateful instance
+    protected.at("/instances").get(instances::list_instances); // List own instances
+    protected.at("/instances/:id").delete(instances::delete_instance); // Tear down an instance
+    protected.at("/execute/:id").post(execute_fn); // Executa funções wasm
+    protected.at("/jobs").post(jobs::create_job); // Queue an execution
+    protected.at("/jobs/:id").get(jobs::get_job); // Job status and result
+    protected.at("/jobs/:id").delete(jobs::cancel_job); // Cancel a job
+
+    // Define admin routes
+    protected.at("/admin/export").get(export_data); // Export everything as JSONL
+    protected.at("/admin/import").post(import_data); // Import a JSONL archive
+    protected.at("/admin/users").get(list_users); // List users
+    protected.at("/admin/users").post(create_user); // Create user
+    protected.at("/admin/users/:username").delete(delete_user); // Delete user (?records=trash|delete|transfer)
+    protected.at("/admin/users/:username/disable").post(disable_user); // Disable user
+    protected.at("/admin/users/:username/enable").post(enable_user); // Re-enable user
+    protected.at("/admin/users/:username/password").put(reset_password); // Reset password
+    protected.at("/admin/users/:username/unlock").post(lockout::unlock_user); // Clear a login lockout
+    protected.at("/admin/users/:username/mfa").delete(mfa::reset_mfa); // Remove a user's second factor
+    protected.at("/admin/users/:username/wasm-features").get(get_wasm_features); // Feature policy for the user's modules
+    protected.at("/admin/users/:username/wasm-features").put(set_wasm_features); // Narrow the features the user's modules may use
+    protected.at("/admin/users/:username/wasm-features").delete(clear_wasm_features); // Back to the server's policy
 
-    // Define CRUD routes (now protected by JWT authentication)
-    app.at("/data").post(create_data); // Create
-    app.at("/data").get(read_all_data); // Read all
-    app.at("/data/:id").get(read_data); // Read one
-    app.at("/data/:id").put(update_data); // Update
-    app.at("/data/:id").delete(delete_data); // Delete
-    app.at("/execute/:id").post(execute_fn); // Executa funções wasm
+    app.at("/").nest(protected);
 
     // Get server address from environment variable or use default
     let addr = env::var("SERVER_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".to_string());
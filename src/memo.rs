@@ -0,0 +1,209 @@
+// Memoized results of pure functions.
+//
+// A record lists functions whose result depends only on their arguments in
+// `pure_functions`. Plain calls to them (no input, payload, WASI or instance,
+// not deterministic) are answered from this cache when the same module was
+// called the same way in the last RESULT_CACHE_TTL_SECS, and the response
+// says `"cached": true`. At most RESULT_CACHE_MAX_ENTRIES results are kept,
+// oldest dropped first. Updating a record drops its module's results, and
+// results only count for the feature policy they were computed under.
+// Modules that import host_v1's clock or random functions cannot mark
+// functions pure.
+use crate::wasm::deterministic::{digest, NONDETERMINISTIC_IMPORTS};
+use crate::wasm::features::FeatureSet;
+use crate::wasm::host::HOST_API_V1;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use wasmparser::{Parser, Payload};
+
+fn get_result_cache_ttl_secs() -> u64 {
+    env::var("RESULT_CACHE_TTL_SECS")
+        .unwrap_or_else(|_| "300".to_string())
+        .parse()
+        .unwrap_or(300)
+}
+
+fn get_result_cache_max_entries() -> usize {
+    env::var("RESULT_CACHE_MAX_ENTRIES")
+        .unwrap_or_else(|_| "10000".to_string())
+        .parse()
+        .unwrap_or(10000)
+}
+
+// Rejects `pure_functions` on a module whose results may change between
+// calls with the same arguments. Bytes that do not parse are left to the
+// module checks.
+pub fn check_pure_functions(bytecode: &[u8], pure_functions: &[String]) -> tide::Result<()> {
+    if pure_functions.is_empty() {
+        return Ok(());
+    }
+    for payload in Parser::new(0).parse_all(bytecode) {
+        let Ok(Payload::ImportSection(reader)) = payload else { continue };
+        for import in reader.into_iter().flatten() {
+            if import.module == HOST_API_V1 && NONDETERMINISTIC_IMPORTS.contains(&import.name) {
+                return Err(tide::Error::from_str(
+                    400,
+                    format!("Module imports '{}::{}', so none of its functions can be in pure_functions", import.module, import.name),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Module digest, function and arguments of a call, and the features the
+// owner allowed; a narrower policy must compile the module again
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct CallKey {
+    module: String,
+    function: String,
+    args: [i32; 2],
+    features: FeatureSet,
+}
+
+impl CallKey {
+    pub fn new(bytecode: &[u8], features: &FeatureSet, function: &str, args: [i32; 2]) -> Self {
+        CallKey { module: digest(bytecode), function: function.to_string(), args, features: features.clone() }
+    }
+}
+
+struct Memo {
+    result: Option<i32>,
+    stored_at: Instant,
+    seq: u64, // Position in `order`
+}
+
+// Results by call, with their keys in insertion order for expiry and
+// eviction. It has its own lock so that executions only need to read the
+// state.
+#[derive(Default)]
+pub struct ResultCache(Mutex<Results>);
+
+#[derive(Default)]
+struct Results {
+    entries: HashMap<CallKey, Memo>,
+    order: VecDeque<(CallKey, u64)>,
+    next_seq: u64,
+}
+
+impl Results {
+    // Drops expired entries and, while over `max`, the oldest ones
+    fn trim(&mut self, now: Instant, max: usize) {
+        let ttl = Duration::from_secs(get_result_cache_ttl_secs());
+        while let Some((key, seq)) = self.order.front() {
+            // Keys stored again since have a newer position that counts
+            if let Some(memo) = self.entries.get(key).filter(|memo| memo.seq == *seq) {
+                if self.entries.len() <= max && now.duration_since(memo.stored_at) < ttl {
+                    break;
+                }
+                self.entries.remove(key);
+            }
+            self.order.pop_front();
+        }
+    }
+}
+
+impl ResultCache {
+    // The result of an earlier call, if it has not expired
+    pub fn get(&self, key: &CallKey) -> Option<Option<i32>> {
+        let mut results = self.0.lock().unwrap();
+        results.trim(Instant::now(), get_result_cache_max_entries());
+        results.entries.get(key).map(|memo| memo.result)
+    }
+
+    pub fn insert(&self, key: CallKey, result: Option<i32>) {
+        let max = get_result_cache_max_entries();
+        if max == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut results = self.0.lock().unwrap();
+        let seq = results.next_seq;
+        results.next_seq += 1;
+        results.entries.insert(key.clone(), Memo { result, stored_at: now, seq });
+        results.order.push_back((key, seq));
+        results.trim(now, max);
+    }
+
+    // Drops every result of a module
+    pub fn invalidate(&self, bytecode: &[u8]) {
+        let module = digest(bytecode);
+        let mut results = self.0.lock().unwrap();
+        results.entries.retain(|key, _| key.module != module);
+        results.order.retain(|(key, _)| key.module != module);
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.0.lock().unwrap().entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm::features::Feature;
+
+    fn all() -> FeatureSet {
+        Feature::ALL.into()
+    }
+
+    #[test]
+    fn test_results_are_keyed_by_module_function_and_args() {
+        let cache = ResultCache::default();
+        cache.insert(CallKey::new(b"module", &all(), "add", [2, 3]), Some(5));
+        assert_eq!(cache.get(&CallKey::new(b"module", &all(), "add", [2, 3])), Some(Some(5)));
+        assert_eq!(cache.get(&CallKey::new(b"module", &all(), "add", [3, 2])), None);
+        assert_eq!(cache.get(&CallKey::new(b"module", &all(), "mul", [2, 3])), None);
+        assert_eq!(cache.get(&CallKey::new(b"other", &all(), "add", [2, 3])), None);
+    }
+
+    #[test]
+    fn test_check_pure_functions() {
+        let random = wat::parse_str(r#"(module (import "host_v1" "random_u64" (func (result i64))))"#).unwrap();
+        let log = wat::parse_str(r#"(module (import "host_v1" "log" (func (param i32 i32 i32))))"#).unwrap();
+        let pure = vec!["roll".to_string()];
+        assert_eq!(check_pure_functions(&random, &pure).unwrap_err().status(), 400);
+        assert!(check_pure_functions(&random, &[]).is_ok());
+        assert!(check_pure_functions(&log, &pure).is_ok());
+    }
+
+    #[test]
+    fn test_results_are_kept_per_feature_policy() {
+        let cache = ResultCache::default();
+        cache.insert(CallKey::new(b"module", &all(), "add", [2, 3]), Some(5));
+        let narrowed: FeatureSet = [Feature::BulkMemory].into();
+        assert_eq!(cache.get(&CallKey::new(b"module", &narrowed, "add", [2, 3])), None);
+    }
+
+    #[test]
+    fn test_invalidate_drops_a_module() {
+        let cache = ResultCache::default();
+        cache.insert(CallKey::new(b"module", &all(), "add", [2, 3]), Some(5));
+        cache.insert(CallKey::new(b"other", &all(), "add", [2, 3]), Some(5));
+        cache.invalidate(b"module");
+        assert_eq!(cache.get(&CallKey::new(b"module", &all(), "add", [2, 3])), None);
+        assert_eq!(cache.get(&CallKey::new(b"other", &all(), "add", [2, 3])), Some(Some(5)));
+    }
+
+    #[test]
+    fn test_trim_evicts_oldest_and_expired() {
+        let cache = ResultCache::default();
+        for n in 0..3 {
+            cache.insert(CallKey::new(b"module", &all(), "add", [n, 0]), Some(n));
+        }
+        // Storing a key again moves it to the back
+        cache.insert(CallKey::new(b"module", &all(), "add", [0, 0]), Some(0));
+        let now = Instant::now();
+        cache.0.lock().unwrap().trim(now, 2);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&CallKey::new(b"module", &all(), "add", [1, 0])), None);
+        assert!(cache.get(&CallKey::new(b"module", &all(), "add", [0, 0])).is_some());
+
+        let later = now + Duration::from_secs(get_result_cache_ttl_secs());
+        cache.0.lock().unwrap().trim(later, 2);
+        assert_eq!(cache.len(), 0);
+    }
+}
@@ -0,0 +1,311 @@
+// Optional TOTP second factor (RFC 6238) for local accounts.
+//
+// Enrollment is two-step: /auth/mfa/enroll hands out a secret and recovery
+// codes, and the factor only becomes active once /auth/mfa/verify has seen a
+// valid code. From then on /auth/login answers with a short-lived "mfa_pending"
+// token that /auth/mfa/login exchanges for a session together with a code.
+// OIDC users get their second factor from the identity provider instead.
+use crate::auth::{auth_error, authenticated_user, decode_mfa_token, issue_session, session_client, ROLE_ADMIN};
+use crate::lockout::{check_login_allowed, client_ip, record_login_failure, record_login_success};
+use crate::models::{MfaCodeRequest, MfaEnrollResponse, MfaEnrollment, MfaLoginRequest};
+use crate::state::{AppState, AppStateInner};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::env;
+use tide::Request;
+use totp_rs::{Algorithm, TOTP};
+use tracing::{info, warn};
+
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP_SECS: u64 = 30;
+const TOTP_SKEW_STEPS: u8 = 1; // Codes from the previous and next step are accepted too
+const SECRET_BYTES: usize = 20;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+// Issuer shown by authenticator apps
+fn get_mfa_issuer() -> String {
+    env::var("MFA_ISSUER")
+        .unwrap_or_else(|_| "learn-rust-crud".to_string())
+}
+
+// Whether login for `username` needs a second factor
+pub fn mfa_enabled(app_state: &AppStateInner, username: &str) -> bool {
+    app_state.mfa.get(username).is_some_and(|enrollment| enrollment.confirmed)
+}
+
+// Starts (or restarts) enrollment for the caller. The secret and recovery
+// codes are only returned here.
+pub async fn enroll(req: Request<AppState>) -> tide::Result {
+    let username = authenticated_user(&req)?.username;
+    let mut app_state = req.state().lock().unwrap();
+    if !app_state.users.contains_key(&username) {
+        return Err(tide::Error::from_str(400, "Accounts without a local password use the identity provider's MFA"));
+    }
+    if mfa_enabled(&app_state, &username) {
+        return Err(tide::Error::from_str(409, "MFA is already enabled; disable it first"));
+    }
+
+    let secret: Vec<u8> = (0..SECRET_BYTES).map(|_| rand::random::<u8>()).collect();
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| generate_recovery_code()).collect();
+    let totp = totp(&secret, &username);
+    let response = MfaEnrollResponse {
+        secret: totp.get_secret_base32(),
+        otpauth_url: totp.get_url(),
+        recovery_codes: recovery_codes.clone(),
+    };
+    app_state.mfa.insert(username.clone(), MfaEnrollment {
+        secret,
+        confirmed: false,
+        recovery_codes: recovery_codes.iter().map(|code| hash_recovery_code(code)).collect(),
+        last_used_step: None,
+    });
+    info!(user = %username, "MFA enrollment started");
+    Ok(tide::Body::from_json(&response)?.into())
+}
+
+// Activates a pending enrollment once the authenticator produces a valid code
+pub async fn verify(mut req: Request<AppState>) -> tide::Result {
+    let username = authenticated_user(&req)?.username;
+    let code_req: MfaCodeRequest = req.body_json().await?;
+    let code = code_req.code.ok_or_else(|| tide::Error::from_str(400, "code is required"))?;
+
+    let mut app_state = req.state().lock().unwrap();
+    let enrollment = match app_state.mfa.get_mut(&username) {
+        None => return Err(tide::Error::from_str(400, "No MFA enrollment in progress")),
+        Some(enrollment) if enrollment.confirmed => return Err(tide::Error::from_str(409, "MFA is already enabled")),
+        Some(enrollment) => enrollment,
+    };
+    if !check_totp(enrollment, &username, &code, Utc::now()) {
+        return Err(tide::Error::from_str(400, "Invalid MFA code"));
+    }
+    enrollment.confirmed = true;
+    info!(user = %username, "MFA enabled");
+    Ok(tide::Response::new(204))
+}
+
+// Turns MFA off; needs a current code or a recovery code
+pub async fn disable(mut req: Request<AppState>) -> tide::Result {
+    let username = authenticated_user(&req)?.username;
+    let code_req: MfaCodeRequest = req.body_json().await?;
+    let ip = client_ip(&req);
+
+    let mut app_state = req.state().lock().unwrap();
+    if !mfa_enabled(&app_state, &username) {
+        return Err(tide::Error::from_str(400, "MFA is not enabled"));
+    }
+    if let Some(mut response) = reject_second_factor(&mut app_state, &username, &ip, &code_req) {
+        // The caller is authenticated; a wrong code must not look like a bad access token
+        if response.status() == 401 {
+            response.set_status(403);
+        }
+        return Ok(response);
+    }
+    app_state.mfa.remove(&username);
+    info!(user = %username, "MFA disabled by user");
+    Ok(tide::Response::new(204))
+}
+
+// Second step of a login: trades the "mfa_pending" token and a code for a session
+pub async fn mfa_login(mut req: Request<AppState>) -> tide::Result {
+    let login_req: MfaLoginRequest = req.body_json().await?;
+    let client = session_client(&req);
+    let username = decode_mfa_token(&login_req.mfa_token)?;
+
+    let mut app_state = req.state().lock().unwrap();
+    // MFA may have been reset since the password was checked
+    if !mfa_enabled(&app_state, &username) {
+        return Err(tide::Error::from_str(401, "Invalid MFA token"));
+    }
+    if let Some(response) = reject_second_factor(&mut app_state, &username, &client.ip, &login_req.code) {
+        return Ok(response);
+    }
+    let response = issue_session(&mut app_state, &username, &client)?;
+    info!(user = %username, "Login completed with second factor");
+    Ok(tide::Body::from_json(&response)?.into())
+}
+
+// Admin endpoint: removes a user's second factor, e.g. after a lost device
+pub async fn reset_mfa(req: Request<AppState>) -> tide::Result {
+    let admin = authenticated_user(&req)?;
+    if !admin.has_role(ROLE_ADMIN) {
+        return Err(tide::Error::from_str(403, "Access denied: resetting MFA requires admin privileges"));
+    }
+    let username = req.param("username")?.to_string();
+    if req.state().lock().unwrap().mfa.remove(&username).is_none() {
+        return Ok(tide::Response::new(404));
+    }
+    info!(user = %admin.username, target_user = %username, "MFA reset");
+    Ok(tide::Response::new(204))
+}
+
+// Checks a TOTP or recovery code, returning the error response if it is refused.
+// Wrong codes count towards the login lockout of the username and IP, which
+// keeps the 6-digit space from being guessed.
+fn reject_second_factor(app_state: &mut AppStateInner, username: &str, ip: &str, code_req: &MfaCodeRequest) -> Option<tide::Response> {
+    let now = Utc::now();
+    if let Err(retry_after) = check_login_allowed(&app_state.login_throttle, username, ip, now) {
+        let mut response = auth_error("too_many_attempts", "Too many failed attempts; try again later");
+        response.set_status(429);
+        response.insert_header("Retry-After", retry_after.to_string());
+        return Some(response);
+    }
+
+    let Some(enrollment) = app_state.mfa.get_mut(username) else {
+        return Some(auth_error("invalid_mfa_code", "Invalid MFA code"));
+    };
+    let valid = match (&code_req.code, &code_req.recovery_code) {
+        (Some(code), _) => check_totp(enrollment, username, code, now),
+        (None, Some(recovery_code)) => use_recovery_code(enrollment, recovery_code),
+        (None, None) => false,
+    };
+    if !valid {
+        record_login_failure(&mut app_state.login_throttle, username, ip, now);
+        warn!(user = %username, ip = %ip, "Invalid MFA code");
+        return Some(auth_error("invalid_mfa_code", "Invalid MFA code"));
+    }
+    record_login_success(&mut app_state.login_throttle, username);
+    None
+}
+
+// Accepts a code for the current step (give or take the skew) that is newer
+// than the last accepted one
+fn check_totp(enrollment: &mut MfaEnrollment, username: &str, code: &str, now: DateTime<Utc>) -> bool {
+    let totp = totp(&enrollment.secret, username);
+    let current_step = now.timestamp().max(0) as u64 / TOTP_STEP_SECS;
+    let code = code.trim();
+    let matched = (current_step.saturating_sub(TOTP_SKEW_STEPS as u64)..=current_step + TOTP_SKEW_STEPS as u64)
+        .filter(|step| enrollment.last_used_step.is_none_or(|last| *step > last))
+        .find(|step| constant_time_eq(totp.generate(step * TOTP_STEP_SECS).as_bytes(), code.as_bytes()));
+    match matched {
+        Some(step) => {
+            enrollment.last_used_step = Some(step);
+            true
+        }
+        None => false,
+    }
+}
+
+// Recovery codes work once
+fn use_recovery_code(enrollment: &mut MfaEnrollment, recovery_code: &str) -> bool {
+    let hash = hash_recovery_code(recovery_code);
+    let before = enrollment.recovery_codes.len();
+    enrollment.recovery_codes.retain(|stored| stored != &hash);
+    enrollment.recovery_codes.len() < before
+}
+
+fn totp(secret: &[u8], username: &str) -> TOTP {
+    TOTP::new_unchecked(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        TOTP_SKEW_STEPS,
+        TOTP_STEP_SECS,
+        secret.to_vec(),
+        Some(get_mfa_issuer()),
+        username.to_string(),
+    )
+}
+
+// Ten hex digits shown as `xxxxx-xxxxx`
+fn generate_recovery_code() -> String {
+    let digits: String = rand::random::<[u8; 5]>().iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}-{}", &digits[..5], &digits[5..])
+}
+
+// Hyphens, whitespace and case are ignored when comparing recovery codes
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    Sha256::digest(normalized.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::new_state;
+    use chrono::Duration;
+
+    fn enrollment() -> MfaEnrollment {
+        MfaEnrollment {
+            secret: b"12345678901234567890".to_vec(),
+            confirmed: true,
+            recovery_codes: vec![hash_recovery_code("abcde-12345")],
+            last_used_step: None,
+        }
+    }
+
+    #[test]
+    fn test_totp_matches_rfc_6238_vector() {
+        // RFC 6238 appendix B, SHA1, T = 59, truncated to 6 digits
+        assert_eq!(totp(b"12345678901234567890", "user1").generate(59), "287082");
+    }
+
+    #[test]
+    fn test_check_totp_allows_skew_and_rejects_replay() {
+        let mut enrollment = enrollment();
+        let now = Utc::now();
+        let previous = totp(&enrollment.secret, "user1").generate((now - Duration::seconds(30)).timestamp() as u64);
+
+        assert!(!check_totp(&mut enrollment, "user1", "000000x", now));
+        assert!(check_totp(&mut enrollment, "user1", &previous, now));
+        // The same code cannot be used twice
+        assert!(!check_totp(&mut enrollment, "user1", &previous, now));
+
+        let current = totp(&enrollment.secret, "user1").generate(now.timestamp() as u64);
+        assert!(check_totp(&mut enrollment, "user1", &current, now));
+        // Too old
+        let old = totp(&enrollment.secret, "user1").generate((now - Duration::seconds(120)).timestamp() as u64);
+        assert!(!check_totp(&mut enrollment, "user1", &old, now + Duration::seconds(30)));
+    }
+
+    #[test]
+    fn test_recovery_codes_are_single_use() {
+        let mut enrollment = enrollment();
+        assert!(use_recovery_code(&mut enrollment, "ABCDE 12345"));
+        assert!(!use_recovery_code(&mut enrollment, "abcde-12345"));
+        assert!(enrollment.recovery_codes.is_empty());
+    }
+
+    #[test]
+    fn test_generate_recovery_code_format() {
+        let code = generate_recovery_code();
+        assert_eq!(code.len(), 11);
+        assert_eq!(&code[5..6], "-");
+        assert_ne!(hash_recovery_code(&code), code);
+    }
+
+    #[test]
+    fn test_wrong_codes_count_towards_lockout() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        app_state.mfa.insert("user1".to_string(), enrollment());
+        let wrong = MfaCodeRequest { code: Some("000000x".to_string()), recovery_code: None };
+
+        assert_eq!(reject_second_factor(&mut app_state, "user1", "10.0.0.1", &wrong).unwrap().status(), 401);
+        assert_eq!(app_state.login_throttle.by_username["user1"].count, 1);
+
+        let recovery = MfaCodeRequest { code: None, recovery_code: Some("abcde-12345".to_string()) };
+        assert!(reject_second_factor(&mut app_state, "user1", "10.0.0.1", &recovery).is_none());
+        assert!(!app_state.login_throttle.by_username.contains_key("user1"));
+    }
+
+    #[test]
+    fn test_mfa_enabled_requires_confirmation() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        app_state.mfa.insert("user1".to_string(), MfaEnrollment { confirmed: false, ..enrollment() });
+        assert!(!mfa_enabled(&app_state, "user1"));
+        app_state.mfa.get_mut("user1").unwrap().confirmed = true;
+        assert!(mfa_enabled(&app_state, "user1"));
+        assert!(!mfa_enabled(&app_state, "user2"));
+    }
+}
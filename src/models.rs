@@ -1,5 +1,6 @@
 // This struct represents a data record in our CRUD.
 // It will be automatically converted to JSON using Serde.
+use crate::wasm::features::FeatureSet;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -7,6 +8,28 @@ pub struct DataEntry {
     pub func_names: Vec<String>, // Lista de textos
     pub bytecode: Vec<u8>,       // Lista de números inteiros (bytes)
     pub owner: String,           // Record owner
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_api: Option<String>, // Host API the module may import, e.g. "host_v1"
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub wasi: bool, // Module may import WASI preview1
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pure_functions: Vec<String>, // Functions whose results may be memoized (see crate::memo)
+}
+
+// A soft-deleted record waiting in the trash until `purge_at`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TrashedEntry {
+    pub entry: DataEntry,
+    pub deleted_by: String,
+    pub deleted_at: chrono::DateTime<chrono::Utc>,
+    pub purge_at: chrono::DateTime<chrono::Utc>,
+}
+
+// Query parameters accepted by DELETE /data/:id
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct DeleteQuery {
+    #[serde(default)]
+    pub hard: bool, // Skip the trash and remove permanently (admin only)
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -31,8 +54,134 @@ pub struct RefreshRequest {
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct CreateDataRequest {
-    pub func_names: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub func_names: Option<Vec<String>>, // Required for binary uploads; text uploads default to the exports
+    #[serde(default)]
     pub bytecode: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_api: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub wasi: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pure_functions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>, // "wasm" (default) or "wat"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>, // Module text when format is "wat"
+}
+
+// ===== BULK MODELS =====
+
+/// How a bulk request treats failures
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkMode {
+    #[default]
+    AllOrNothing, // Roll everything back on the first failure
+    BestEffort,   // Apply what succeeds, report the rest
+}
+
+/// A single operation inside a bulk request
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BulkOperation {
+    Create {
+        #[serde(flatten)]
+        data: CreateDataRequest,
+    },
+    Update {
+        id: u32,
+        #[serde(flatten)]
+        data: CreateDataRequest,
+    },
+    Delete {
+        id: u32,
+    },
+}
+
+/// Request for POST /data/bulk
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BulkRequest {
+    #[serde(default)]
+    pub mode: BulkMode,
+    pub operations: Vec<BulkOperation>,
+}
+
+/// Outcome of one bulk operation
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BulkItemResult {
+    pub index: usize,
+    pub op: String,
+    pub id: Option<u32>,
+    pub success: bool,
+    pub status: u16,
+    pub error: Option<String>,
+}
+
+/// Response for POST /data/bulk, shaped like `WasmBatchResponse`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BulkResponse {
+    pub results: Vec<BulkItemResult>,
+    pub total_operations: usize,
+    pub successful_operations: usize,
+    pub mode: BulkMode,
+    pub committed: bool,
+}
+
+// ===== ARCHIVE MODELS =====
+
+/// One line of a JSONL export archive. The first line is always a `header`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ArchiveLine {
+    Header {
+        format: String,
+        version: u32,
+        exported_at: chrono::DateTime<chrono::Utc>,
+        next_id: u32,
+    },
+    User {
+        username: String,
+        password: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        disabled: bool,
+    },
+    Record {
+        id: u32,
+        entry: DataEntry,
+    },
+    Trashed {
+        id: u32,
+        trashed: TrashedEntry,
+    },
+}
+
+/// What to do when an imported user or record already exists
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    #[default]
+    Skip,      // Keep what is already on the server
+    Overwrite, // Replace it with the imported copy
+    Renumber,  // Import records under a fresh id (users are skipped)
+}
+
+/// Query parameters accepted by POST /admin/import
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+}
+
+/// Summary returned by POST /admin/import
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ImportReport {
+    pub users_imported: usize,
+    pub users_skipped: usize,
+    pub records_imported: usize,
+    pub records_skipped: usize,
+    pub records_overwritten: usize,
+    pub renumbered: std::collections::BTreeMap<u32, u32>, // old id -> new id
 }
 
 // JWT Claims structure for access tokens
@@ -42,7 +191,183 @@ pub struct Claims {
     pub exp: i64,          // Expiration time
     pub iat: i64,          // Issued at
     pub iss: String,       // Issuer
+    pub aud: String,       // Audience
     pub token_type: String, // "access" or "refresh"
+    #[serde(default)]
+    pub jti: String,       // Unique token id
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUserRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+// One row of the admin user listing
+#[derive(Debug, Serialize)]
+pub struct UserSummary {
+    pub username: String,
+    pub admin: bool,
+    pub disabled: bool,
+    pub external: bool, // Signed in through OIDC
+    pub mfa_enabled: bool,
+    pub record_count: usize,
+}
+
+// GET /auth/me
+#[derive(Debug, Serialize)]
+pub struct CurrentUserResponse {
+    pub username: String,
+    pub roles: Vec<String>,
+    pub external: Option<ExternalUser>,
+    pub record_count: usize,
+    pub api_key_count: usize,
+    pub mfa_enabled: bool,
+    pub wasm_features: FeatureSet, // Features the user's modules may use
+}
+
+// PUT /admin/users/:username/wasm-features
+#[derive(Debug, Deserialize)]
+pub struct WasmFeaturesRequest {
+    pub allowed: FeatureSet,
+}
+
+// A user's feature policy: the server's, the user's own and what applies
+#[derive(Debug, Serialize)]
+pub struct WasmFeaturesResponse {
+    pub server: FeatureSet,
+    pub user: Option<FeatureSet>, // None when no policy is set for the user
+    pub effective: FeatureSet,
+}
+
+// What happens to a deleted user's records (live and trashed)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OwnedRecordsPolicy {
+    #[default]
+    Trash, // Move live records to the trash, restorable until purged
+    Delete, // Delete live and trashed records permanently
+    Transfer, // Give live and trashed records to `transfer_to`
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteUserQuery {
+    #[serde(default)]
+    pub records: OwnedRecordsPolicy,
+    pub transfer_to: Option<String>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct DeleteUserReport {
+    pub username: String,
+    pub records_trashed: usize,
+    pub records_deleted: usize,
+    pub records_transferred: usize,
+    pub revoked_tokens: usize,
+    pub revoked_api_keys: usize,
+}
+
+// An OIDC login that was started but has not come back through the callback yet
+#[derive(Debug, Clone)]
+pub struct PendingOidcLogin {
+    pub nonce: String,
+    pub code_verifier: String, // PKCE verifier
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+// A local username signed in through the external identity provider
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalUser {
+    pub issuer: String,
+    pub subject: String,
+    pub groups: Vec<String>,
+    pub admin: bool, // One of the groups is in OIDC_ADMIN_GROUPS
+    pub last_login_at: chrono::DateTime<chrono::Utc>,
+}
+
+// Query string of the OIDC redirect back to /auth/oidc/callback
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+// A user's TOTP second factor. Unconfirmed enrollments do not affect login.
+#[derive(Debug, Clone)]
+pub struct MfaEnrollment {
+    pub secret: Vec<u8>,
+    pub confirmed: bool,
+    pub recovery_codes: Vec<String>, // SHA-256 hashes of the unused recovery codes
+    pub last_used_step: Option<u64>, // Time step of the last accepted code, so it cannot be replayed
+}
+
+// POST /auth/mfa/enroll
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaEnrollResponse {
+    pub secret: String, // Base32, for manual entry
+    pub otpauth_url: String, // For QR codes
+    pub recovery_codes: Vec<String>,
+}
+
+// A TOTP code or, where allowed, a recovery code
+#[derive(Debug, Deserialize, Default)]
+pub struct MfaCodeRequest {
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub recovery_code: Option<String>,
+}
+
+// POST /auth/mfa/login
+#[derive(Debug, Deserialize)]
+pub struct MfaLoginRequest {
+    pub mfa_token: String,
+    #[serde(flatten)]
+    pub code: MfaCodeRequest,
+}
+
+// Returned by /auth/login instead of tokens when the user has MFA enabled
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaChallengeResponse {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+    pub expires_in: i64,
+}
+
+// Failed login counter for one username or client IP
+#[derive(Debug, Clone)]
+pub struct FailedLogins {
+    pub count: u32,
+    pub last_failure: chrono::DateTime<chrono::Utc>,
+    pub locked_until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// The caller of a protected route, inserted as a request extension by the
+// authentication middleware
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub username: String,
+    pub roles: Vec<String>,
+    pub token_id: String, // Access token `jti` or API key id
+}
+
+impl AuthenticatedUser {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|granted| granted == role)
+    }
 }
 
 // Refresh token storage structure
@@ -50,12 +375,78 @@ pub struct Claims {
 pub struct RefreshTokenInfo {
     pub username: String,
     pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub family_id: String,                                   // Shared by every token rotated from the same login
+    pub rotated_at: Option<chrono::DateTime<chrono::Utc>>, // Set once exchanged; presenting it again is reuse
+    pub issued_at: chrono::DateTime<chrono::Utc>,          // When the login that started the family happened
+    pub last_used_at: chrono::DateTime<chrono::Utc>,       // Login or latest refresh
+    pub user_agent: Option<String>,                        // Client of the latest login or refresh
+    pub ip: String,
+}
+
+// Where a login or refresh comes from, recorded on the refresh token it issues
+#[derive(Debug, Clone, Default)]
+pub struct SessionClient {
+    pub user_agent: Option<String>,
+    pub ip: String,
+}
+
+// A signed-in session (one refresh token family), as listed by GET /auth/sessions
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub id: String, // The family id
+    pub current: bool, // The session of the access token making the request
+    pub issued_at: chrono::DateTime<chrono::Utc>,
+    pub last_used_at: chrono::DateTime<chrono::Utc>,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub user_agent: Option<String>,
+    pub ip: String,
+}
+
+// Issued access token, tracked so it can be revoked before it expires
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IssuedAccessToken {
+    pub username: String,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub session_id: String, // Refresh token family it was issued for
+}
+
+// ===== API KEY MODELS =====
+
+/// Long-lived, scoped API key. Only a SHA-256 hash of the secret is stored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub owner: String,
+    #[serde(skip_serializing, default)]
+    pub key_hash: String,
+    pub scopes: Vec<String>, // e.g. "data:read", "data:write", "execute"
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Request for POST /auth/api-keys
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_in_days: Option<i64>, // None = never expires
+}
+
+/// Response for POST /auth/api-keys; `key` is shown only once
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CreateApiKeyResponse {
+    pub key: String,
+    #[serde(flatten)]
+    pub info: ApiKey,
 }
 
 // ===== WEBASSEMBLY MODELS =====
 
 /// Request for executing WebAssembly operations
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[allow(dead_code)]
 pub struct WasmExecuteRequest {
     pub operation: String,           // "add", "mul", "sub", "div"
     pub operands: Vec<i32>,          // [10, 20] para add(10, 20)
@@ -64,6 +455,7 @@ pub struct WasmExecuteRequest {
 
 /// Response from WebAssembly operations
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[allow(dead_code)]
 pub struct WasmExecuteResponse {
     pub success: bool,               // Operação foi bem-sucedida?
     pub result: Option<i32>,         // Resultado da operação
@@ -74,12 +466,14 @@ pub struct WasmExecuteResponse {
 
 /// Request for batch WebAssembly operations
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[allow(dead_code)]
 pub struct WasmBatchRequest {
     pub operations: Vec<WasmExecuteRequest>, // Múltiplas operações
 }
 
 /// Response for batch WebAssembly operations
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[allow(dead_code)]
 pub struct WasmBatchResponse {
     pub results: Vec<WasmExecuteResponse>,
     pub total_operations: usize,
@@ -88,6 +482,7 @@ pub struct WasmBatchResponse {
 
 /// Request for data processing with WebAssembly
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[allow(dead_code)]
 pub struct WasmDataProcessRequest {
     pub data: Vec<i32>,              // Dados para processar
     pub operations: Vec<String>,     // Operações a aplicar
@@ -96,6 +491,7 @@ pub struct WasmDataProcessRequest {
 
 /// Response for data processing with WebAssembly
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[allow(dead_code)]
 pub struct WasmDataProcessResponse {
     pub original_data: Vec<i32>,
     pub processed_data: Vec<i32>,
@@ -106,6 +502,7 @@ pub struct WasmDataProcessResponse {
 
 /// WebAssembly module information
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[allow(dead_code)]
 pub struct WasmModuleInfo {
     pub name: String,                // Nome do módulo
     pub available_functions: Vec<String>, // Funções disponíveis
@@ -114,6 +511,7 @@ pub struct WasmModuleInfo {
 
 /// Request for loading a WebAssembly module
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[allow(dead_code)]
 pub struct WasmLoadModuleRequest {
     pub module_name: String,         // Nome do módulo
     pub module_data: String,         // Dados WASM em Base64
@@ -121,6 +519,7 @@ pub struct WasmLoadModuleRequest {
 
 /// Response for loading a WebAssembly module
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[allow(dead_code)]
 pub struct WasmLoadModuleResponse {
     pub success: bool,
     pub module_name: String,
@@ -128,10 +527,57 @@ pub struct WasmLoadModuleResponse {
     pub available_functions: Option<Vec<String>>,
 }
 
+// ===== JOB MODELS =====
+
+/// Lifecycle of an asynchronous execution
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,    // Waiting for one of the owner's job slots
+    Running,
+    Succeeded, // `result` holds the execution response
+    Failed,    // `error` says why
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(self, JobStatus::Succeeded | JobStatus::Failed | JobStatus::Cancelled)
+    }
+}
+
+/// A job as returned by POST /jobs and GET /jobs/:id
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JobSummary {
+    pub id: String,
+    pub record_id: u32,
+    pub function: String,
+    pub status: JobStatus,
+    pub cancel_requested: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub started_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>, // Same body /execute/:id would have returned
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// ===== INSTANCE MODELS =====
+
+/// A live instance as returned by POST /data/:id/instances and GET /instances
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InstanceSummary {
+    pub id: String,
+    pub record_id: u32,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_used_at: chrono::DateTime<chrono::Utc>,
+    pub expires_at: chrono::DateTime<chrono::Utc>, // Torn down if unused until then
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json;
 
     #[test]
     fn test_data_entry_serialization() {
@@ -139,6 +585,9 @@ mod tests {
             func_names: vec!["add".to_string(), "mul".to_string()],
             bytecode: vec![1, 2, 3, 4, 5],
             owner: "test_user".to_string(),
+            host_api: None,
+            wasi: false,
+            pure_functions: vec![],
         };
 
         let json = serde_json::to_string(&entry).unwrap();
@@ -152,8 +601,13 @@ mod tests {
     #[test]
     fn test_create_data_request_serialization() {
         let request = CreateDataRequest {
-            func_names: vec!["add".to_string(), "sub".to_string()],
+            func_names: Some(vec!["add".to_string(), "sub".to_string()]),
             bytecode: vec![10, 20, 30, 40, 50],
+            host_api: None,
+            wasi: false,
+            pure_functions: vec![],
+            format: None,
+            source: None,
         };
 
         let json = serde_json::to_string(&request).unwrap();
@@ -163,6 +617,28 @@ mod tests {
         assert_eq!(request.bytecode, deserialized.bytecode);
     }
 
+    #[test]
+    fn test_bulk_request_deserialization() {
+        let json = r#"{
+            "operations": [
+                {"op": "create", "func_names": ["add"], "bytecode": [1, 2]},
+                {"op": "update", "id": 3, "func_names": ["mul"], "bytecode": [3]},
+                {"op": "delete", "id": 4}
+            ]
+        }"#;
+
+        let request: BulkRequest = serde_json::from_str(json).unwrap();
+
+        assert_eq!(request.mode, BulkMode::AllOrNothing);
+        assert_eq!(request.operations.len(), 3);
+        assert!(matches!(&request.operations[0], BulkOperation::Create { data } if data.func_names == Some(vec!["add".to_string()])));
+        assert!(matches!(&request.operations[1], BulkOperation::Update { id: 3, data } if data.bytecode == vec![3]));
+        assert!(matches!(request.operations[2], BulkOperation::Delete { id: 4 }));
+
+        let best_effort: BulkRequest = serde_json::from_str(r#"{"mode": "best_effort", "operations": []}"#).unwrap();
+        assert_eq!(best_effort.mode, BulkMode::BestEffort);
+    }
+
     #[test]
     fn test_login_request_serialization() {
         let request = AuthRequest {
@@ -231,6 +707,12 @@ mod tests {
         let token_info = RefreshTokenInfo {
             username: username.clone(),
             expires_at: chrono::DateTime::from_timestamp(expires_at, 0).unwrap(),
+            family_id: "family_1".to_string(),
+            rotated_at: None,
+            issued_at: chrono::Utc::now(),
+            last_used_at: chrono::Utc::now(),
+            user_agent: None,
+            ip: "127.0.0.1".to_string(),
         };
 
         assert_eq!(token_info.username, username);
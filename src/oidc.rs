@@ -0,0 +1,483 @@
+// OpenID Connect relying party, enabled when OIDC_ISSUER is set.
+//
+// Users sign in through the external identity provider with the
+// authorization-code flow (with PKCE) and receive this server's own tokens.
+// JWTs issued by the provider are also accepted directly as bearer tokens.
+// Both paths verify signatures against the provider's JWKS.
+//
+// External users are mapped onto local usernames taken from
+// OIDC_USERNAME_CLAIM (plus an optional OIDC_USERNAME_PREFIX). The first login
+// binds a username to the provider's subject; members of any group listed in
+// OIDC_ADMIN_GROUPS get the admin role.
+use crate::auth::{authenticated_user_for, get_jwt_leeway_secs, issue_session, session_client};
+use crate::models::{AuthenticatedUser, ExternalUser, OidcCallbackQuery, PendingOidcLogin};
+use crate::state::{AppState, AppStateInner};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, Utc};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::Instant;
+use tide::http::Url;
+use tide::Request;
+use tracing::{info, warn};
+
+// Provider metadata and keys, fetched on first use
+static PROVIDER: Mutex<Option<Provider>> = Mutex::new(None);
+
+// Unknown key ids trigger a JWKS refetch at most this often
+const JWKS_MIN_REFRESH_SECS: u64 = 30;
+
+// How long a started login may take to come back through the callback
+const PENDING_LOGIN_SECS: i64 = 600;
+
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub scopes: String,
+    pub audience: String,
+    pub username_claim: String,
+    pub username_prefix: String,
+    pub groups_claim: String,
+    pub admin_groups: Vec<String>,
+}
+
+impl OidcConfig {
+    // Reads the OIDC_* variables; None when OIDC is not configured
+    pub fn from_env() -> Option<Self> {
+        let issuer = env::var("OIDC_ISSUER").ok().filter(|issuer| !issuer.is_empty())?;
+        let client_id = env::var("OIDC_CLIENT_ID").unwrap_or_default();
+        Some(Self {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            audience: env::var("OIDC_AUDIENCE").unwrap_or_else(|_| client_id.clone()),
+            client_id,
+            client_secret: env::var("OIDC_CLIENT_SECRET").unwrap_or_default(),
+            redirect_uri: env::var("OIDC_REDIRECT_URI").unwrap_or_default(),
+            scopes: env::var("OIDC_SCOPES").unwrap_or_else(|_| "openid profile email groups".to_string()),
+            username_claim: env::var("OIDC_USERNAME_CLAIM").unwrap_or_else(|_| "preferred_username".to_string()),
+            username_prefix: env::var("OIDC_USERNAME_PREFIX").unwrap_or_default(),
+            groups_claim: env::var("OIDC_GROUPS_CLAIM").unwrap_or_else(|_| "groups".to_string()),
+            admin_groups: env::var("OIDC_ADMIN_GROUPS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|group| group.trim().to_string())
+                .filter(|group| !group.is_empty())
+                .collect(),
+        })
+    }
+}
+
+#[derive(Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Clone)]
+struct Provider {
+    metadata: ProviderMetadata,
+    jwks: JwkSet,
+    fetched_at: Instant,
+}
+
+// Claims we read from provider-issued JWTs; everything else stays in `extra`
+#[derive(Debug, Deserialize)]
+pub struct IdpClaims {
+    pub iss: String,
+    pub sub: String,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub jti: Option<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum IdpTokenError {
+    UnknownKey,
+    Expired,
+    Invalid(String),
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+// Starts the authorization-code flow by redirecting to the provider
+pub async fn oidc_login(req: Request<AppState>) -> tide::Result {
+    let config = OidcConfig::from_env().ok_or_else(|| tide::Error::from_str(404, "OIDC login is not configured"))?;
+    let provider = load_provider(&config, false).await?;
+
+    let state = random_token();
+    let nonce = random_token();
+    let code_verifier = random_token();
+    let mut url = Url::parse(&provider.metadata.authorization_endpoint)
+        .map_err(|e| tide::Error::from_str(502, format!("Invalid authorization endpoint: {}", e)))?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", &config.redirect_uri)
+        .append_pair("scope", &config.scopes)
+        .append_pair("state", &state)
+        .append_pair("nonce", &nonce)
+        .append_pair("code_challenge", &pkce_challenge(&code_verifier))
+        .append_pair("code_challenge_method", "S256");
+
+    req.state().lock().unwrap().oidc_logins.insert(state, PendingOidcLogin {
+        nonce,
+        code_verifier,
+        expires_at: Utc::now() + Duration::seconds(PENDING_LOGIN_SECS),
+    });
+    Ok(tide::Redirect::new(url.as_str()).into())
+}
+
+// Completes the flow: exchanges the code, verifies the ID token and issues local tokens
+pub async fn oidc_callback(req: Request<AppState>) -> tide::Result {
+    let start_time = Instant::now();
+    let config = OidcConfig::from_env().ok_or_else(|| tide::Error::from_str(404, "OIDC login is not configured"))?;
+    let query: OidcCallbackQuery = req.query()?;
+    if let Some(error) = query.error {
+        let description = query.error_description.unwrap_or_default();
+        return Err(tide::Error::from_str(401, format!("Identity provider error: {} {}", error, description)));
+    }
+    let (code, state) = query
+        .code
+        .zip(query.state)
+        .ok_or_else(|| tide::Error::from_str(400, "Missing code or state"))?;
+
+    let pending = req
+        .state()
+        .lock()
+        .unwrap()
+        .oidc_logins
+        .remove(&state)
+        .filter(|pending| pending.expires_at > Utc::now())
+        .ok_or_else(|| tide::Error::from_str(400, "Unknown or expired login state"))?;
+
+    let provider = load_provider(&config, false).await?;
+    let token_endpoint = provider.metadata.token_endpoint.clone();
+    let form = [
+        ("grant_type", "authorization_code".to_string()),
+        ("code", code),
+        ("redirect_uri", config.redirect_uri.clone()),
+        ("client_id", config.client_id.clone()),
+        ("client_secret", config.client_secret.clone()),
+        ("code_verifier", pending.code_verifier),
+    ];
+    let tokens: TokenResponse = async_std::task::spawn_blocking(move || {
+        let form: Vec<(&str, &str)> = form.iter().map(|(name, value)| (*name, value.as_str())).collect();
+        ureq::post(&token_endpoint)
+            .send_form(&form)
+            .map_err(|e| format!("Token exchange failed: {}", e))?
+            .into_json::<TokenResponse>()
+            .map_err(|e| format!("Invalid token response: {}", e))
+    })
+    .await
+    .map_err(|e| tide::Error::from_str(502, e))?;
+
+    let claims = verify_with_refresh(&config, &tokens.id_token, Some(&pending.nonce)).await?;
+    let client = session_client(&req);
+    let mut app_state = req.state().lock().unwrap();
+    let username = link_external_user(&mut app_state, &config, &claims)?;
+    let response = issue_session(&mut app_state, &username, &client)?;
+
+    let execution_time = start_time.elapsed();
+    info!(user = %username, subject = %claims.sub, execution_time_ms = execution_time.as_millis(), "OIDC login completed successfully");
+    Ok(tide::Body::from_json(&response)?.into())
+}
+
+// True if `token` is a JWT whose (unverified) issuer is the configured provider
+pub fn is_idp_token(token: &str) -> bool {
+    let Some(config) = OidcConfig::from_env() else {
+        return false;
+    };
+    token
+        .split('.')
+        .nth(1)
+        .and_then(|payload| URL_SAFE_NO_PAD.decode(payload).ok())
+        .and_then(|payload| serde_json::from_slice::<serde_json::Value>(&payload).ok())
+        .and_then(|claims| claims.get("iss").and_then(|iss| iss.as_str()).map(|iss| iss.trim_end_matches('/') == config.issuer))
+        .unwrap_or(false)
+}
+
+// Authenticates a bearer token issued by the provider
+pub async fn authenticate_idp_token(state: &AppState, token: &str) -> Result<AuthenticatedUser, tide::Error> {
+    let config = OidcConfig::from_env().ok_or_else(|| tide::Error::from_str(401, "OIDC is not configured"))?;
+    let claims = verify_with_refresh(&config, token, None).await?;
+    let mut app_state = state.lock().unwrap();
+    let username = link_external_user(&mut app_state, &config, &claims)?;
+    let token_id = claims.jti.clone().unwrap_or_else(|| format!("idp:{}", claims.sub));
+    Ok(authenticated_user_for(&app_state, username, token_id))
+}
+
+// Verifies a provider JWT, refetching the JWKS once if its key id is unknown
+async fn verify_with_refresh(config: &OidcConfig, token: &str, nonce: Option<&str>) -> Result<IdpClaims, tide::Error> {
+    let provider = load_provider(config, false).await?;
+    let result = match verify_idp_token(token, &provider.jwks, config, nonce) {
+        Err(IdpTokenError::UnknownKey) => verify_idp_token(token, &load_provider(config, true).await?.jwks, config, nonce),
+        result => result,
+    };
+    result.map_err(|e| {
+        warn!(error = ?e, "Rejected identity provider token");
+        let description = match e {
+            IdpTokenError::UnknownKey => "Unknown signing key".to_string(),
+            IdpTokenError::Expired => "Token expired".to_string(),
+            IdpTokenError::Invalid(reason) => reason,
+        };
+        crate::auth::bearer_error(401, Some("invalid_token"), description)
+    })
+}
+
+// Checks signature, issuer, audience, expiry and (for ID tokens) the nonce
+pub fn verify_idp_token(token: &str, jwks: &JwkSet, config: &OidcConfig, nonce: Option<&str>) -> Result<IdpClaims, IdpTokenError> {
+    let header = decode_header(token).map_err(|e| IdpTokenError::Invalid(format!("Malformed token: {}", e)))?;
+    // Provider tokens must be signed with a published key, never a shared secret
+    if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+        return Err(IdpTokenError::Invalid("Symmetric algorithms are not accepted".to_string()));
+    }
+    let jwk = match header.kid.as_deref() {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or(IdpTokenError::UnknownKey)?;
+    let key = DecodingKey::from_jwk(jwk).map_err(|e| IdpTokenError::Invalid(format!("Unusable key: {}", e)))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&config.issuer, &format!("{}/", config.issuer)]);
+    validation.set_audience(&[&config.audience]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+    validation.leeway = get_jwt_leeway_secs();
+
+    let claims = decode::<IdpClaims>(token, &key, &validation)
+        .map_err(|e| match e.kind() {
+            jsonwebtoken::errors::ErrorKind::ExpiredSignature => IdpTokenError::Expired,
+            _ => IdpTokenError::Invalid(format!("Invalid token: {}", e)),
+        })?
+        .claims;
+
+    if let Some(expected) = nonce {
+        if claims.nonce.as_deref() != Some(expected) {
+            return Err(IdpTokenError::Invalid("Nonce mismatch".to_string()));
+        }
+    }
+    Ok(claims)
+}
+
+// Maps provider claims onto a local username, recording subject and groups
+pub fn link_external_user(app_state: &mut AppStateInner, config: &OidcConfig, claims: &IdpClaims) -> Result<String, tide::Error> {
+    let name = claims
+        .extra
+        .get(&config.username_claim)
+        .and_then(|value| value.as_str())
+        .unwrap_or(&claims.sub);
+    let username = format!("{}{}", config.username_prefix, name);
+
+    // A provider identity must never sign in as a local password account
+    if app_state.users.contains_key(&username) {
+        warn!(user = %username, subject = %claims.sub, "OIDC username collides with a local account");
+        return Err(tide::Error::from_str(403, "Username belongs to a local account"));
+    }
+    if let Some(existing) = app_state.external_users.get(&username) {
+        if existing.issuer != claims.iss || existing.subject != claims.sub {
+            warn!(user = %username, subject = %claims.sub, "OIDC subject does not match the account it maps to");
+            return Err(tide::Error::from_str(403, "Account is linked to a different external identity"));
+        }
+    }
+
+    let groups: Vec<String> = match claims.extra.get(&config.groups_claim) {
+        Some(serde_json::Value::Array(values)) => values.iter().filter_map(|value| value.as_str().map(str::to_string)).collect(),
+        Some(serde_json::Value::String(group)) => vec![group.clone()],
+        _ => Vec::new(),
+    };
+    let admin = groups.iter().any(|group| config.admin_groups.contains(group));
+    app_state.external_users.insert(username.clone(), ExternalUser {
+        issuer: claims.iss.clone(),
+        subject: claims.sub.clone(),
+        groups,
+        admin,
+        last_login_at: Utc::now(),
+    });
+    Ok(username)
+}
+
+// Forgets logins that were started but never completed
+pub fn purge_expired_oidc_logins(app_state: &mut AppStateInner, now: chrono::DateTime<Utc>) -> usize {
+    let before = app_state.oidc_logins.len();
+    app_state.oidc_logins.retain(|_, pending| pending.expires_at > now);
+    before - app_state.oidc_logins.len()
+}
+
+// Returns the cached provider, fetching discovery and JWKS when missing or
+// when `refresh_jwks` asks for fresh keys
+async fn load_provider(config: &OidcConfig, refresh_jwks: bool) -> Result<Provider, tide::Error> {
+    let cached = PROVIDER.lock().unwrap().clone();
+    if let Some(provider) = cached {
+        if !refresh_jwks || provider.fetched_at.elapsed().as_secs() < JWKS_MIN_REFRESH_SECS {
+            return Ok(provider);
+        }
+    }
+
+    let issuer = config.issuer.clone();
+    let provider = async_std::task::spawn_blocking(move || -> Result<Provider, String> {
+        let metadata: ProviderMetadata = fetch_json(&format!("{}/.well-known/openid-configuration", issuer))?;
+        if metadata.issuer.trim_end_matches('/') != issuer {
+            return Err(format!("Discovery document is for issuer '{}'", metadata.issuer));
+        }
+        let jwks: JwkSet = fetch_json(&metadata.jwks_uri)?;
+        Ok(Provider { metadata, jwks, fetched_at: Instant::now() })
+    })
+    .await
+    .map_err(|e| tide::Error::from_str(502, format!("Identity provider unavailable: {}", e)))?;
+
+    info!(issuer = %config.issuer, keys = provider.jwks.keys.len(), "Identity provider metadata loaded");
+    *PROVIDER.lock().unwrap() = Some(provider.clone());
+    Ok(provider)
+}
+
+fn fetch_json<T: DeserializeOwned>(url: &str) -> Result<T, String> {
+    ureq::get(url)
+        .call()
+        .map_err(|e| format!("GET {} failed: {}", url, e))?
+        .into_json()
+        .map_err(|e| format!("Invalid JSON from {}: {}", url, e))
+}
+
+fn random_token() -> String {
+    URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+// RFC 7636 S256 code challenge
+fn pkce_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::SigningKeys;
+    use crate::state::new_state;
+    use jsonwebtoken::encode;
+    use serde_json::json;
+
+    const RSA_PRIVATE: &str = include_str!("../tests/fixtures/keys/rsa_private.pem");
+    const ISSUER: &str = "https://idp.example.com";
+
+    fn config() -> OidcConfig {
+        OidcConfig {
+            issuer: ISSUER.to_string(),
+            client_id: "crud".to_string(),
+            client_secret: "secret".to_string(),
+            redirect_uri: "http://127.0.0.1:8080/auth/oidc/callback".to_string(),
+            scopes: "openid".to_string(),
+            audience: "crud".to_string(),
+            username_claim: "preferred_username".to_string(),
+            username_prefix: String::new(),
+            groups_claim: "groups".to_string(),
+            admin_groups: vec!["crud-admins".to_string()],
+        }
+    }
+
+    fn idp_keys() -> SigningKeys {
+        SigningKeys::from_key_pair(Algorithm::RS256, "mock-idp", RSA_PRIVATE.as_bytes(), &[]).unwrap()
+    }
+
+    fn sign(keys: &SigningKeys, claims: serde_json::Value) -> String {
+        encode(&keys.header(), &claims, keys.encoding_key()).unwrap()
+    }
+
+    fn claims(extra: serde_json::Value) -> serde_json::Value {
+        let mut claims = json!({
+            "iss": ISSUER,
+            "sub": "subject-1",
+            "aud": "crud",
+            "exp": Utc::now().timestamp() + 300,
+            "nonce": "n-1",
+            "preferred_username": "alice",
+            "groups": ["staff", "crud-admins"],
+        });
+        claims.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        claims
+    }
+
+    #[test]
+    fn test_verify_idp_token() {
+        let keys = idp_keys();
+        let token = sign(&keys, claims(json!({})));
+        let verified = verify_idp_token(&token, keys.jwks(), &config(), Some("n-1")).unwrap();
+        assert_eq!(verified.sub, "subject-1");
+        assert_eq!(verified.extra["preferred_username"], "alice");
+    }
+
+    #[test]
+    fn test_verify_idp_token_rejections() {
+        let keys = idp_keys();
+        let check = |claims: serde_json::Value, nonce: Option<&str>| verify_idp_token(&sign(&keys, claims), keys.jwks(), &config(), nonce);
+
+        assert!(matches!(check(claims(json!({})), Some("other")), Err(IdpTokenError::Invalid(_))));
+        assert!(matches!(check(claims(json!({ "aud": "someone-else" })), None), Err(IdpTokenError::Invalid(_))));
+        assert!(matches!(check(claims(json!({ "iss": "https://evil.example.com" })), None), Err(IdpTokenError::Invalid(_))));
+        assert_eq!(check(claims(json!({ "exp": Utc::now().timestamp() - 3600 })), None).unwrap_err(), IdpTokenError::Expired);
+
+        // Keys the provider does not publish are unknown, even if validly signed
+        let other = SigningKeys::from_key_pair(Algorithm::RS256, "rotated", RSA_PRIVATE.as_bytes(), &[]).unwrap();
+        let token = sign(&other, claims(json!({})));
+        assert_eq!(verify_idp_token(&token, keys.jwks(), &config(), None).unwrap_err(), IdpTokenError::UnknownKey);
+
+        // HMAC tokens are refused outright
+        let hmac = SigningKeys::from_secret(b"shared");
+        let token = sign(&hmac, claims(json!({})));
+        assert!(matches!(verify_idp_token(&token, keys.jwks(), &config(), None), Err(IdpTokenError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_link_external_user_maps_groups_and_binds_subject() {
+        let keys = idp_keys();
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        let verified = verify_idp_token(&sign(&keys, claims(json!({}))), keys.jwks(), &config(), None).unwrap();
+
+        let username = link_external_user(&mut app_state, &config(), &verified).unwrap();
+        assert_eq!(username, "alice");
+        assert!(authenticated_user_for(&app_state, username, "t".to_string()).has_role(crate::auth::ROLE_ADMIN));
+
+        // A different subject claiming the same username is refused
+        let impostor = verify_idp_token(&sign(&keys, claims(json!({ "sub": "subject-2" }))), keys.jwks(), &config(), None).unwrap();
+        assert_eq!(link_external_user(&mut app_state, &config(), &impostor).unwrap_err().status(), 403);
+    }
+
+    #[test]
+    fn test_link_external_user_refuses_local_accounts() {
+        let keys = idp_keys();
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        let admin = verify_idp_token(&sign(&keys, claims(json!({ "preferred_username": "admin" }))), keys.jwks(), &config(), None).unwrap();
+        assert_eq!(link_external_user(&mut app_state, &config(), &admin).unwrap_err().status(), 403);
+        assert!(!app_state.external_users.contains_key("admin"));
+
+        // A prefix keeps provider names apart from local ones
+        let prefixed = OidcConfig { username_prefix: "sso:".to_string(), ..config() };
+        assert_eq!(link_external_user(&mut app_state, &prefixed, &admin).unwrap(), "sso:admin");
+    }
+
+    #[test]
+    fn test_pkce_challenge() {
+        // Example from RFC 7636 appendix B
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+}
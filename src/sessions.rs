@@ -0,0 +1,109 @@
+// Lets users see where they are signed in and end individual sessions.
+//
+// A session is one refresh token family: it starts at a login and lives on
+// through every refresh. Only the family's newest refresh token is live; older
+// ones are kept just for reuse detection.
+use crate::auth::{authenticated_user, revoke_session};
+use crate::models::SessionSummary;
+use crate::state::{AppState, AppStateInner};
+use chrono::Utc;
+use tide::Request;
+use tracing::info;
+
+// Lists the caller's live sessions, most recently used first
+pub async fn list_sessions(req: Request<AppState>) -> tide::Result {
+    let user = authenticated_user(&req)?;
+    let app_state = req.state().read().unwrap();
+    let current = current_session_id(&app_state, &user.token_id);
+    let mut sessions = user_sessions(&app_state, &user.username, current);
+    sessions.sort_by_key(|session| std::cmp::Reverse(session.last_used_at));
+    Ok(tide::Body::from_json(&sessions)?.into())
+}
+
+// Ends one of the caller's sessions, including its access tokens
+pub async fn delete_session(req: Request<AppState>) -> tide::Result {
+    let username = authenticated_user(&req)?.username;
+    let id = req.param("id")?.to_string();
+    let mut app_state = req.state().lock().unwrap();
+    // Other users' sessions look the same as unknown ones
+    let owned = app_state
+        .refresh_tokens
+        .values()
+        .any(|info| info.family_id == id && info.username == username);
+    if !owned {
+        return Ok(tide::Response::new(404));
+    }
+    let revoked = revoke_session(&mut app_state, &id);
+    info!(user = %username, session_id = %id, revoked_tokens = revoked, "Session revoked");
+    Ok(tide::Response::new(204))
+}
+
+// The session an access token was issued for
+fn current_session_id<'a>(app_state: &'a AppStateInner, token_id: &str) -> Option<&'a str> {
+    app_state
+        .issued_access_tokens
+        .get(token_id)
+        .map(|issued| issued.session_id.as_str())
+}
+
+fn user_sessions(app_state: &AppStateInner, username: &str, current: Option<&str>) -> Vec<SessionSummary> {
+    let now = Utc::now();
+    app_state
+        .refresh_tokens
+        .values()
+        .filter(|info| info.username == username && info.rotated_at.is_none() && info.expires_at > now)
+        .map(|info| SessionSummary {
+            id: info.family_id.clone(),
+            current: current == Some(info.family_id.as_str()),
+            issued_at: info.issued_at,
+            last_used_at: info.last_used_at,
+            expires_at: info.expires_at,
+            user_agent: info.user_agent.clone(),
+            ip: info.ip.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::issue_session;
+    use crate::models::SessionClient;
+    use crate::state::new_state;
+
+    #[test]
+    fn test_user_sessions_lists_live_families() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        let laptop = SessionClient { user_agent: Some("laptop".to_string()), ip: "10.0.0.1".to_string() };
+        let phone = SessionClient { user_agent: Some("phone".to_string()), ip: "10.0.0.2".to_string() };
+        issue_session(&mut app_state, "user1", &laptop).unwrap();
+        issue_session(&mut app_state, "user1", &phone).unwrap();
+        issue_session(&mut app_state, "user2", &phone).unwrap();
+
+        let phone_session = app_state
+            .refresh_tokens
+            .values()
+            .find(|info| info.username == "user1" && info.user_agent.as_deref() == Some("phone"))
+            .unwrap()
+            .family_id
+            .clone();
+        let sessions = user_sessions(&app_state, "user1", Some(&phone_session));
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.iter().all(|session| session.current == (session.id == phone_session)));
+        assert!(sessions.iter().any(|session| session.ip == "10.0.0.1"));
+
+        revoke_session(&mut app_state, &phone_session);
+        assert_eq!(user_sessions(&app_state, "user1", None).len(), 1);
+    }
+
+    #[test]
+    fn test_current_session_id() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        issue_session(&mut app_state, "user1", &SessionClient::default()).unwrap();
+        let (jti, issued) = app_state.issued_access_tokens.iter().next().unwrap();
+        assert_eq!(current_session_id(&app_state, jti), Some(issued.session_id.as_str()));
+        assert_eq!(current_session_id(&app_state, "unknown"), None);
+    }
+}
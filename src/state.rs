@@ -1,16 +1,20 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, LockResult, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::sync::atomic::AtomicU64;
 use std::time::{Duration, Instant};
 
 // Import the data model we defined
-use crate::models::{DataEntry, RefreshTokenInfo};
+use crate::instances::Session;
+use crate::jobs::Job;
+use crate::memo::ResultCache;
+use crate::models::{ApiKey, DataEntry, ExternalUser, FailedLogins, IssuedAccessToken, MfaEnrollment, PendingOidcLogin, RefreshTokenInfo, TrashedEntry};
+use crate::wasm::features::FeatureSet;
 
 pub struct Metrics {
     pub total_executions: AtomicU64,
     pub successful_executions: AtomicU64,
     pub failed_executions: AtomicU64,
-    pub function_counts: HashMap<String, AtomicU64>,
+    pub function_counts: Mutex<HashMap<String, u64>>,
 }
 
 impl Default for Metrics {
@@ -19,7 +23,7 @@ impl Default for Metrics {
             total_executions: AtomicU64::new(0),
             successful_executions: AtomicU64::new(0),
             failed_executions: AtomicU64::new(0),
-            function_counts: HashMap::new(),
+            function_counts: Mutex::new(HashMap::new()),
         }
     }
 }
@@ -44,19 +48,97 @@ impl Default for RateLimiter {
     }
 }
 
-// AppState is the global state of the application.
-// We use Arc<Mutex<...>> to allow safe access between multiple requests.
-pub type AppState = Arc<Mutex<AppStateInner>>;
+// Failed login counters for brute-force protection (see crate::lockout)
+#[derive(Default)]
+pub struct LoginThrottle {
+    pub by_username: HashMap<String, FailedLogins>,
+    pub by_ip: HashMap<String, FailedLogins>,
+}
+
+// Module bytes by record id, shared with running executions. It has its own
+// lock so that preparing an execution only needs to read the state.
+#[derive(Default)]
+pub struct ModuleCache(RwLock<HashMap<u32, Arc<[u8]>>>);
+
+impl ModuleCache {
+    pub fn get(&self, id: u32) -> Option<Arc<[u8]>> {
+        self.0.read().unwrap().get(&id).cloned()
+    }
+
+    pub fn insert(&self, id: u32, bytes: Arc<[u8]>) {
+        self.0.write().unwrap().insert(id, bytes);
+    }
+
+    pub fn remove(&self, id: &u32) {
+        self.0.write().unwrap().remove(id);
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.0.read().unwrap().is_empty()
+    }
+}
+
+// AppState is the global state of the application, shared by every request.
+// Records, users and tokens sit behind one RwLock: `lock()` takes it for
+// writing and `read()` lets lookups (authentication, reads, preparing an
+// execution) run side by side. Metrics are kept outside it, and modules run
+// after the lock is released.
+pub type AppState = Arc<SharedState>;
+
+pub struct SharedState {
+    inner: RwLock<AppStateInner>,
+    pub metrics: Metrics,
+}
+
+impl SharedState {
+    pub fn new(inner: AppStateInner) -> Self {
+        Self { inner: RwLock::new(inner), metrics: Metrics::default() }
+    }
+
+    // Exclusive access, for handlers that change the state
+    pub fn lock(&self) -> LockResult<RwLockWriteGuard<'_, AppStateInner>> {
+        self.inner.write()
+    }
+
+    // Shared access, for handlers that only look
+    pub fn read(&self) -> LockResult<RwLockReadGuard<'_, AppStateInner>> {
+        self.inner.read()
+    }
+}
 
 pub struct AppStateInner {
     pub data: HashMap<u32, DataEntry>,
+    pub trash: HashMap<u32, TrashedEntry>, // Soft-deleted records, keyed by their original id
+    pub next_id: u32, // Next record id; never reused, even after a purge
     pub users: HashMap<String, String>, // username -> password (in production, use hash)
+    pub disabled_users: HashSet<String>, // Users who may not sign in
     pub refresh_tokens: HashMap<String, RefreshTokenInfo>, // refresh_token -> info
-    pub wasm_cache: HashMap<u32, Vec<u8>>, // Cache for compiled WASM modules
-    pub metrics: Metrics,
+    pub issued_access_tokens: HashMap<String, IssuedAccessToken>, // jti -> owner and expiry
+    pub revoked_access_tokens: HashMap<String, chrono::DateTime<chrono::Utc>>, // jti -> expiry (kept until then)
+    pub api_keys: HashMap<String, ApiKey>, // key id -> key
+    pub login_throttle: LoginThrottle,
+    pub oidc_logins: HashMap<String, PendingOidcLogin>, // OIDC `state` -> pending login
+    pub external_users: HashMap<String, ExternalUser>, // local username -> linked OIDC identity
+    pub mfa: HashMap<String, MfaEnrollment>, // username -> TOTP second factor
+    pub wasm_features: HashMap<String, FeatureSet>, // username -> features their modules may use (see crate::wasm::features)
+    pub wasm_cache: ModuleCache, // Module bytes shared with running executions
+    pub results: Arc<ResultCache>, // Memoized results of pure functions (see crate::memo)
+    pub jobs: HashMap<String, Job>, // job id -> asynchronous execution (see crate::jobs)
+    pub job_queue: VecDeque<String>, // Queued job ids, oldest first
+    pub instances: HashMap<String, Arc<Session>>, // instance id -> live instance (see crate::instances)
     pub rate_limiter: RateLimiter,
 }
 
+impl AppStateInner {
+    // Hands out the next record id
+    pub fn allocate_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+}
+
 // Creates a new empty state
 pub fn new_state() -> AppState {
     let mut users = HashMap::new();
@@ -65,12 +147,26 @@ pub fn new_state() -> AppState {
     users.insert("user1".to_string(), "password123".to_string());
     users.insert("user2".to_string(), "password456".to_string());
 
-    Arc::new(Mutex::new(AppStateInner {
+    Arc::new(SharedState::new(AppStateInner {
         data: HashMap::new(),
+        trash: HashMap::new(),
+        next_id: 1,
         users,
+        disabled_users: HashSet::new(),
         refresh_tokens: HashMap::new(),
-        wasm_cache: HashMap::new(),
-        metrics: Metrics::default(),
+        issued_access_tokens: HashMap::new(),
+        revoked_access_tokens: HashMap::new(),
+        api_keys: HashMap::new(),
+        login_throttle: LoginThrottle::default(),
+        oidc_logins: HashMap::new(),
+        external_users: HashMap::new(),
+        mfa: HashMap::new(),
+        wasm_features: HashMap::new(),
+        wasm_cache: ModuleCache::default(),
+        results: Arc::default(),
+        jobs: HashMap::new(),
+        job_queue: VecDeque::new(),
+        instances: HashMap::new(),
         rate_limiter: RateLimiter::default(),
     }))
 }
@@ -95,14 +191,28 @@ mod tests {
         
         // Test empty collections
         assert!(state_guard.data.is_empty());
+        assert!(state_guard.trash.is_empty());
+        assert_eq!(state_guard.next_id, 1);
+        assert!(state_guard.disabled_users.is_empty());
         assert!(state_guard.refresh_tokens.is_empty());
+        assert!(state_guard.issued_access_tokens.is_empty());
+        assert!(state_guard.revoked_access_tokens.is_empty());
+        assert!(state_guard.api_keys.is_empty());
+        assert!(state_guard.login_throttle.by_username.is_empty());
+        assert!(state_guard.login_throttle.by_ip.is_empty());
+        assert!(state_guard.oidc_logins.is_empty());
+        assert!(state_guard.external_users.is_empty());
+        assert!(state_guard.mfa.is_empty());
         assert!(state_guard.wasm_cache.is_empty());
+        assert!(state_guard.jobs.is_empty());
+        assert!(state_guard.job_queue.is_empty());
+        assert!(state_guard.instances.is_empty());
         
         // Test metrics initialization
-        assert_eq!(state_guard.metrics.total_executions.load(std::sync::atomic::Ordering::Relaxed), 0);
-        assert_eq!(state_guard.metrics.successful_executions.load(std::sync::atomic::Ordering::Relaxed), 0);
-        assert_eq!(state_guard.metrics.failed_executions.load(std::sync::atomic::Ordering::Relaxed), 0);
-        assert!(state_guard.metrics.function_counts.is_empty());
+        assert_eq!(state.metrics.total_executions.load(std::sync::atomic::Ordering::Relaxed), 0);
+        assert_eq!(state.metrics.successful_executions.load(std::sync::atomic::Ordering::Relaxed), 0);
+        assert_eq!(state.metrics.failed_executions.load(std::sync::atomic::Ordering::Relaxed), 0);
+        assert!(state.metrics.function_counts.lock().unwrap().is_empty());
         
         // Test rate limiter initialization
         assert_eq!(state_guard.rate_limiter.max_requests, 100);
@@ -117,7 +227,7 @@ mod tests {
         assert_eq!(metrics.total_executions.load(std::sync::atomic::Ordering::Relaxed), 0);
         assert_eq!(metrics.successful_executions.load(std::sync::atomic::Ordering::Relaxed), 0);
         assert_eq!(metrics.failed_executions.load(std::sync::atomic::Ordering::Relaxed), 0);
-        assert!(metrics.function_counts.is_empty());
+        assert!(metrics.function_counts.lock().unwrap().is_empty());
     }
 
     #[test]
@@ -140,6 +250,9 @@ mod tests {
                 func_names: vec!["add".to_string(), "mul".to_string()],
                 bytecode: vec![1, 2, 3, 4, 5],
                 owner: "test_user".to_string(),
+                host_api: None,
+                wasi: false,
+                pure_functions: vec![],
             };
             state_guard.data.insert(1, entry);
             assert_eq!(state_guard.data.len(), 1);
@@ -162,6 +275,9 @@ mod tests {
                 func_names: vec!["add".to_string(), "sub".to_string()],
                 bytecode: vec![10, 20, 30],
                 owner: "test_user".to_string(),
+                host_api: None,
+                wasi: false,
+                pure_functions: vec![],
             };
             state_guard.data.insert(1, updated_entry);
             assert_eq!(state_guard.data.len(), 1);
@@ -179,38 +295,51 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_allocate_id_is_monotonic() {
+        let state = new_state();
+        let mut state_guard = state.lock().unwrap();
+
+        assert_eq!(state_guard.allocate_id(), 1);
+        assert_eq!(state_guard.allocate_id(), 2);
+
+        // Removing records must not make ids available again
+        state_guard.data.clear();
+        assert_eq!(state_guard.allocate_id(), 3);
+    }
+
     #[test]
     fn test_wasm_cache_operations() {
         let state = new_state();
         
-        // Test cache insertion
+        // Test cache insertion; a read guard is enough
         {
-            let mut state_guard = state.lock().unwrap();
-            state_guard.wasm_cache.insert(1, vec![1, 2, 3, 4, 5]);
-            assert!(state_guard.wasm_cache.contains_key(&1));
-            assert_eq!(state_guard.wasm_cache.get(&1).unwrap().len(), 5);
+            let state_guard = state.read().unwrap();
+            state_guard.wasm_cache.insert(1, Arc::from(vec![1, 2, 3, 4, 5]));
+            assert_eq!(state_guard.wasm_cache.get(1).unwrap().len(), 5);
         }
         
         // Test cache retrieval
         {
-            let state_guard = state.lock().unwrap();
-            let cached = state_guard.wasm_cache.get(&1).unwrap();
-            assert_eq!(cached, &vec![1, 2, 3, 4, 5]);
+            let state_guard = state.read().unwrap();
+            let cached = state_guard.wasm_cache.get(1).unwrap();
+            assert_eq!(&cached[..], &[1, 2, 3, 4, 5]);
         }
         
         // Test cache update
         {
-            let mut state_guard = state.lock().unwrap();
-            state_guard.wasm_cache.insert(1, vec![10, 20, 30]);
-            let cached = state_guard.wasm_cache.get(&1).unwrap();
-            assert_eq!(cached, &vec![10, 20, 30]);
+            let state_guard = state.lock().unwrap();
+            state_guard.wasm_cache.insert(1, Arc::from(vec![10, 20, 30]));
+            let cached = state_guard.wasm_cache.get(1).unwrap();
+            assert_eq!(&cached[..], &[10, 20, 30]);
         }
         
         // Test cache removal
         {
-            let mut state_guard = state.lock().unwrap();
+            let state_guard = state.lock().unwrap();
             state_guard.wasm_cache.remove(&1);
-            assert!(!state_guard.wasm_cache.contains_key(&1));
+            assert!(state_guard.wasm_cache.get(1).is_none());
+            assert!(state_guard.wasm_cache.is_empty());
         }
     }
 
@@ -218,27 +347,25 @@ mod tests {
     fn test_metrics_operations() {
         let state = new_state();
         
-        // Test incrementing metrics
-        {
-            let mut state_guard = state.lock().unwrap();
-            state_guard.metrics.total_executions.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-            state_guard.metrics.successful_executions.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-            state_guard.metrics.function_counts
-                .entry("add".to_string())
-                .or_insert_with(|| std::sync::atomic::AtomicU64::new(0))
-                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        }
+        // Test incrementing metrics; no state lock is needed
+        state.metrics.total_executions.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        state.metrics.successful_executions.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        *state.metrics.function_counts.lock().unwrap().entry("add".to_string()).or_insert(0) += 1;
         
         // Test reading metrics
-        {
-            let state_guard = state.lock().unwrap();
-            assert_eq!(state_guard.metrics.total_executions.load(std::sync::atomic::Ordering::Relaxed), 1);
-            assert_eq!(state_guard.metrics.successful_executions.load(std::sync::atomic::Ordering::Relaxed), 1);
-            assert_eq!(state_guard.metrics.failed_executions.load(std::sync::atomic::Ordering::Relaxed), 0);
-            
-            let add_count = state_guard.metrics.function_counts.get("add").unwrap();
-            assert_eq!(add_count.load(std::sync::atomic::Ordering::Relaxed), 1);
-        }
+        assert_eq!(state.metrics.total_executions.load(std::sync::atomic::Ordering::Relaxed), 1);
+        assert_eq!(state.metrics.successful_executions.load(std::sync::atomic::Ordering::Relaxed), 1);
+        assert_eq!(state.metrics.failed_executions.load(std::sync::atomic::Ordering::Relaxed), 0);
+        assert_eq!(state.metrics.function_counts.lock().unwrap().get("add"), Some(&1));
+    }
+
+    #[test]
+    fn test_read_guards_are_shared() {
+        let state = new_state();
+        let first = state.read().unwrap();
+        let second = state.read().unwrap();
+        assert_eq!(first.next_id, second.next_id);
+        assert!(state.inner.try_write().is_err(), "writers wait for readers");
     }
 
     #[test]
@@ -251,6 +378,12 @@ mod tests {
             let token_info = RefreshTokenInfo {
                 username: "test_user".to_string(),
                 expires_at: chrono::Utc::now() + chrono::Duration::days(30),
+                family_id: "family_1".to_string(),
+                rotated_at: None,
+                issued_at: chrono::Utc::now(),
+                last_used_at: chrono::Utc::now(),
+                user_agent: None,
+                ip: "127.0.0.1".to_string(),
             };
             state_guard.refresh_tokens.insert("test_token".to_string(), token_info);
             assert!(state_guard.refresh_tokens.contains_key("test_token"));
@@ -275,7 +408,6 @@ mod tests {
     #[test]
     fn test_concurrent_access() {
         use std::thread;
-        use std::sync::Arc;
         
         let state = new_state();
         let state_clone = state.clone();
@@ -289,6 +421,9 @@ mod tests {
                     func_names: vec![format!("func_{}", i)],
                     bytecode: vec![i as u8],
                     owner: format!("user_{}", i),
+                    host_api: None,
+                    wasi: false,
+                    pure_functions: vec![],
                 };
                 state_guard.data.insert(i, entry);
             })
@@ -0,0 +1,150 @@
+// Passing bytes to and from modules through linear memory.
+//
+// A module that takes a payload exports its memory as `memory` and
+//   alloc(len: i32) -> i32          reserves `len` bytes and returns their address
+//   dealloc(ptr: i32, len: i32)     releases a block returned by alloc
+// and the called function has the signature (ptr: i32, len: i32) -> i64. The
+// host copies the payload into a block from `alloc`, calls the function and
+// reads its output from the returned value: the address in the high 32 bits,
+// the length in the low 32 bits. The output must also come from `alloc`; the
+// host hands both blocks back to `dealloc`, so the function must not free its
+// input.
+use std::sync::atomic::AtomicBool;
+use wasmi::{AsContextMut, Func, Instance, Memory, Store, Val};
+
+// Largest output read back from a module
+pub const MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+
+// The exports needed to call one function with a payload
+pub struct ByteCall {
+    memory: Memory,
+    alloc: Func,
+    dealloc: Func,
+    func: Func,
+}
+
+impl ByteCall {
+    // Checks that the instance implements the ABI for `func`
+    pub fn new(mut store: impl AsContextMut, instance: &Instance, func: Func) -> Result<Self, wasmi::Error> {
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| wasmi::Error::new("module does not export its memory as \"memory\""))?;
+        let alloc = instance
+            .get_func(&store, "alloc")
+            .filter(|alloc| alloc.typed::<i32, i32>(&store).is_ok())
+            .ok_or_else(|| wasmi::Error::new("module must export alloc(len: i32) -> i32"))?;
+        let dealloc = instance
+            .get_func(&store, "dealloc")
+            .filter(|dealloc| dealloc.typed::<(i32, i32), ()>(&store).is_ok())
+            .ok_or_else(|| wasmi::Error::new("module must export dealloc(ptr: i32, len: i32)"))?;
+        func.typed::<(i32, i32), i64>(&mut store)
+            .map_err(|e| wasmi::Error::new(format!("payload functions take (ptr: i32, len: i32) -> i64: {e}")))?;
+        Ok(Self { memory, alloc, dealloc, func })
+    }
+
+    // Runs the function on `input` and returns its output; `cancel` works as
+    // for `wasm::call`
+    pub fn call<T>(&self, store: &mut Store<T>, input: &[u8], cancel: Option<&AtomicBool>) -> Result<Vec<u8>, wasmi::Error> {
+        let input_len = i32::try_from(input.len()).map_err(|_| wasmi::Error::new("payload too large"))?;
+        let mut ptr = [Val::I32(0)];
+        super::call(store, self.alloc, &[Val::I32(input_len)], &mut ptr, cancel)?;
+        let input_ptr = ptr[0].i32().unwrap_or_default();
+        self.memory
+            .write(&mut *store, input_ptr as u32 as usize, input)
+            .map_err(|_| wasmi::Error::new("alloc returned a block outside memory"))?;
+
+        let mut packed = [Val::I64(0)];
+        super::call(store, self.func, &[Val::I32(input_ptr), Val::I32(input_len)], &mut packed, cancel)?;
+        let (output_ptr, output_len) = unpack(packed[0].i64().unwrap_or_default());
+        if output_len as usize > MAX_OUTPUT_BYTES {
+            return Err(wasmi::Error::new(format!(
+                "output of {} bytes exceeds the {} byte limit",
+                output_len, MAX_OUTPUT_BYTES
+            )));
+        }
+        let mut output = vec![0; output_len as usize];
+        self.memory
+            .read(&*store, output_ptr as usize, &mut output)
+            .map_err(|_| wasmi::Error::new("output points outside memory"))?;
+
+        super::call(store, self.dealloc, &[Val::I32(input_ptr), Val::I32(input_len)], &mut [], cancel)?;
+        if output_len > 0 {
+            super::call(store, self.dealloc, &[Val::I32(output_ptr as i32), Val::I32(output_len as i32)], &mut [], cancel)?;
+        }
+        Ok(output)
+    }
+}
+
+// Splits a returned i64 into the output address and length
+fn unpack(value: i64) -> (u32, u32) {
+    let value = value as u64;
+    ((value >> 32) as u32, value as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmi::{Engine, Linker, Module, Store};
+
+    // Bump allocator that counts dealloc calls in global 1
+    const ECHO_MODULE: &str = r#"(module
+        (memory (export "memory") 1)
+        (global $next (mut i32) (i32.const 1024))
+        (global $freed (export "freed") (mut i32) (i32.const 0))
+        (func $alloc (export "alloc") (param $len i32) (result i32)
+            (global.get $next)
+            (global.set $next (i32.add (global.get $next) (local.get $len))))
+        (func (export "dealloc") (param i32 i32)
+            (global.set $freed (i32.add (global.get $freed) (i32.const 1))))
+        (func (export "echo") (param $ptr i32) (param $len i32) (result i64)
+            (local $out i32)
+            (local.set $out (call $alloc (local.get $len)))
+            (memory.copy (local.get $out) (local.get $ptr) (local.get $len))
+            (i64.or
+                (i64.shl (i64.extend_i32_u (local.get $out)) (i64.const 32))
+                (i64.extend_i32_u (local.get $len))))
+        (func (export "wild") (param i32 i32) (result i64)
+            (i64.const 0x00100000_00000010))
+        (func (export "narrow") (param i32) (result i32) (local.get 0)))"#;
+
+    fn instantiate(text: &str) -> (Store<()>, Instance) {
+        let engine = Engine::default();
+        let module = Module::new(&engine, wat::parse_str(text).unwrap()).unwrap();
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::new(&engine).instantiate(&mut store, &module).unwrap().start(&mut store).unwrap();
+        (store, instance)
+    }
+
+    #[test]
+    fn test_unpack() {
+        assert_eq!(unpack(0x0000_0400_0000_0005), (1024, 5));
+        assert_eq!(unpack(-1), (u32::MAX, u32::MAX));
+    }
+
+    #[test]
+    fn test_call_round_trips_bytes() {
+        let (mut store, instance) = instantiate(ECHO_MODULE);
+        let echo = instance.get_func(&store, "echo").unwrap();
+        let call = ByteCall::new(&mut store, &instance, echo).unwrap();
+        assert_eq!(call.call(&mut store, b"{\"a\":1}", None).unwrap(), b"{\"a\":1}");
+        assert_eq!(call.call(&mut store, b"", None).unwrap(), b"");
+
+        let freed = instance.get_global(&store, "freed").unwrap().get(&store).i32();
+        assert_eq!(freed, Some(3), "both blocks of the first call and the empty input should be freed");
+    }
+
+    #[test]
+    fn test_call_rejects_bad_modules() {
+        let (mut store, instance) = instantiate(ECHO_MODULE);
+        let narrow = instance.get_func(&store, "narrow").unwrap();
+        assert!(ByteCall::new(&mut store, &instance, narrow).is_err());
+
+        let wild = instance.get_func(&store, "wild").unwrap();
+        let call = ByteCall::new(&mut store, &instance, wild).unwrap();
+        assert!(call.call(&mut store, b"x", None).is_err());
+
+        let (mut store, instance) = instantiate(r#"(module (memory (export "memory") 1) (func (export "f") (param i32 i32) (result i64) i64.const 0))"#);
+        let f = instance.get_func(&store, "f").unwrap();
+        assert!(ByteCall::new(&mut store, &instance, f).is_err(), "alloc and dealloc are required");
+    }
+}
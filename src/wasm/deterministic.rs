@@ -0,0 +1,239 @@
+// Deterministic execution.
+//
+// A call made with `"deterministic": true` gives the same result for the same
+// module and inputs wherever it runs:
+//   - every float instruction that can produce a NaN has its result replaced
+//     by the canonical NaN, so NaN bit patterns do not depend on the host;
+//   - SIMD is not allowed, since relaxed SIMD is nondeterministic by design;
+//   - host_v1's time_now_ms and random_u64 are not available, and WASI clocks
+//     read zero and random_get returns a fixed stream;
+//   - the call gets DETERMINISTIC_FUEL fuel, so it runs out at the same point.
+// The response carries the module digest and a hash of the inputs.
+use super::host::HOST_API_V1;
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+use std::env;
+use wasm_encoder::reencode::{utils, Error, Reencode};
+use wasm_encoder::{CodeSection, Function, Instruction, ValType};
+use wasmi::Module;
+use wasmparser::{CompositeInnerType, FunctionBody, Operator, Parser, Payload};
+
+// host_v1 functions whose results depend on when or where a call runs
+pub const NONDETERMINISTIC_IMPORTS: [&str; 2] = ["time_now_ms", "random_u64"];
+
+const CANONICAL_NAN_F32: u32 = 0x7fc0_0000;
+const CANONICAL_NAN_F64: u64 = 0x7ff8_0000_0000_0000;
+
+pub fn get_deterministic_fuel() -> u64 {
+    env::var("DETERMINISTIC_FUEL")
+        .unwrap_or_else(|_| "1000000000".to_string())
+        .parse()
+        .unwrap_or(1_000_000_000)
+}
+
+// Hex SHA-256 of some bytes
+pub fn digest(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Rejects modules importing host functions that make a run depend on when or
+// where it happens
+pub fn check_imports(module: &Module) -> tide::Result<()> {
+    match module
+        .imports()
+        .find(|import| import.module() == HOST_API_V1 && NONDETERMINISTIC_IMPORTS.contains(&import.name()))
+    {
+        Some(import) => Err(tide::Error::from_str(
+            400,
+            format!("Module imports '{}::{}', which deterministic runs do not offer", import.module(), import.name()),
+        )),
+        None => Ok(()),
+    }
+}
+
+// Rewrites a module so that float results are never non-canonical NaNs
+pub fn canonicalize_nans(bytecode: &[u8]) -> Result<Vec<u8>, String> {
+    let mut canonicalizer = Canonicalizer { params: function_params(bytecode)?, next: 0 };
+    let mut module = wasm_encoder::Module::new();
+    canonicalizer
+        .parse_core_module(&mut module, Parser::new(0), bytecode)
+        .map_err(|e| e.to_string())?;
+    Ok(module.finish())
+}
+
+// Number of parameters of each function defined (not imported) by the module
+fn function_params(bytecode: &[u8]) -> Result<Vec<u32>, String> {
+    let mut types = Vec::new();
+    let mut params = Vec::new();
+    for payload in Parser::new(0).parse_all(bytecode) {
+        match payload.map_err(|e| e.to_string())? {
+            Payload::TypeSection(reader) => {
+                for group in reader {
+                    for ty in group.map_err(|e| e.to_string())?.into_types() {
+                        types.push(match &ty.composite_type.inner {
+                            CompositeInnerType::Func(func) => func.params().len() as u32,
+                            _ => 0,
+                        });
+                    }
+                }
+            }
+            Payload::FunctionSection(reader) => {
+                for ty in reader {
+                    let ty = ty.map_err(|e| e.to_string())? as usize;
+                    params.push(*types.get(ty).ok_or("function type out of range")?);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(params)
+}
+
+struct Canonicalizer {
+    params: Vec<u32>, // Per defined function, in code section order
+    next: usize,
+}
+
+impl Reencode for Canonicalizer {
+    type Error = Infallible;
+
+    // Copies the body, adding one f32 and one f64 scratch local and a
+    // canonicalizing sequence after every instruction that may yield a NaN.
+    // Bodies without such instructions are copied as they are, so modules
+    // without floats still validate when floats are not allowed.
+    fn parse_function_body(&mut self, code: &mut CodeSection, func: FunctionBody<'_>) -> Result<(), Error<Infallible>> {
+        let mut local_count = self.params.get(self.next).copied().unwrap_or(0);
+        self.next += 1;
+        let mut reader = func.get_operators_reader()?;
+        let mut produces_nan = false;
+        while !reader.eof() {
+            produces_nan |= nan_result(&reader.read()?).is_some();
+        }
+        if !produces_nan {
+            return utils::parse_function_body(self, code, func);
+        }
+        let mut locals = Vec::new();
+        for pair in func.get_locals_reader()? {
+            let (count, ty) = pair?;
+            local_count += count;
+            locals.push((count, self.val_type(ty)?));
+        }
+        let (scratch_f32, scratch_f64) = (local_count, local_count + 1);
+        locals.extend([(1, ValType::F32), (1, ValType::F64)]);
+
+        let mut function = Function::new(locals);
+        let mut reader = func.get_operators_reader()?;
+        while !reader.eof() {
+            let operator = reader.read()?;
+            let result = nan_result(&operator);
+            function.instruction(&self.instruction(operator)?);
+            // x == x is false only for NaN: select(x, NaN, x == x)
+            match result {
+                Some(ValType::F32) => {
+                    function.instruction(&Instruction::LocalTee(scratch_f32));
+                    function.instruction(&Instruction::F32Const(f32::from_bits(CANONICAL_NAN_F32).into()));
+                    function.instruction(&Instruction::LocalGet(scratch_f32));
+                    function.instruction(&Instruction::LocalGet(scratch_f32));
+                    function.instruction(&Instruction::F32Eq);
+                    function.instruction(&Instruction::Select);
+                }
+                Some(_) => {
+                    function.instruction(&Instruction::LocalTee(scratch_f64));
+                    function.instruction(&Instruction::F64Const(f64::from_bits(CANONICAL_NAN_F64).into()));
+                    function.instruction(&Instruction::LocalGet(scratch_f64));
+                    function.instruction(&Instruction::LocalGet(scratch_f64));
+                    function.instruction(&Instruction::F64Eq);
+                    function.instruction(&Instruction::Select);
+                }
+                None => {}
+            }
+        }
+        code.function(&function);
+        Ok(())
+    }
+}
+
+// The float type of an instruction's result when it may be a NaN whose bits
+// the host chooses. Loads, moves and sign operations only copy bits.
+fn nan_result(operator: &Operator) -> Option<ValType> {
+    use Operator::*;
+    match operator {
+        F32Add | F32Sub | F32Mul | F32Div | F32Sqrt | F32Min | F32Max | F32Ceil | F32Floor | F32Trunc | F32Nearest
+        | F32DemoteF64 => Some(ValType::F32),
+        F64Add | F64Sub | F64Mul | F64Div | F64Sqrt | F64Min | F64Max | F64Ceil | F64Floor | F64Trunc | F64Nearest
+        | F64PromoteF32 => Some(ValType::F64),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm::features::{self, Feature};
+    use wasmi::{Engine, Linker, Store, Val};
+
+    // Returns the bits of 0/0 with a NaN payload picked by the module
+    const NAN_MODULE: &str = r#"(module
+        (func (export "div") (param $x f32) (result i32)
+            (local $unused i64)
+            (i32.reinterpret_f32 (f32.div (local.get $x) (f32.const 0))))
+        (func (export "payload") (result i64)
+            (i64.reinterpret_f64 (f64.add (f64.const nan:0x4) (f64.const 1))))
+        (func (export "neg") (result i32)
+            (i32.reinterpret_f32 (f32.neg (f32.const nan:0x1)))))"#;
+
+    fn call(bytecode: &[u8], name: &str, params: &[Val], result: Val) -> Val {
+        let engine = Engine::default();
+        let module = Module::new(&engine, bytecode).unwrap();
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::new(&engine).instantiate(&mut store, &module).unwrap().start(&mut store).unwrap();
+        let mut results = [result];
+        instance.get_func(&store, name).unwrap().call(&mut store, params, &mut results).unwrap();
+        results[0].clone()
+    }
+
+    #[test]
+    fn test_canonicalize_nans() {
+        let bytecode = canonicalize_nans(&wat::parse_str(NAN_MODULE).unwrap()).unwrap();
+        let div = call(&bytecode, "div", &[Val::F32(0.0f32.into())], Val::I32(0));
+        assert_eq!(div.i32(), Some(CANONICAL_NAN_F32 as i32));
+        let payload = call(&bytecode, "payload", &[], Val::I64(0));
+        assert_eq!(payload.i64(), Some(CANONICAL_NAN_F64 as i64));
+        // Non-NaN results and bit operations are untouched
+        let div = call(&bytecode, "div", &[Val::F32(1.0f32.into())], Val::I32(0));
+        assert_eq!(div.i32(), Some(f32::INFINITY.to_bits() as i32));
+        let neg = call(&bytecode, "neg", &[], Val::I32(0));
+        assert_eq!(neg.i32(), Some(0xff80_0001u32 as i32));
+    }
+
+    #[test]
+    fn test_canonicalize_nans_leaves_integer_code_alone() {
+        let bytecode = wat::parse_str(r#"(module (func (export "add") (param i32 i32) (result i32)
+            (i32.add (local.get 0) (local.get 1))))"#).unwrap();
+        let canonical = canonicalize_nans(&bytecode).unwrap();
+        // Still valid when the owner's policy does not allow floats
+        let no_floats = Feature::ALL.into_iter().filter(|feature| *feature != Feature::Floats).collect();
+        assert!(Module::new(&Engine::new(&features::config(&no_floats)), &canonical[..]).is_ok());
+    }
+
+    #[test]
+    fn test_canonicalize_nans_rejects_invalid_bytes() {
+        assert!(canonicalize_nans(b"not wasm").is_err());
+    }
+
+    #[test]
+    fn test_check_imports() {
+        let engine = Engine::default();
+        let log = r#"(module (import "host_v1" "log" (func (param i32 i32 i32))))"#;
+        assert!(check_imports(&Module::new(&engine, wat::parse_str(log).unwrap()).unwrap()).is_ok());
+        let time = r#"(module (import "host_v1" "time_now_ms" (func (result i64))))"#;
+        let error = check_imports(&Module::new(&engine, wat::parse_str(time).unwrap()).unwrap()).unwrap_err();
+        assert_eq!(error.status(), 400);
+        assert!(error.to_string().contains("host_v1::time_now_ms"));
+    }
+
+    #[test]
+    fn test_digest() {
+        assert_eq!(digest(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+}
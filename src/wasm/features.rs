@@ -0,0 +1,187 @@
+// Which WebAssembly proposals modules may use.
+//
+// WASM_FEATURES lists the features the server allows (all of them by
+// default); an admin can narrow the list for a user. Uploads are checked
+// against the uploader's policy and executions compile the module with its
+// owner's, so tightening a policy also stops records uploaded before.
+use crate::state::AppStateInner;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::env;
+use tracing::warn;
+use wasmi::{Config, Engine, Module};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    Simd, // Including relaxed SIMD
+    BulkMemory,
+    MultiValue,
+    ReferenceTypes,
+    TailCall,
+    Floats, // f32 and f64 types and instructions
+}
+
+pub type FeatureSet = BTreeSet<Feature>;
+
+impl Feature {
+    pub const ALL: [Feature; 6] = [
+        Feature::Simd,
+        Feature::BulkMemory,
+        Feature::MultiValue,
+        Feature::ReferenceTypes,
+        Feature::TailCall,
+        Feature::Floats,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Simd => "simd",
+            Feature::BulkMemory => "bulk_memory",
+            Feature::MultiValue => "multi_value",
+            Feature::ReferenceTypes => "reference_types",
+            Feature::TailCall => "tail_call",
+            Feature::Floats => "floats",
+        }
+    }
+
+    fn set(self, config: &mut Config, enable: bool) {
+        match self {
+            Feature::Simd => config.wasm_simd(enable).wasm_relaxed_simd(enable),
+            Feature::BulkMemory => config.wasm_bulk_memory(enable),
+            Feature::MultiValue => config.wasm_multi_value(enable),
+            Feature::ReferenceTypes => config.wasm_reference_types(enable),
+            Feature::TailCall => config.wasm_tail_call(enable),
+            Feature::Floats => config.floats(enable),
+        };
+    }
+}
+
+// Comma-separated feature names; unset means all of them
+pub fn get_server_features() -> FeatureSet {
+    let Ok(names) = env::var("WASM_FEATURES") else {
+        return Feature::ALL.into();
+    };
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let feature = Feature::ALL.into_iter().find(|feature| feature.name() == name);
+            if feature.is_none() {
+                warn!(feature = %name, "Ignoring unknown feature in WASM_FEATURES");
+            }
+            feature
+        })
+        .collect()
+}
+
+// The features a user's modules may use: the server's, narrowed by the
+// user's own policy if an admin has set one
+pub fn allowed_features(app_state: &AppStateInner, username: &str) -> FeatureSet {
+    let server = get_server_features();
+    match app_state.wasm_features.get(username) {
+        Some(user) => server.intersection(user).copied().collect(),
+        None => server,
+    }
+}
+
+// Engine configuration that validates modules against `allowed`
+pub fn config(allowed: &FeatureSet) -> Config {
+    let mut config = Config::default();
+    for feature in Feature::ALL {
+        feature.set(&mut config, allowed.contains(&feature));
+    }
+    config
+}
+
+// A module rejected for the features it uses; sent as the response body
+#[derive(Debug, Serialize)]
+pub struct FeatureError {
+    error: String,
+    features: Vec<Feature>, // The offending features
+    allowed: FeatureSet,
+}
+
+impl std::fmt::Display for FeatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.error)
+    }
+}
+
+impl std::error::Error for FeatureError {}
+
+// Explains why a module does not compile under `allowed`, when the reason is
+// a feature it may not use: a feature is named when the module compiles with
+// every feature but that one. None when the module is invalid regardless.
+pub fn violation(bytecode: &[u8], allowed: &FeatureSet) -> Option<tide::Error> {
+    let compiles = |features: &FeatureSet| Module::new(&Engine::new(&config(features)), bytecode).is_ok();
+    let all: FeatureSet = Feature::ALL.into();
+    if !compiles(&all) {
+        return None;
+    }
+    let features: Vec<Feature> = all
+        .difference(allowed)
+        .copied()
+        .filter(|feature| {
+            let mut without = all.clone();
+            without.remove(feature);
+            !compiles(&without)
+        })
+        .collect();
+    let names: Vec<&str> = features.iter().map(|feature| feature.name()).collect();
+    let error = match names.as_slice() {
+        [] => "Module uses a combination of WASM features that is not allowed".to_string(),
+        [name] => format!("Module uses the {} feature, which is not allowed", name),
+        names => format!("Module uses the {} features, which are not allowed", names.join(", ")),
+    };
+    Some(tide::Error::new(400, FeatureError { error, features, allowed: allowed.clone() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::new_state;
+
+    const SIMD_MODULE: &str = r#"(module (func (export "f") (result i32)
+        (i32x4.extract_lane 0 (v128.const i32x4 1 2 3 4))))"#;
+    const FLOAT_MODULE: &str = r#"(module (func (export "f") (param f64) (result f64) (f64.sqrt (local.get 0))))"#;
+    const BULK_MEMORY_MODULE: &str = r#"(module (memory 1)
+        (func (export "f") (memory.fill (i32.const 0) (i32.const 0) (i32.const 8))))"#;
+
+    fn without(excluded: &[Feature]) -> FeatureSet {
+        Feature::ALL.into_iter().filter(|feature| !excluded.contains(feature)).collect()
+    }
+
+    fn offending(text: &str, allowed: &FeatureSet) -> Option<Vec<Feature>> {
+        let error = violation(&wat::parse_str(text).unwrap(), allowed)?;
+        Some(error.downcast_ref::<FeatureError>().unwrap().features.clone())
+    }
+
+    #[test]
+    fn test_config_enforces_features() {
+        let bytecode = wat::parse_str(SIMD_MODULE).unwrap();
+        assert!(Module::new(&Engine::new(&config(&Feature::ALL.into())), &bytecode).is_ok());
+        assert!(Module::new(&Engine::new(&config(&without(&[Feature::Simd]))), &bytecode).is_err());
+    }
+
+    #[test]
+    fn test_violation_names_the_feature() {
+        assert_eq!(offending(SIMD_MODULE, &without(&[Feature::Simd])), Some(vec![Feature::Simd]));
+        assert_eq!(offending(FLOAT_MODULE, &without(&[Feature::Floats])), Some(vec![Feature::Floats]));
+        assert_eq!(offending(BULK_MEMORY_MODULE, &FeatureSet::new()), Some(vec![Feature::BulkMemory]));
+        // Not a feature problem
+        assert!(violation(b"not wasm", &FeatureSet::new()).is_none());
+    }
+
+    #[test]
+    fn test_user_policy_narrows_the_server_policy() {
+        let state = new_state();
+        let mut app_state = state.lock().unwrap();
+        assert_eq!(allowed_features(&app_state, "user1"), get_server_features());
+        app_state.wasm_features.insert("user1".to_string(), [Feature::Floats].into());
+        let allowed = allowed_features(&app_state, "user1");
+        assert!(allowed.is_subset(&[Feature::Floats].into()));
+        assert_eq!(allowed_features(&app_state, "user2"), get_server_features());
+    }
+}
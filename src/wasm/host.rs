@@ -0,0 +1,99 @@
+// Host functions offered to uploaded modules.
+//
+// A record opts into a host API by naming its version in `host_api`; the
+// module then imports that version's functions from the module of the same
+// name, e.g. `(import "host_v1" "log" (func (param i32 i32 i32)))`. Versions
+// are never changed once published, so existing modules keep linking. WASI is
+// enabled separately (see `wasi.rs`); records with neither get no imports.
+//
+// host_v1:
+//   log(level: i32, ptr: i32, len: i32)   UTF-8 message from memory; 0=error 1=warn 2=info 3=debug 4=trace
+//   time_now_ms() -> i64                  Unix time in milliseconds
+//   random_u64() -> i64                   Random 64 bits
+//   input_len() -> i32                    Length of the call's `input`
+//   input_read(ptr: i32, len: i32) -> i32 Copies up to `len` input bytes to `ptr`, returns the count
+use super::wasi::{self, WasiCtx};
+use chrono::Utc;
+use tracing::{debug, error, info, trace, warn};
+use wasmi::{Caller, Engine, Extern, Linker, Memory};
+
+pub const HOST_API_V1: &str = "host_v1";
+pub const HOST_API_VERSIONS: [&str; 1] = [HOST_API_V1];
+
+// Longest log message taken from a guest
+const MAX_LOG_MESSAGE_BYTES: usize = 4096;
+
+// Per-call data the host functions can see
+#[derive(Default)]
+pub struct HostState {
+    pub record_id: u32,
+    pub user: String,
+    pub input: Vec<u8>,
+    pub wasi: WasiCtx,
+}
+
+// Builds the linker for a record's host API and WASI setting
+pub fn linker(engine: &Engine, host_api: Option<&str>, with_wasi: bool) -> Result<Linker<HostState>, wasmi::Error> {
+    let mut linker = Linker::new(engine);
+    match host_api {
+        None => {}
+        Some(HOST_API_V1) => define_v1(&mut linker)?,
+        Some(other) => return Err(wasmi::Error::new(format!("unknown host API '{}'", other))),
+    }
+    if with_wasi {
+        wasi::define(&mut linker)?;
+    }
+    Ok(linker)
+}
+
+fn define_v1(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+    linker.func_wrap(HOST_API_V1, "log", |caller: Caller<'_, HostState>, level: i32, ptr: i32, len: i32| -> Result<(), wasmi::Error> {
+        let len = usize::try_from(len).map_err(|_| wasmi::Error::new("log: negative length"))?;
+        let bytes = read_memory(&caller, ptr, len.min(MAX_LOG_MESSAGE_BYTES))?;
+        let message = String::from_utf8_lossy(&bytes);
+        let (record_id, user) = (caller.data().record_id, &caller.data().user);
+        match level {
+            0 => error!(record_id = record_id, user = %user, "wasm: {}", message),
+            1 => warn!(record_id = record_id, user = %user, "wasm: {}", message),
+            3 => debug!(record_id = record_id, user = %user, "wasm: {}", message),
+            4 => trace!(record_id = record_id, user = %user, "wasm: {}", message),
+            _ => info!(record_id = record_id, user = %user, "wasm: {}", message),
+        }
+        Ok(())
+    })?;
+    linker.func_wrap(HOST_API_V1, "time_now_ms", || -> i64 { Utc::now().timestamp_millis() })?;
+    linker.func_wrap(HOST_API_V1, "random_u64", || -> i64 { rand::random::<i64>() })?;
+    linker.func_wrap(HOST_API_V1, "input_len", |caller: Caller<'_, HostState>| -> i32 {
+        caller.data().input.len() as i32
+    })?;
+    linker.func_wrap(HOST_API_V1, "input_read", |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> Result<i32, wasmi::Error> {
+        let len = usize::try_from(len).map_err(|_| wasmi::Error::new("input_read: negative length"))?;
+        let input = std::mem::take(&mut caller.data_mut().input);
+        let count = len.min(input.len());
+        let written = write_memory(&mut caller, ptr, &input[..count]);
+        caller.data_mut().input = input;
+        written.map(|_| count as i32)
+    })?;
+    Ok(())
+}
+
+fn memory(caller: &Caller<'_, HostState>) -> Result<Memory, wasmi::Error> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmi::Error::new("module does not export its memory as \"memory\""))
+}
+
+fn read_memory(caller: &Caller<'_, HostState>, ptr: i32, len: usize) -> Result<Vec<u8>, wasmi::Error> {
+    let mut buffer = vec![0; len];
+    memory(caller)?
+        .read(caller, ptr as u32 as usize, &mut buffer)
+        .map_err(|_| wasmi::Error::new("memory access out of bounds"))?;
+    Ok(buffer)
+}
+
+fn write_memory(caller: &mut Caller<'_, HostState>, ptr: i32, bytes: &[u8]) -> Result<(), wasmi::Error> {
+    memory(caller)?
+        .write(caller, ptr as u32 as usize, bytes)
+        .map_err(|_| wasmi::Error::new("memory access out of bounds"))
+}
@@ -0,0 +1,296 @@
+// WebAssembly support shared by the upload and execute handlers
+pub mod abi;
+pub mod deterministic;
+pub mod features;
+pub mod host;
+pub mod text;
+pub mod wasi;
+
+use features::FeatureSet;
+use host::{HostState, HOST_API_VERSIONS};
+use std::sync::atomic::{AtomicBool, Ordering};
+use wasi::WASI_MODULE;
+use wasmi::core::TrapCode;
+use wasmi::{Engine, Func, Instance, Linker, Module, ResumableCall, Store, Val};
+
+// Fuel a cancellable call gets between checks of its cancel flag
+const FUEL_SLICE: u64 = 1_000_000;
+
+pub const CANCELLED: &str = "execution cancelled";
+
+// Engine for one run that accepts modules using the `allowed` features;
+// cancellable runs meter fuel so they can be stopped
+pub fn engine(cancellable: bool, allowed: &FeatureSet) -> Engine {
+    let mut config = features::config(allowed);
+    config.consume_fuel(cancellable);
+    Engine::new(&config)
+}
+
+// Instantiates a module and runs its start function, which always runs to
+// completion. Metered stores get `fuel` as the budget for everything the
+// instance runs.
+pub fn instantiate<T>(linker: &Linker<T>, store: &mut Store<T>, module: &Module, fuel: Option<u64>) -> Result<Instance, wasmi::Error> {
+    if let Some(fuel) = fuel {
+        store.set_fuel(fuel)?;
+    }
+    linker.instantiate(&mut *store, module)?.start(&mut *store)
+}
+
+// Calls a function. With `cancel` (on an engine from `engine(true)`) the call
+// runs in fuel slices taken from the store's fuel, and fails with CANCELLED
+// once the flag is set or out of fuel once the store has none left.
+pub fn call<T>(store: &mut Store<T>, func: Func, params: &[Val], results: &mut [Val], cancel: Option<&AtomicBool>) -> Result<(), wasmi::Error> {
+    let Some(cancel) = cancel else {
+        return func.call(store, params, results);
+    };
+    let mut remaining = store.get_fuel()?;
+    let outcome = call_in_slices(store, func, params, results, cancel, &mut remaining);
+    store.set_fuel(remaining)?;
+    outcome
+}
+
+fn call_in_slices<T>(
+    store: &mut Store<T>,
+    func: Func,
+    params: &[Val],
+    results: &mut [Val],
+    cancel: &AtomicBool,
+    remaining: &mut u64,
+) -> Result<(), wasmi::Error> {
+    let mut slice = FUEL_SLICE.min(*remaining);
+    store.set_fuel(slice)?;
+    let mut call = func.call_resumable(&mut *store, params, results);
+    loop {
+        *remaining -= slice - store.get_fuel()?;
+        match call? {
+            ResumableCall::Finished => return Ok(()),
+            ResumableCall::HostTrap(trap) => return Err(trap.into_host_error()),
+            ResumableCall::OutOfFuel(paused) => {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(wasmi::Error::new(CANCELLED));
+                }
+                if *remaining < paused.required_fuel() {
+                    return Err(TrapCode::OutOfFuel.into());
+                }
+                slice = FUEL_SLICE.max(paused.required_fuel()).min(*remaining);
+                store.set_fuel(slice)?;
+                call = paused.resume(&mut *store, results);
+            }
+        }
+    }
+}
+
+// Checks an uploaded module against the record's host API and WASI setting
+// and the uploader's feature policy. Unknown API versions, imports that would
+// not link and disallowed features are rejected here rather than at execution
+// time. Bytes that do not parse are still accepted when no imports are
+// enabled; execution reports them as invalid.
+pub fn validate_upload(bytecode: &[u8], host_api: Option<&str>, with_wasi: bool, allowed: &FeatureSet) -> Result<(), tide::Error> {
+    if let Some(version) = host_api.filter(|version| !HOST_API_VERSIONS.contains(version)) {
+        return Err(tide::Error::from_str(
+            400,
+            format!("Unknown host_api '{}'. Available versions: {:?}", version, HOST_API_VERSIONS),
+        ));
+    }
+
+    let engine = Engine::new(&features::config(allowed));
+    let imports_enabled = host_api.is_some() || with_wasi;
+    let module = match Module::new(&engine, bytecode) {
+        Ok(module) => module,
+        Err(e) => {
+            if let Some(violation) = features::violation(bytecode, allowed) {
+                return Err(violation);
+            }
+            if !imports_enabled {
+                return Ok(());
+            }
+            return Err(tide::Error::from_str(400, format!("Invalid WASM: {e}")));
+        }
+    };
+
+    // Point at the missing opt-in rather than reporting a failed link
+    for import in module.imports() {
+        let hint = match import.module() {
+            WASI_MODULE if !with_wasi => "set wasi to true".to_string(),
+            WASI_MODULE => continue,
+            _ if host_api.is_none() => format!("set host_api to one of {:?}", HOST_API_VERSIONS),
+            _ => continue,
+        };
+        return Err(tide::Error::from_str(
+            400,
+            format!("Module imports '{}::{}' but the record does not enable it ({})", import.module(), import.name(), hint),
+        ));
+    }
+    if !imports_enabled {
+        return Ok(());
+    }
+
+    let linker = host::linker(&engine, host_api, with_wasi).map_err(|e| tide::Error::from_str(500, e.to_string()))?;
+    let mut store = Store::new(&engine, HostState::default());
+    linker
+        .instantiate(&mut store, &module)
+        .map_err(|e| tide::Error::from_str(400, format!("Unsupported import: {e}")))?;
+    Ok(())
+}
+
+// Error bodies are empty elsewhere; upload errors that point into the module
+// (a WAT parse position, disallowed features) are sent as JSON
+pub async fn add_module_error_details(mut res: tide::Response) -> tide::Result {
+    let body = if let Some(error) = res.downcast_error::<text::WatError>() {
+        Some(tide::Body::from_json(error)?)
+    } else if let Some(error) = res.downcast_error::<features::FeatureError>() {
+        Some(tide::Body::from_json(error)?)
+    } else {
+        None
+    };
+    if let Some(body) = body {
+        res.set_body(body);
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn wasm(text: &str) -> Vec<u8> {
+        wat::parse_str(text).unwrap()
+    }
+
+    fn all() -> FeatureSet {
+        features::Feature::ALL.into()
+    }
+
+    #[test]
+    fn test_validate_upload_without_host_api() {
+        let pure = wasm(r#"(module (func (export "add") (param i32 i32) (result i32) local.get 0 local.get 1 i32.add))"#);
+        assert!(validate_upload(&pure, None, false, &all()).is_ok());
+        // Legacy behaviour: unparsable bytes are left for execution to reject
+        assert!(validate_upload(&[1, 2, 3], None, false, &all()).is_ok());
+
+        let importing = wasm(r#"(module (import "host_v1" "time_now_ms" (func (result i64))))"#);
+        assert_eq!(validate_upload(&importing, None, false, &all()).unwrap_err().status(), 400);
+    }
+
+    #[test]
+    fn test_validate_upload_with_host_api() {
+        let module = wasm(
+            r#"(module
+                (import "host_v1" "log" (func (param i32 i32 i32)))
+                (import "host_v1" "input_read" (func (param i32 i32) (result i32)))
+                (memory (export "memory") 1))"#,
+        );
+        assert!(validate_upload(&module, Some("host_v1"), false, &all()).is_ok());
+        assert_eq!(validate_upload(&module, Some("host_v9"), false, &all()).unwrap_err().status(), 400);
+        assert_eq!(validate_upload(&[1, 2, 3], Some("host_v1"), false, &all()).unwrap_err().status(), 400);
+
+        let unknown = wasm(r#"(module (import "host_v1" "open_socket" (func)))"#);
+        assert_eq!(validate_upload(&unknown, Some("host_v1"), false, &all()).unwrap_err().status(), 400);
+        let other_module = wasm(r#"(module (import "env" "log" (func (param i32 i32 i32))))"#);
+        assert_eq!(validate_upload(&other_module, Some("host_v1"), false, &all()).unwrap_err().status(), 400);
+        let wrong_type = wasm(r#"(module (import "host_v1" "time_now_ms" (func (result i32))))"#);
+        assert_eq!(validate_upload(&wrong_type, Some("host_v1"), false, &all()).unwrap_err().status(), 400);
+    }
+
+    #[test]
+    fn test_validate_upload_with_wasi() {
+        let module = wasm(
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write" (func (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+                (memory (export "memory") 1))"#,
+        );
+        assert!(validate_upload(&module, None, true, &all()).is_ok());
+        assert_eq!(validate_upload(&module, None, false, &all()).unwrap_err().status(), 400);
+        assert_eq!(validate_upload(&module, Some("host_v1"), false, &all()).unwrap_err().status(), 400);
+
+        let mixed = wasm(
+            r#"(module
+                (import "wasi_snapshot_preview1" "sched_yield" (func (result i32)))
+                (import "host_v1" "time_now_ms" (func (result i64))))"#,
+        );
+        assert!(validate_upload(&mixed, Some("host_v1"), true, &all()).is_ok());
+        assert_eq!(validate_upload(&mixed, None, true, &all()).unwrap_err().status(), 400);
+        let wrong_type = wasm(r#"(module (import "wasi_snapshot_preview1" "fd_write" (func (param i32))))"#);
+        assert_eq!(validate_upload(&wrong_type, None, true, &all()).unwrap_err().status(), 400);
+    }
+
+    #[test]
+    fn test_validate_upload_with_feature_policy() {
+        let simd = wasm(r#"(module (func (export "f") (result i32) (i32x4.extract_lane 0 (v128.const i32x4 1 2 3 4))))"#);
+        assert!(validate_upload(&simd, None, false, &all()).is_ok());
+        let mut no_simd = all();
+        no_simd.remove(&features::Feature::Simd);
+        let error = validate_upload(&simd, None, false, &no_simd).unwrap_err();
+        assert_eq!(error.status(), 400);
+        assert_eq!(error.to_string(), "Module uses the simd feature, which is not allowed");
+    }
+
+    #[async_std::test]
+    async fn test_add_module_error_details() {
+        let mut res: tide::Response = text::compile("(module (func i32.cnst))").unwrap_err().into();
+        res = add_module_error_details(res).await.unwrap();
+        let body: serde_json::Value = res.take_body().into_json().await.unwrap();
+        assert_eq!((body["line"].as_u64(), body["column"].as_u64()), (Some(1), Some(15)));
+        assert!(body["error"].as_str().unwrap().contains("unknown operator"), "{}", body);
+
+        let floats = wasm(r#"(module (func (export "f") (param f32) (result f32) (local.get 0)))"#);
+        let mut res: tide::Response = validate_upload(&floats, None, false, &FeatureSet::new()).unwrap_err().into();
+        res = add_module_error_details(res).await.unwrap();
+        let body: serde_json::Value = res.take_body().into_json().await.unwrap();
+        assert_eq!(body["features"], serde_json::json!(["floats"]));
+
+        let mut plain: tide::Response = tide::Error::from_str(400, "Invalid id").into();
+        plain = add_module_error_details(plain).await.unwrap();
+        assert_eq!(plain.take_body().is_empty(), Some(true), "other errors keep an empty body");
+    }
+
+    #[test]
+    fn test_call_can_be_cancelled() {
+        let engine = engine(true, &all());
+        let module = Module::new(&engine, wasm(r#"(module (func (export "spin") (loop br 0)) (func (export "one") (result i32) i32.const 1))"#)).unwrap();
+        let mut store = Store::new(&engine, ());
+        let instance = instantiate(&Linker::new(&engine), &mut store, &module, Some(u64::MAX)).unwrap();
+
+        let not_cancelled = AtomicBool::new(false);
+        let mut results = [Val::I32(0)];
+        let one = instance.get_func(&store, "one").unwrap();
+        call(&mut store, one, &[], &mut results, Some(&not_cancelled)).unwrap();
+        assert_eq!(results[0].i32(), Some(1));
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = cancel.clone();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            flag.store(true, Ordering::Relaxed);
+        });
+        let spin = instance.get_func(&store, "spin").unwrap();
+        let error = call(&mut store, spin, &[], &mut [], Some(&cancel)).unwrap_err();
+        assert_eq!(error.to_string(), CANCELLED);
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn test_call_stops_when_the_budget_runs_out() {
+        let engine = engine(true, &all());
+        let module = Module::new(&engine, wasm(r#"(module (func (export "spin") (loop br 0)) (func (export "one") (result i32) i32.const 1))"#)).unwrap();
+        let mut store = Store::new(&engine, ());
+        let instance = instantiate(&Linker::new(&engine), &mut store, &module, Some(FUEL_SLICE * 3 / 2)).unwrap();
+        let not_cancelled = AtomicBool::new(false);
+
+        // The budget carries over from one call to the next
+        let one = instance.get_func(&store, "one").unwrap();
+        call(&mut store, one, &[], &mut [Val::I32(0)], Some(&not_cancelled)).unwrap();
+        let left = store.get_fuel().unwrap();
+        assert!(left < FUEL_SLICE * 3 / 2);
+        call(&mut store, one, &[], &mut [Val::I32(0)], Some(&not_cancelled)).unwrap();
+        assert!(store.get_fuel().unwrap() < left);
+
+        let spin = instance.get_func(&store, "spin").unwrap();
+        let error = call(&mut store, spin, &[], &mut [], Some(&not_cancelled)).unwrap_err();
+        assert_eq!(error.as_trap_code(), Some(TrapCode::OutOfFuel));
+        assert!(store.get_fuel().unwrap() < FUEL_SLICE);
+    }
+}
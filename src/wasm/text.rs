@@ -0,0 +1,166 @@
+// Uploads in the WebAssembly text format.
+//
+// A record can be sent as text instead of bytes: `"format": "wat"` with the
+// module in `source`, or the text itself as a `text/wat` body. The server
+// compiles it to binary before the usual upload checks, and parse errors
+// point at the line and column where the text went wrong.
+use crate::models::CreateDataRequest;
+use serde::Serialize;
+use wasmi::{Engine, ExternType, Module};
+use wast::parser::{self, ParseBuffer};
+use wast::Wat;
+
+pub const FORMAT_WASM: &str = "wasm";
+pub const FORMAT_WAT: &str = "wat";
+
+// Content types taken as module text
+pub const WAT_CONTENT_TYPES: [&str; 2] = ["text/wat", "application/wat"];
+
+// Turns a text upload into a binary one. Text records that do not list their
+// functions get the ones the module exports; binary ones must list them.
+pub fn resolve_source(upload: &mut CreateDataRequest) -> Result<(), tide::Error> {
+    match upload.format.as_deref().unwrap_or(FORMAT_WASM) {
+        FORMAT_WASM if upload.source.is_some() => Err(tide::Error::from_str(400, "source needs \"format\": \"wat\"")),
+        FORMAT_WASM if upload.func_names.is_none() => Err(tide::Error::from_str(400, "Binary uploads need func_names")),
+        FORMAT_WASM => Ok(()),
+        FORMAT_WAT => {
+            if !upload.bytecode.is_empty() {
+                return Err(tide::Error::from_str(400, "Send either bytecode or source, not both"));
+            }
+            let source = upload
+                .source
+                .take()
+                .ok_or_else(|| tide::Error::from_str(400, "\"format\": \"wat\" needs the module text in source"))?;
+            upload.bytecode = compile(&source)?;
+            upload.format = None;
+            if upload.func_names.is_none() {
+                upload.func_names = Some(exported_functions(&upload.bytecode));
+            }
+            Ok(())
+        }
+        other => Err(tide::Error::from_str(
+            400,
+            format!("Unknown format '{}'. Available formats: {:?}", other, [FORMAT_WASM, FORMAT_WAT]),
+        )),
+    }
+}
+
+// Compiles module text to binary
+pub fn compile(source: &str) -> Result<Vec<u8>, tide::Error> {
+    let buffer = ParseBuffer::new(source).map_err(|e| diagnostic(source, e))?;
+    match parser::parse::<Wat>(&buffer).map_err(|e| diagnostic(source, e))? {
+        Wat::Module(mut module) => module.encode().map_err(|e| diagnostic(source, e)),
+        Wat::Component(_) => Err(tide::Error::from_str(400, "Components are not supported; upload a core module")),
+    }
+}
+
+// A parse error in module text; `add_module_error_details` sends it as the body
+#[derive(Debug, Serialize)]
+pub struct WatError {
+    error: String,
+    line: usize,   // 1-based
+    column: usize, // 1-based, in characters
+    snippet: String, // The offending line with a caret under the position
+}
+
+impl std::fmt::Display for WatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid WAT at line {}, column {}: {}\n{}", self.line, self.column, self.error, self.snippet)
+    }
+}
+
+impl std::error::Error for WatError {}
+
+fn diagnostic(source: &str, error: wast::Error) -> tide::Error {
+    let (line, offset) = error.span().linecol_in(source);
+    let text = source.split_terminator('\n').nth(line).unwrap_or("").trim_end_matches('\r');
+    let column = text.get(..offset).map_or(offset, |prefix| prefix.chars().count());
+    tide::Error::new(400, WatError {
+        error: error.message(),
+        line: line + 1,
+        column: column + 1,
+        snippet: format!("{:>4} | {}\n     | {}^", line + 1, text, " ".repeat(column)),
+    })
+}
+
+// Names of the functions a module exports; empty if it does not parse
+fn exported_functions(bytecode: &[u8]) -> Vec<String> {
+    let Ok(module) = Module::new(&Engine::default(), bytecode) else {
+        return Vec::new();
+    };
+    module
+        .exports()
+        .filter(|export| matches!(export.ty(), ExternType::Func(_)))
+        .map(|export| export.name().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_upload(source: &str) -> CreateDataRequest {
+        CreateDataRequest {
+            func_names: None,
+            bytecode: vec![],
+            host_api: None,
+            wasi: false,
+            pure_functions: vec![],
+            format: Some(FORMAT_WAT.to_string()),
+            source: Some(source.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_compile_matches_binary() {
+        let source = r#"(module (func (export "one") (result i32) i32.const 1))"#;
+        assert_eq!(compile(source).unwrap(), wat::parse_str(source).unwrap());
+    }
+
+    #[test]
+    fn test_diagnostic_points_at_the_error() {
+        let error = compile("(module\n  (func (export \"f\")\n    i32.cnst 1))").unwrap_err();
+        assert_eq!(error.status(), 400);
+        let message = error.to_string();
+        assert!(message.starts_with("Invalid WAT at line 3, column 5:"), "{}", message);
+        assert!(message.ends_with("   3 |     i32.cnst 1))\n     |     ^"), "{}", message);
+
+        // Unresolved names are reported where they are used
+        let error = compile("(module (func call $missing))").unwrap_err();
+        assert!(error.to_string().starts_with("Invalid WAT at line 1, column 20:"), "{}", error);
+    }
+
+
+    #[test]
+    fn test_resolve_source() {
+        let mut upload = text_upload(r#"(module (func (export "a")) (func (export "b")) (memory (export "memory") 1))"#);
+        resolve_source(&mut upload).unwrap();
+        assert_eq!(upload.func_names.unwrap(), ["a", "b"], "exported functions fill in missing func_names");
+        assert!(upload.source.is_none() && upload.format.is_none());
+        assert!(!upload.bytecode.is_empty());
+
+        let mut listed = text_upload(r#"(module (func (export "a")) (func (export "b")))"#);
+        listed.func_names = Some(vec!["a".to_string()]);
+        resolve_source(&mut listed).unwrap();
+        assert_eq!(listed.func_names.unwrap(), ["a"]);
+
+        let mut both = text_upload("(module)");
+        both.bytecode = vec![0, 97, 115, 109];
+        assert_eq!(resolve_source(&mut both).unwrap_err().status(), 400);
+        let mut unknown = text_upload("(module)");
+        unknown.format = Some("wasm64".to_string());
+        assert_eq!(resolve_source(&mut unknown).unwrap_err().status(), 400);
+        let mut stray = text_upload("(module)");
+        stray.format = None;
+        assert_eq!(resolve_source(&mut stray).unwrap_err().status(), 400);
+
+        // Binary uploads get no default
+        let mut binary = text_upload("(module)");
+        binary.format = None;
+        binary.source = None;
+        binary.bytecode = vec![0, 97, 115, 109, 1, 0, 0, 0];
+        assert!(resolve_source(&mut binary).unwrap_err().to_string().contains("func_names"));
+        binary.func_names = Some(vec![]);
+        assert!(resolve_source(&mut binary).is_ok());
+    }
+}
@@ -0,0 +1,987 @@
+// WASI preview1 for modules built for `wasm32-wasi`.
+//
+// Records opt in with `"wasi": true`. Every execution gets a fresh context:
+// args, env and stdin come from the request, stdout and stderr are captured
+// for the response, and the filesystem is an in-memory tree preopened as "/"
+// that starts with the request's files and is dropped after the call. Nothing
+// on the host is reachable. Calls outside that model (sockets, links, signals)
+// fail with ENOTSUP or ENOSYS so modules importing them still link.
+use super::host::HostState;
+use chrono::Utc;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use std::collections::BTreeMap;
+use std::time::Instant;
+use wasmi::{Caller, Extern, Linker};
+
+pub const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+// Largest stdout/stderr kept per stream; further output is dropped
+pub const MAX_CAPTURE_BYTES: usize = 1024 * 1024;
+// Total size of the in-memory filesystem
+const MAX_FS_BYTES: usize = 16 * 1024 * 1024;
+
+const PREOPEN_FD: u32 = 3;
+
+mod errno {
+    pub const SUCCESS: i32 = 0;
+    pub const ACCES: i32 = 2;
+    pub const BADF: i32 = 8;
+    pub const EXIST: i32 = 20;
+    pub const FAULT: i32 = 21;
+    pub const ILSEQ: i32 = 25;
+    pub const INVAL: i32 = 28;
+    pub const ISDIR: i32 = 31;
+    pub const NOENT: i32 = 44;
+    pub const NOSPC: i32 = 51;
+    pub const NOSYS: i32 = 52;
+    pub const NOTDIR: i32 = 54;
+    pub const NOTEMPTY: i32 = 55;
+    pub const NOTSUP: i32 = 58;
+    pub const OVERFLOW: i32 = 61;
+    pub const SPIPE: i32 = 70;
+    pub const NOTCAPABLE: i32 = 76;
+}
+
+mod filetype {
+    pub const CHARACTER_DEVICE: u8 = 2;
+    pub const DIRECTORY: u8 = 3;
+    pub const REGULAR_FILE: u8 = 4;
+}
+
+const OFLAGS_CREAT: i32 = 1;
+const OFLAGS_DIRECTORY: i32 = 2;
+const OFLAGS_EXCL: i32 = 4;
+const OFLAGS_TRUNC: i32 = 8;
+const FDFLAGS_APPEND: i32 = 1;
+
+type WasiResult = Result<(), i32>;
+
+enum Node {
+    File(Vec<u8>),
+    Dir,
+}
+
+enum Descriptor {
+    Stdin,
+    Stdout,
+    Stderr,
+    File { path: String, pos: u64, append: bool },
+    Dir { path: String },
+}
+
+// Everything one execution can see through WASI
+pub struct WasiCtx {
+    args: Vec<String>,
+    env: Vec<String>, // "KEY=VALUE"
+    stdin: Vec<u8>,
+    stdin_pos: usize,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    nodes: BTreeMap<String, Node>, // Absolute normalized path -> node; "/" is always present
+    fds: BTreeMap<u32, Descriptor>,
+    started: Instant,
+    fixed_rng: Option<StdRng>, // Deterministic runs: clocks read 0 and randomness is seeded
+}
+
+impl Default for WasiCtx {
+    fn default() -> Self {
+        let fds = BTreeMap::from([
+            (0, Descriptor::Stdin),
+            (1, Descriptor::Stdout),
+            (2, Descriptor::Stderr),
+            (PREOPEN_FD, Descriptor::Dir { path: "/".to_string() }),
+        ]);
+        WasiCtx {
+            args: Vec::new(),
+            env: Vec::new(),
+            stdin: Vec::new(),
+            stdin_pos: 0,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            nodes: BTreeMap::from([("/".to_string(), Node::Dir)]),
+            fds,
+            started: Instant::now(),
+            fixed_rng: None,
+        }
+    }
+}
+
+impl WasiCtx {
+    // Builds a context; `files` maps paths to their initial contents and
+    // parent directories are created as needed
+    pub fn new(
+        args: Vec<String>,
+        env: &BTreeMap<String, String>,
+        stdin: Vec<u8>,
+        files: &BTreeMap<String, String>,
+    ) -> Result<Self, String> {
+        if let Some(key) = env.keys().find(|key| key.is_empty() || key.contains('=')) {
+            return Err(format!("Invalid environment variable name '{}'", key));
+        }
+        let mut ctx = WasiCtx {
+            args,
+            env: env.iter().map(|(key, value)| format!("{}={}", key, value)).collect(),
+            stdin,
+            ..WasiCtx::default()
+        };
+        if files.values().map(String::len).sum::<usize>() > MAX_FS_BYTES {
+            return Err(format!("Files exceed the {} byte filesystem limit", MAX_FS_BYTES));
+        }
+        for (path, contents) in files {
+            let path = normalize("/", path.trim_start_matches('/')).map_err(|_| format!("Invalid file path '{}'", path))?;
+            if path == "/" {
+                return Err("Invalid file path '/'".to_string());
+            }
+            let mut dir = String::new();
+            for component in path[1..].split('/').collect::<Vec<_>>().split_last().unwrap().1 {
+                dir = format!("{}/{}", dir, component);
+                if let Some(Node::File(_)) = ctx.nodes.insert(dir.clone(), Node::Dir) {
+                    return Err(format!("'{}' is both a file and a directory", dir));
+                }
+            }
+            if let Some(Node::Dir) = ctx.nodes.get(&path) {
+                return Err(format!("'{}' is both a file and a directory", path));
+            }
+            ctx.nodes.insert(path, Node::File(contents.clone().into_bytes()));
+        }
+        Ok(ctx)
+    }
+
+    // Makes clocks read zero and random_get return the same bytes every run
+    pub fn make_deterministic(&mut self) {
+        self.fixed_rng = Some(StdRng::seed_from_u64(0));
+    }
+
+    fn fs_bytes(&self) -> usize {
+        self.nodes
+            .values()
+            .map(|node| match node {
+                Node::File(data) => data.len(),
+                Node::Dir => 0,
+            })
+            .sum()
+    }
+
+    // Resolves `path` against the directory open as `fd`
+    fn resolve(&self, fd: i32, mem: &[u8], ptr: i32, len: i32) -> Result<String, i32> {
+        let path = read_str(mem, ptr, len)?;
+        match self.fds.get(&(fd as u32)) {
+            Some(Descriptor::Dir { path: dir }) => normalize(dir, path),
+            Some(_) => Err(errno::NOTDIR),
+            None => Err(errno::BADF),
+        }
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        matches!(self.nodes.get(path), Some(Node::Dir))
+    }
+
+    fn children(&self, dir: &str) -> Vec<&str> {
+        let prefix = if dir == "/" { "/".to_string() } else { format!("{}/", dir) };
+        self.nodes
+            .range(prefix.clone()..)
+            .map(|(path, _)| path.as_str())
+            .take_while(|path| path.starts_with(&prefix))
+            .filter(|path| path.len() > prefix.len() && !path[prefix.len()..].contains('/'))
+            .collect()
+    }
+
+    fn file_mut(&mut self, path: &str) -> Result<&mut Vec<u8>, i32> {
+        match self.nodes.get_mut(path) {
+            Some(Node::File(data)) => Ok(data),
+            Some(Node::Dir) => Err(errno::ISDIR),
+            None => Err(errno::NOENT),
+        }
+    }
+
+    fn next_fd(&self) -> u32 {
+        (0..).find(|fd| !self.fds.contains_key(fd)).unwrap()
+    }
+
+    fn args_get(&self, mem: &mut [u8], argv: i32, buf: i32) -> WasiResult {
+        write_strings(mem, &self.args, argv, buf)
+    }
+
+    fn args_sizes_get(&self, mem: &mut [u8], count_ptr: i32, size_ptr: i32) -> WasiResult {
+        write_string_sizes(mem, &self.args, count_ptr, size_ptr)
+    }
+
+    fn environ_get(&self, mem: &mut [u8], environ: i32, buf: i32) -> WasiResult {
+        write_strings(mem, &self.env, environ, buf)
+    }
+
+    fn environ_sizes_get(&self, mem: &mut [u8], count_ptr: i32, size_ptr: i32) -> WasiResult {
+        write_string_sizes(mem, &self.env, count_ptr, size_ptr)
+    }
+
+    fn clock_res_get(&self, mem: &mut [u8], id: i32, ptr: i32) -> WasiResult {
+        if !(0..=3).contains(&id) {
+            return Err(errno::INVAL);
+        }
+        write_u64(mem, ptr, 1_000)
+    }
+
+    fn clock_time_get(&self, mem: &mut [u8], id: i32, ptr: i32) -> WasiResult {
+        let nanos = match id {
+            0..=3 if self.fixed_rng.is_some() => 0,
+            0 => Utc::now().timestamp_nanos_opt().ok_or(errno::OVERFLOW)? as u64,
+            1..=3 => self.started.elapsed().as_nanos() as u64,
+            _ => return Err(errno::INVAL),
+        };
+        write_u64(mem, ptr, nanos)
+    }
+
+    fn fd_close(&mut self, fd: i32) -> WasiResult {
+        self.fds.remove(&(fd as u32)).map(|_| ()).ok_or(errno::BADF)
+    }
+
+    fn fd_exists(&self, fd: i32) -> WasiResult {
+        self.fds.get(&(fd as u32)).map(|_| ()).ok_or(errno::BADF)
+    }
+
+    fn fd_fdstat_get(&self, mem: &mut [u8], fd: i32, ptr: i32) -> WasiResult {
+        let (filetype, flags) = match self.fds.get(&(fd as u32)).ok_or(errno::BADF)? {
+            Descriptor::Stdin | Descriptor::Stdout | Descriptor::Stderr => (filetype::CHARACTER_DEVICE, 0),
+            Descriptor::File { append, .. } => (filetype::REGULAR_FILE, if *append { FDFLAGS_APPEND } else { 0 }),
+            Descriptor::Dir { .. } => (filetype::DIRECTORY, 0),
+        };
+        write_u8(mem, ptr, filetype)?;
+        write_u16(mem, add_offset(ptr, 2)?, flags as u16)?;
+        write_u64(mem, add_offset(ptr, 8)?, u64::MAX)?;
+        write_u64(mem, add_offset(ptr, 16)?, u64::MAX)
+    }
+
+    fn fd_fdstat_set_flags(&mut self, fd: i32, flags: i32) -> WasiResult {
+        match self.fds.get_mut(&(fd as u32)).ok_or(errno::BADF)? {
+            Descriptor::File { append, .. } => *append = flags & FDFLAGS_APPEND != 0,
+            _ if flags & FDFLAGS_APPEND != 0 => return Err(errno::INVAL),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn fd_filestat_get(&self, mem: &mut [u8], fd: i32, ptr: i32) -> WasiResult {
+        match self.fds.get(&(fd as u32)).ok_or(errno::BADF)? {
+            Descriptor::File { path, .. } | Descriptor::Dir { path } => self.write_filestat(mem, path, ptr),
+            _ => write_filestat(mem, ptr, fd as u64, filetype::CHARACTER_DEVICE, 0),
+        }
+    }
+
+    fn write_filestat(&self, mem: &mut [u8], path: &str, ptr: i32) -> WasiResult {
+        match self.nodes.get(path).ok_or(errno::NOENT)? {
+            Node::File(data) => write_filestat(mem, ptr, inode(path), filetype::REGULAR_FILE, data.len() as u64),
+            Node::Dir => write_filestat(mem, ptr, inode(path), filetype::DIRECTORY, 0),
+        }
+    }
+
+    fn fd_filestat_set_size(&mut self, fd: i32, size: i64) -> WasiResult {
+        let path = match self.fds.get(&(fd as u32)).ok_or(errno::BADF)? {
+            Descriptor::File { path, .. } => path.clone(),
+            _ => return Err(errno::INVAL),
+        };
+        let size = usize::try_from(size).map_err(|_| errno::INVAL)?;
+        let current = self.file_mut(&path)?.len();
+        if size > current && self.fs_bytes() + (size - current) > MAX_FS_BYTES {
+            return Err(errno::NOSPC);
+        }
+        self.file_mut(&path)?.resize(size, 0);
+        Ok(())
+    }
+
+    fn fd_prestat_get(&self, mem: &mut [u8], fd: i32, ptr: i32) -> WasiResult {
+        if fd as u32 != PREOPEN_FD || !self.fds.contains_key(&PREOPEN_FD) {
+            return Err(errno::BADF);
+        }
+        write_u8(mem, ptr, 0)?;
+        write_u32(mem, add_offset(ptr, 4)?, 1)
+    }
+
+    fn fd_prestat_dir_name(&self, mem: &mut [u8], fd: i32, ptr: i32, len: i32) -> WasiResult {
+        if fd as u32 != PREOPEN_FD || !self.fds.contains_key(&PREOPEN_FD) {
+            return Err(errno::BADF);
+        }
+        if len < 1 {
+            return Err(errno::INVAL);
+        }
+        slice_mut(mem, ptr, 1)?.copy_from_slice(b"/");
+        Ok(())
+    }
+
+    fn fd_read(&mut self, mem: &mut [u8], fd: i32, iovs: i32, iovs_len: i32, nread_ptr: i32) -> WasiResult {
+        let iovecs = read_iovecs(mem, iovs, iovs_len)?;
+        let wanted: usize = iovecs.iter().map(|(_, len)| *len as usize).sum();
+        let bytes = match self.fds.get_mut(&(fd as u32)).ok_or(errno::BADF)? {
+            Descriptor::Stdin => {
+                let end = (self.stdin_pos + wanted).min(self.stdin.len());
+                let bytes = self.stdin[self.stdin_pos..end].to_vec();
+                self.stdin_pos = end;
+                bytes
+            }
+            Descriptor::File { path, pos, .. } => {
+                let bytes = read_at(&self.nodes, path, *pos, wanted)?;
+                *pos += bytes.len() as u64;
+                bytes
+            }
+            Descriptor::Dir { .. } => return Err(errno::ISDIR),
+            _ => return Err(errno::BADF),
+        };
+        scatter(mem, &iovecs, &bytes)?;
+        write_u32(mem, nread_ptr, bytes.len() as u32)
+    }
+
+    fn fd_pread(&mut self, mem: &mut [u8], fd: i32, iovs: i32, iovs_len: i32, offset: i64, nread_ptr: i32) -> WasiResult {
+        let iovecs = read_iovecs(mem, iovs, iovs_len)?;
+        let wanted: usize = iovecs.iter().map(|(_, len)| *len as usize).sum();
+        let bytes = match self.fds.get(&(fd as u32)).ok_or(errno::BADF)? {
+            Descriptor::File { path, .. } => read_at(&self.nodes, path, offset as u64, wanted)?,
+            Descriptor::Dir { .. } => return Err(errno::ISDIR),
+            _ => return Err(errno::SPIPE),
+        };
+        scatter(mem, &iovecs, &bytes)?;
+        write_u32(mem, nread_ptr, bytes.len() as u32)
+    }
+
+    fn fd_write(&mut self, mem: &mut [u8], fd: i32, iovs: i32, iovs_len: i32, nwritten_ptr: i32) -> WasiResult {
+        let bytes = gather(mem, iovs, iovs_len)?;
+        match self.fds.get(&(fd as u32)).ok_or(errno::BADF)? {
+            Descriptor::Stdout => capture(&mut self.stdout, &bytes),
+            Descriptor::Stderr => capture(&mut self.stderr, &bytes),
+            Descriptor::File { path, pos, append } => {
+                let (path, pos, append) = (path.clone(), *pos, *append);
+                let offset = if append { self.file_mut(&path)?.len() as u64 } else { pos };
+                let end = self.write_at(&path, offset, &bytes)?;
+                if let Some(Descriptor::File { pos, .. }) = self.fds.get_mut(&(fd as u32)) {
+                    *pos = end;
+                }
+            }
+            Descriptor::Dir { .. } => return Err(errno::ISDIR),
+            Descriptor::Stdin => return Err(errno::BADF),
+        }
+        write_u32(mem, nwritten_ptr, bytes.len() as u32)
+    }
+
+    fn fd_pwrite(&mut self, mem: &mut [u8], fd: i32, iovs: i32, iovs_len: i32, offset: i64, nwritten_ptr: i32) -> WasiResult {
+        let bytes = gather(mem, iovs, iovs_len)?;
+        match self.fds.get(&(fd as u32)).ok_or(errno::BADF)? {
+            Descriptor::File { path, .. } => {
+                let path = path.clone();
+                self.write_at(&path, offset as u64, &bytes)?;
+            }
+            Descriptor::Dir { .. } => return Err(errno::ISDIR),
+            _ => return Err(errno::SPIPE),
+        }
+        write_u32(mem, nwritten_ptr, bytes.len() as u32)
+    }
+
+    // Writes into a file, growing it as needed; returns the end offset
+    fn write_at(&mut self, path: &str, offset: u64, bytes: &[u8]) -> Result<u64, i32> {
+        let offset = usize::try_from(offset).map_err(|_| errno::INVAL)?;
+        let end = offset.checked_add(bytes.len()).ok_or(errno::OVERFLOW)?;
+        let growth = end.saturating_sub(self.file_mut(path)?.len());
+        if self.fs_bytes() + growth > MAX_FS_BYTES {
+            return Err(errno::NOSPC);
+        }
+        let data = self.file_mut(path)?;
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[offset..end].copy_from_slice(bytes);
+        Ok(end as u64)
+    }
+
+    fn fd_seek(&mut self, mem: &mut [u8], fd: i32, offset: i64, whence: i32, newoffset_ptr: i32) -> WasiResult {
+        let len = match self.fds.get(&(fd as u32)).ok_or(errno::BADF)? {
+            Descriptor::File { path, .. } => match self.nodes.get(path) {
+                Some(Node::File(data)) => data.len() as i64,
+                _ => return Err(errno::NOENT),
+            },
+            Descriptor::Dir { .. } => return Err(errno::ISDIR),
+            _ => return Err(errno::SPIPE),
+        };
+        let Some(Descriptor::File { pos, .. }) = self.fds.get_mut(&(fd as u32)) else {
+            return Err(errno::BADF);
+        };
+        let base = match whence {
+            0 => 0,
+            1 => *pos as i64,
+            2 => len,
+            _ => return Err(errno::INVAL),
+        };
+        let target = base.checked_add(offset).filter(|target| *target >= 0).ok_or(errno::INVAL)?;
+        *pos = target as u64;
+        write_u64(mem, newoffset_ptr, target as u64)
+    }
+
+    fn fd_tell(&self, mem: &mut [u8], fd: i32, ptr: i32) -> WasiResult {
+        match self.fds.get(&(fd as u32)).ok_or(errno::BADF)? {
+            Descriptor::File { pos, .. } => write_u64(mem, ptr, *pos),
+            _ => Err(errno::SPIPE),
+        }
+    }
+
+    fn fd_readdir(&self, mem: &mut [u8], fd: i32, buf: i32, buf_len: i32, cookie: i64, bufused_ptr: i32) -> WasiResult {
+        let dir = match self.fds.get(&(fd as u32)).ok_or(errno::BADF)? {
+            Descriptor::Dir { path } => path,
+            _ => return Err(errno::NOTDIR),
+        };
+        let mut entries = vec![(".".to_string(), inode(dir), filetype::DIRECTORY)];
+        entries.push(("..".to_string(), inode(dir), filetype::DIRECTORY));
+        for child in self.children(dir) {
+            let kind = if self.is_dir(child) { filetype::DIRECTORY } else { filetype::REGULAR_FILE };
+            entries.push((child.rsplit('/').next().unwrap().to_string(), inode(child), kind));
+        }
+
+        // Entries are written back to back; the last one may be cut off,
+        // which tells the caller to come back with a bigger buffer
+        let mut out = Vec::new();
+        for (index, (name, ino, kind)) in entries.iter().enumerate().skip(cookie.max(0) as usize) {
+            out.extend_from_slice(&(index as u64 + 1).to_le_bytes());
+            out.extend_from_slice(&ino.to_le_bytes());
+            out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            out.extend_from_slice(&[*kind, 0, 0, 0]);
+            out.extend_from_slice(name.as_bytes());
+            if out.len() >= buf_len.max(0) as usize {
+                break;
+            }
+        }
+        out.truncate(buf_len.max(0) as usize);
+        slice_mut(mem, buf, out.len())?.copy_from_slice(&out);
+        write_u32(mem, bufused_ptr, out.len() as u32)
+    }
+
+    fn fd_renumber(&mut self, from: i32, to: i32) -> WasiResult {
+        if !self.fds.contains_key(&(to as u32)) {
+            return Err(errno::BADF);
+        }
+        let descriptor = self.fds.remove(&(from as u32)).ok_or(errno::BADF)?;
+        self.fds.insert(to as u32, descriptor);
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn path_open(&mut self, mem: &mut [u8], fd: i32, path_ptr: i32, path_len: i32, oflags: i32, fdflags: i32, fd_ptr: i32) -> WasiResult {
+        let path = self.resolve(fd, mem, path_ptr, path_len)?;
+        let descriptor = match self.nodes.get(&path) {
+            Some(_) if oflags & OFLAGS_CREAT != 0 && oflags & OFLAGS_EXCL != 0 => return Err(errno::EXIST),
+            Some(Node::Dir) if oflags & OFLAGS_TRUNC != 0 => return Err(errno::ISDIR),
+            Some(Node::Dir) => Descriptor::Dir { path },
+            Some(Node::File(_)) if oflags & OFLAGS_DIRECTORY != 0 => return Err(errno::NOTDIR),
+            Some(Node::File(_)) => {
+                if oflags & OFLAGS_TRUNC != 0 {
+                    self.file_mut(&path)?.clear();
+                }
+                Descriptor::File { path, pos: 0, append: fdflags & FDFLAGS_APPEND != 0 }
+            }
+            None if oflags & OFLAGS_CREAT == 0 => return Err(errno::NOENT),
+            None => {
+                match self.nodes.get(parent(&path)) {
+                    Some(Node::Dir) => {}
+                    Some(Node::File(_)) => return Err(errno::NOTDIR),
+                    None => return Err(errno::NOENT),
+                }
+                self.nodes.insert(path.clone(), Node::File(Vec::new()));
+                Descriptor::File { path, pos: 0, append: fdflags & FDFLAGS_APPEND != 0 }
+            }
+        };
+        let new_fd = self.next_fd();
+        self.fds.insert(new_fd, descriptor);
+        write_u32(mem, fd_ptr, new_fd)
+    }
+
+    fn path_create_directory(&mut self, mem: &mut [u8], fd: i32, path_ptr: i32, path_len: i32) -> WasiResult {
+        let path = self.resolve(fd, mem, path_ptr, path_len)?;
+        if self.nodes.contains_key(&path) {
+            return Err(errno::EXIST);
+        }
+        if !self.is_dir(parent(&path)) {
+            return Err(errno::NOENT);
+        }
+        self.nodes.insert(path, Node::Dir);
+        Ok(())
+    }
+
+    fn path_filestat_get(&self, mem: &mut [u8], fd: i32, path_ptr: i32, path_len: i32, ptr: i32) -> WasiResult {
+        let path = self.resolve(fd, mem, path_ptr, path_len)?;
+        self.write_filestat(mem, &path, ptr)
+    }
+
+    fn path_exists(&self, mem: &mut [u8], fd: i32, path_ptr: i32, path_len: i32) -> WasiResult {
+        let path = self.resolve(fd, mem, path_ptr, path_len)?;
+        self.nodes.get(&path).map(|_| ()).ok_or(errno::NOENT)
+    }
+
+    fn path_remove_directory(&mut self, mem: &mut [u8], fd: i32, path_ptr: i32, path_len: i32) -> WasiResult {
+        let path = self.resolve(fd, mem, path_ptr, path_len)?;
+        match self.nodes.get(&path).ok_or(errno::NOENT)? {
+            Node::File(_) => Err(errno::NOTDIR),
+            Node::Dir if path == "/" => Err(errno::ACCES),
+            Node::Dir if !self.children(&path).is_empty() => Err(errno::NOTEMPTY),
+            Node::Dir => {
+                self.nodes.remove(&path);
+                Ok(())
+            }
+        }
+    }
+
+    fn path_unlink_file(&mut self, mem: &mut [u8], fd: i32, path_ptr: i32, path_len: i32) -> WasiResult {
+        let path = self.resolve(fd, mem, path_ptr, path_len)?;
+        match self.nodes.get(&path).ok_or(errno::NOENT)? {
+            Node::Dir => Err(errno::ISDIR),
+            Node::File(_) => {
+                self.nodes.remove(&path);
+                Ok(())
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn path_rename(&mut self, mem: &mut [u8], fd: i32, old_ptr: i32, old_len: i32, new_fd: i32, new_ptr: i32, new_len: i32) -> WasiResult {
+        let from = self.resolve(fd, mem, old_ptr, old_len)?;
+        let to = self.resolve(new_fd, mem, new_ptr, new_len)?;
+        let moving_dir = match self.nodes.get(&from) {
+            None => return Err(errno::NOENT),
+            Some(_) if from == "/" || to == "/" => return Err(errno::ACCES),
+            Some(Node::Dir) => true,
+            Some(Node::File(_)) => false,
+        };
+        if from == to {
+            return Ok(());
+        }
+        if moving_dir && to.starts_with(&format!("{}/", from)) {
+            return Err(errno::INVAL);
+        }
+        if !self.is_dir(parent(&to)) {
+            return Err(errno::NOENT);
+        }
+        match self.nodes.get(&to) {
+            Some(Node::Dir) if !moving_dir => return Err(errno::ISDIR),
+            Some(Node::Dir) if !self.children(&to).is_empty() => return Err(errno::NOTEMPTY),
+            Some(Node::File(_)) if moving_dir => return Err(errno::NOTDIR),
+            _ => {}
+        }
+
+        let prefix = format!("{}/", from);
+        let moved: Vec<String> = self
+            .nodes
+            .keys()
+            .filter(|path| **path == from || path.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for old in moved {
+            let node = self.nodes.remove(&old).unwrap();
+            self.nodes.insert(format!("{}{}", to, &old[from.len()..]), node);
+        }
+        for descriptor in self.fds.values_mut() {
+            if let Descriptor::File { path, .. } | Descriptor::Dir { path } = descriptor {
+                if *path == from || path.starts_with(&prefix) {
+                    *path = format!("{}{}", to, &path[from.len()..]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Clock and fd subscriptions all complete at once; nothing here blocks
+    fn poll_oneoff(&self, mem: &mut [u8], subscriptions: i32, events: i32, count: i32, nevents_ptr: i32) -> WasiResult {
+        if count <= 0 {
+            return Err(errno::INVAL);
+        }
+        for index in 0..count {
+            let subscription = slice(mem, add_offset(subscriptions, index as u64 * 48)?, 48)?;
+            let userdata = u64::from_le_bytes(subscription[0..8].try_into().unwrap());
+            let tag = subscription[8];
+            let mut event = [0u8; 32];
+            event[0..8].copy_from_slice(&userdata.to_le_bytes());
+            event[10] = tag;
+            slice_mut(mem, add_offset(events, index as u64 * 32)?, 32)?.copy_from_slice(&event);
+        }
+        write_u32(mem, nevents_ptr, count as u32)
+    }
+
+    fn random_get(&mut self, mem: &mut [u8], buf: i32, len: i32) -> WasiResult {
+        let len = usize::try_from(len).map_err(|_| errno::INVAL)?;
+        let buf = slice_mut(mem, buf, len)?;
+        match &mut self.fixed_rng {
+            Some(rng) => rng.fill_bytes(buf),
+            None => rand::thread_rng().fill_bytes(buf),
+        }
+        Ok(())
+    }
+}
+
+// Adds the preview1 functions to a linker
+pub fn define(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+    type C<'a> = Caller<'a, HostState>;
+    let m = WASI_MODULE;
+    linker.func_wrap(m, "args_get", |mut c: C, argv: i32, buf: i32| call(&mut c, |mem, ctx| ctx.args_get(mem, argv, buf)))?;
+    linker.func_wrap(m, "args_sizes_get", |mut c: C, count: i32, size: i32| call(&mut c, |mem, ctx| ctx.args_sizes_get(mem, count, size)))?;
+    linker.func_wrap(m, "environ_get", |mut c: C, environ: i32, buf: i32| call(&mut c, |mem, ctx| ctx.environ_get(mem, environ, buf)))?;
+    linker.func_wrap(m, "environ_sizes_get", |mut c: C, count: i32, size: i32| call(&mut c, |mem, ctx| ctx.environ_sizes_get(mem, count, size)))?;
+    linker.func_wrap(m, "clock_res_get", |mut c: C, id: i32, ptr: i32| call(&mut c, |mem, ctx| ctx.clock_res_get(mem, id, ptr)))?;
+    linker.func_wrap(m, "clock_time_get", |mut c: C, id: i32, _precision: i64, ptr: i32| call(&mut c, |mem, ctx| ctx.clock_time_get(mem, id, ptr)))?;
+    linker.func_wrap(m, "fd_advise", |mut c: C, fd: i32, _offset: i64, _len: i64, _advice: i32| call(&mut c, |_, ctx| ctx.fd_exists(fd)))?;
+    linker.func_wrap(m, "fd_allocate", |mut c: C, fd: i32, _offset: i64, _len: i64| call(&mut c, |_, ctx| ctx.fd_exists(fd)))?;
+    linker.func_wrap(m, "fd_close", |mut c: C, fd: i32| call(&mut c, |_, ctx| ctx.fd_close(fd)))?;
+    linker.func_wrap(m, "fd_datasync", |mut c: C, fd: i32| call(&mut c, |_, ctx| ctx.fd_exists(fd)))?;
+    linker.func_wrap(m, "fd_fdstat_get", |mut c: C, fd: i32, ptr: i32| call(&mut c, |mem, ctx| ctx.fd_fdstat_get(mem, fd, ptr)))?;
+    linker.func_wrap(m, "fd_fdstat_set_flags", |mut c: C, fd: i32, flags: i32| call(&mut c, |_, ctx| ctx.fd_fdstat_set_flags(fd, flags)))?;
+    linker.func_wrap(m, "fd_fdstat_set_rights", |mut c: C, fd: i32, _base: i64, _inheriting: i64| call(&mut c, |_, ctx| ctx.fd_exists(fd)))?;
+    linker.func_wrap(m, "fd_filestat_get", |mut c: C, fd: i32, ptr: i32| call(&mut c, |mem, ctx| ctx.fd_filestat_get(mem, fd, ptr)))?;
+    linker.func_wrap(m, "fd_filestat_set_size", |mut c: C, fd: i32, size: i64| call(&mut c, |_, ctx| ctx.fd_filestat_set_size(fd, size)))?;
+    linker.func_wrap(m, "fd_filestat_set_times", |mut c: C, fd: i32, _atim: i64, _mtim: i64, _flags: i32| call(&mut c, |_, ctx| ctx.fd_exists(fd)))?;
+    linker.func_wrap(m, "fd_pread", |mut c: C, fd: i32, iovs: i32, iovs_len: i32, offset: i64, nread: i32| {
+        call(&mut c, |mem, ctx| ctx.fd_pread(mem, fd, iovs, iovs_len, offset, nread))
+    })?;
+    linker.func_wrap(m, "fd_prestat_get", |mut c: C, fd: i32, ptr: i32| call(&mut c, |mem, ctx| ctx.fd_prestat_get(mem, fd, ptr)))?;
+    linker.func_wrap(m, "fd_prestat_dir_name", |mut c: C, fd: i32, ptr: i32, len: i32| call(&mut c, |mem, ctx| ctx.fd_prestat_dir_name(mem, fd, ptr, len)))?;
+    linker.func_wrap(m, "fd_pwrite", |mut c: C, fd: i32, iovs: i32, iovs_len: i32, offset: i64, nwritten: i32| {
+        call(&mut c, |mem, ctx| ctx.fd_pwrite(mem, fd, iovs, iovs_len, offset, nwritten))
+    })?;
+    linker.func_wrap(m, "fd_read", |mut c: C, fd: i32, iovs: i32, iovs_len: i32, nread: i32| {
+        call(&mut c, |mem, ctx| ctx.fd_read(mem, fd, iovs, iovs_len, nread))
+    })?;
+    linker.func_wrap(m, "fd_readdir", |mut c: C, fd: i32, buf: i32, buf_len: i32, cookie: i64, bufused: i32| {
+        call(&mut c, |mem, ctx| ctx.fd_readdir(mem, fd, buf, buf_len, cookie, bufused))
+    })?;
+    linker.func_wrap(m, "fd_renumber", |mut c: C, from: i32, to: i32| call(&mut c, |_, ctx| ctx.fd_renumber(from, to)))?;
+    linker.func_wrap(m, "fd_seek", |mut c: C, fd: i32, offset: i64, whence: i32, newoffset: i32| {
+        call(&mut c, |mem, ctx| ctx.fd_seek(mem, fd, offset, whence, newoffset))
+    })?;
+    linker.func_wrap(m, "fd_sync", |mut c: C, fd: i32| call(&mut c, |_, ctx| ctx.fd_exists(fd)))?;
+    linker.func_wrap(m, "fd_tell", |mut c: C, fd: i32, ptr: i32| call(&mut c, |mem, ctx| ctx.fd_tell(mem, fd, ptr)))?;
+    linker.func_wrap(m, "fd_write", |mut c: C, fd: i32, iovs: i32, iovs_len: i32, nwritten: i32| {
+        call(&mut c, |mem, ctx| ctx.fd_write(mem, fd, iovs, iovs_len, nwritten))
+    })?;
+    linker.func_wrap(m, "path_create_directory", |mut c: C, fd: i32, path: i32, len: i32| {
+        call(&mut c, |mem, ctx| ctx.path_create_directory(mem, fd, path, len))
+    })?;
+    linker.func_wrap(m, "path_filestat_get", |mut c: C, fd: i32, _flags: i32, path: i32, len: i32, ptr: i32| {
+        call(&mut c, |mem, ctx| ctx.path_filestat_get(mem, fd, path, len, ptr))
+    })?;
+    linker.func_wrap(m, "path_filestat_set_times", |mut c: C, fd: i32, _flags: i32, path: i32, len: i32, _atim: i64, _mtim: i64, _fst_flags: i32| {
+        call(&mut c, |mem, ctx| ctx.path_exists(mem, fd, path, len))
+    })?;
+    linker.func_wrap(m, "path_link", |_: C, _: i32, _: i32, _: i32, _: i32, _: i32, _: i32, _: i32| errno::NOTSUP)?;
+    linker.func_wrap(m, "path_open", |mut c: C, fd: i32, _dirflags: i32, path: i32, len: i32, oflags: i32, _base: i64, _inheriting: i64, fdflags: i32, fd_ptr: i32| {
+        call(&mut c, |mem, ctx| ctx.path_open(mem, fd, path, len, oflags, fdflags, fd_ptr))
+    })?;
+    linker.func_wrap(m, "path_readlink", |mut c: C, fd: i32, path: i32, len: i32, _buf: i32, _buf_len: i32, _bufused: i32| {
+        // There are no symlinks, so every existing path is "not a link"
+        call(&mut c, |mem, ctx| ctx.path_exists(mem, fd, path, len).and(Err(errno::INVAL)))
+    })?;
+    linker.func_wrap(m, "path_remove_directory", |mut c: C, fd: i32, path: i32, len: i32| {
+        call(&mut c, |mem, ctx| ctx.path_remove_directory(mem, fd, path, len))
+    })?;
+    linker.func_wrap(m, "path_rename", |mut c: C, fd: i32, old: i32, old_len: i32, new_fd: i32, new: i32, new_len: i32| {
+        call(&mut c, |mem, ctx| ctx.path_rename(mem, fd, old, old_len, new_fd, new, new_len))
+    })?;
+    linker.func_wrap(m, "path_symlink", |_: C, _: i32, _: i32, _: i32, _: i32, _: i32| errno::NOTSUP)?;
+    linker.func_wrap(m, "path_unlink_file", |mut c: C, fd: i32, path: i32, len: i32| {
+        call(&mut c, |mem, ctx| ctx.path_unlink_file(mem, fd, path, len))
+    })?;
+    linker.func_wrap(m, "poll_oneoff", |mut c: C, subscriptions: i32, events: i32, count: i32, nevents: i32| {
+        call(&mut c, |mem, ctx| ctx.poll_oneoff(mem, subscriptions, events, count, nevents))
+    })?;
+    linker.func_wrap(m, "proc_exit", |code: i32| -> Result<(), wasmi::Error> { Err(wasmi::Error::i32_exit(code)) })?;
+    linker.func_wrap(m, "proc_raise", |_: C, _signal: i32| errno::NOSYS)?;
+    linker.func_wrap(m, "random_get", |mut c: C, buf: i32, len: i32| call(&mut c, |mem, ctx| ctx.random_get(mem, buf, len)))?;
+    linker.func_wrap(m, "sched_yield", || errno::SUCCESS)?;
+    linker.func_wrap(m, "sock_accept", |_: C, _: i32, _: i32, _: i32| errno::NOTSUP)?;
+    linker.func_wrap(m, "sock_recv", |_: C, _: i32, _: i32, _: i32, _: i32, _: i32, _: i32| errno::NOTSUP)?;
+    linker.func_wrap(m, "sock_send", |_: C, _: i32, _: i32, _: i32, _: i32, _: i32| errno::NOTSUP)?;
+    linker.func_wrap(m, "sock_shutdown", |_: C, _: i32, _: i32| errno::NOTSUP)?;
+    Ok(())
+}
+
+// Runs a WASI call against the caller's memory and context
+fn call(caller: &mut Caller<'_, HostState>, f: impl FnOnce(&mut [u8], &mut WasiCtx) -> WasiResult) -> Result<i32, wasmi::Error> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmi::Error::new("module does not export its memory as \"memory\""))?;
+    let (mem, state) = memory.data_and_store_mut(caller);
+    Ok(f(mem, &mut state.wasi).err().unwrap_or(errno::SUCCESS))
+}
+
+// Joins `path` onto the directory `base`; WASI paths are relative and may
+// not climb above the preopened root
+fn normalize(base: &str, path: &str) -> Result<String, i32> {
+    if path.starts_with('/') {
+        return Err(errno::NOTCAPABLE);
+    }
+    let mut components: Vec<&str> = base.split('/').filter(|c| !c.is_empty()).collect();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop().ok_or(errno::NOTCAPABLE)?;
+            }
+            name => components.push(name),
+        }
+    }
+    Ok(format!("/{}", components.join("/")))
+}
+
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(index) => &path[..index],
+    }
+}
+
+// Stable inode numbers derived from the path (FNV-1a)
+fn inode(path: &str) -> u64 {
+    path.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+fn capture(stream: &mut Vec<u8>, bytes: &[u8]) {
+    let room = MAX_CAPTURE_BYTES.saturating_sub(stream.len());
+    stream.extend_from_slice(&bytes[..bytes.len().min(room)]);
+}
+
+fn read_at(nodes: &BTreeMap<String, Node>, path: &str, offset: u64, len: usize) -> Result<Vec<u8>, i32> {
+    match nodes.get(path) {
+        Some(Node::File(data)) => {
+            let start = (offset as usize).min(data.len());
+            Ok(data[start..(start + len).min(data.len())].to_vec())
+        }
+        Some(Node::Dir) => Err(errno::ISDIR),
+        None => Err(errno::NOENT),
+    }
+}
+
+fn write_strings(mem: &mut [u8], strings: &[String], ptrs: i32, buf: i32) -> WasiResult {
+    let mut offset = buf;
+    for (index, string) in strings.iter().enumerate() {
+        write_u32(mem, ptrs.wrapping_add(index as i32 * 4), offset as u32)?;
+        let target = slice_mut(mem, offset, string.len() + 1)?;
+        target[..string.len()].copy_from_slice(string.as_bytes());
+        target[string.len()] = 0;
+        offset = offset.wrapping_add(string.len() as i32 + 1);
+    }
+    Ok(())
+}
+
+fn write_string_sizes(mem: &mut [u8], strings: &[String], count_ptr: i32, size_ptr: i32) -> WasiResult {
+    write_u32(mem, count_ptr, strings.len() as u32)?;
+    write_u32(mem, size_ptr, strings.iter().map(|s| s.len() as u32 + 1).sum())
+}
+
+fn write_filestat(mem: &mut [u8], ptr: i32, ino: u64, kind: u8, size: u64) -> WasiResult {
+    let mut stat = [0u8; 64];
+    stat[8..16].copy_from_slice(&ino.to_le_bytes());
+    stat[16] = kind;
+    stat[24..32].copy_from_slice(&1u64.to_le_bytes());
+    stat[32..40].copy_from_slice(&size.to_le_bytes());
+    slice_mut(mem, ptr, 64)?.copy_from_slice(&stat);
+    Ok(())
+}
+
+// (address, length) pairs of an iovec array
+fn read_iovecs(mem: &[u8], iovs: i32, count: i32) -> Result<Vec<(u32, u32)>, i32> {
+    (0..count.max(0))
+        .map(|index| {
+            let entry = slice(mem, add_offset(iovs, index as u64 * 8)?, 8)?;
+            Ok((
+                u32::from_le_bytes(entry[0..4].try_into().unwrap()),
+                u32::from_le_bytes(entry[4..8].try_into().unwrap()),
+            ))
+        })
+        .collect()
+}
+
+fn gather(mem: &[u8], iovs: i32, count: i32) -> Result<Vec<u8>, i32> {
+    let mut bytes = Vec::new();
+    for (ptr, len) in read_iovecs(mem, iovs, count)? {
+        bytes.extend_from_slice(slice(mem, ptr as i32, len as usize)?);
+    }
+    Ok(bytes)
+}
+
+fn scatter(mem: &mut [u8], iovecs: &[(u32, u32)], mut bytes: &[u8]) -> WasiResult {
+    for (ptr, len) in iovecs {
+        let count = (*len as usize).min(bytes.len());
+        slice_mut(mem, *ptr as i32, count)?.copy_from_slice(&bytes[..count]);
+        bytes = &bytes[count..];
+    }
+    Ok(())
+}
+
+// The address `offset` bytes past `ptr`; guest addresses are unsigned 32-bit
+fn add_offset(ptr: i32, offset: u64) -> Result<i32, i32> {
+    u32::try_from(ptr as u32 as u64 + offset).map(|ptr| ptr as i32).map_err(|_| errno::FAULT)
+}
+
+fn slice(mem: &[u8], ptr: i32, len: usize) -> Result<&[u8], i32> {
+    let start = ptr as u32 as usize;
+    mem.get(start..start.checked_add(len).ok_or(errno::FAULT)?).ok_or(errno::FAULT)
+}
+
+fn slice_mut(mem: &mut [u8], ptr: i32, len: usize) -> Result<&mut [u8], i32> {
+    let start = ptr as u32 as usize;
+    mem.get_mut(start..start.checked_add(len).ok_or(errno::FAULT)?).ok_or(errno::FAULT)
+}
+
+fn read_str(mem: &[u8], ptr: i32, len: i32) -> Result<&str, i32> {
+    let bytes = slice(mem, ptr, usize::try_from(len).map_err(|_| errno::INVAL)?)?;
+    std::str::from_utf8(bytes).map_err(|_| errno::ILSEQ)
+}
+
+fn write_u8(mem: &mut [u8], ptr: i32, value: u8) -> WasiResult {
+    slice_mut(mem, ptr, 1)?[0] = value;
+    Ok(())
+}
+
+fn write_u16(mem: &mut [u8], ptr: i32, value: u16) -> WasiResult {
+    slice_mut(mem, ptr, 2)?.copy_from_slice(&value.to_le_bytes());
+    Ok(())
+}
+
+fn write_u32(mem: &mut [u8], ptr: i32, value: u32) -> WasiResult {
+    slice_mut(mem, ptr, 4)?.copy_from_slice(&value.to_le_bytes());
+    Ok(())
+}
+
+fn write_u64(mem: &mut [u8], ptr: i32, value: u64) -> WasiResult {
+    slice_mut(mem, ptr, 8)?.copy_from_slice(&value.to_le_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx_with_files(files: &[(&str, &str)]) -> WasiCtx {
+        let files = files.iter().map(|(path, contents)| (path.to_string(), contents.to_string())).collect();
+        WasiCtx::new(vec![], &BTreeMap::new(), vec![], &files).unwrap()
+    }
+
+    // Writes `text` into a scratch memory at `ptr` and returns its length
+    fn put(mem: &mut [u8], ptr: i32, text: &str) -> i32 {
+        mem[ptr as usize..ptr as usize + text.len()].copy_from_slice(text.as_bytes());
+        text.len() as i32
+    }
+
+    #[test]
+    fn test_pointers_near_the_end_of_the_address_space() {
+        assert_eq!(add_offset(i32::MAX, 16), Ok(i32::MIN + 15));
+        assert_eq!(add_offset(-1, 1), Err(errno::FAULT));
+        assert_eq!(add_offset(-16, i32::MAX as u64 * 48), Err(errno::FAULT));
+
+        let ctx = ctx_with_files(&[]);
+        let mut mem = vec![0u8; 256];
+        assert_eq!(ctx.fd_fdstat_get(&mut mem, 0, i32::MAX), Err(errno::FAULT));
+        assert_eq!(ctx.fd_prestat_get(&mut mem, PREOPEN_FD as i32, i32::MAX), Err(errno::FAULT));
+        assert_eq!(ctx.poll_oneoff(&mut mem, i32::MAX, 0, 2, 0), Err(errno::FAULT));
+        assert_eq!(read_iovecs(&mem, i32::MAX, 2), Err(errno::FAULT));
+    }
+
+    #[test]
+    fn test_normalize_stays_inside_root() {
+        assert_eq!(normalize("/", "a/./b//c").unwrap(), "/a/b/c");
+        assert_eq!(normalize("/a", "../b").unwrap(), "/b");
+        assert_eq!(normalize("/", "..").unwrap_err(), errno::NOTCAPABLE);
+        assert_eq!(normalize("/", "/etc/passwd").unwrap_err(), errno::NOTCAPABLE);
+        assert_eq!(parent("/a/b"), "/a");
+        assert_eq!(parent("/a"), "/");
+    }
+
+    #[test]
+    fn test_new_creates_parent_directories() {
+        let ctx = ctx_with_files(&[("data/in.txt", "hello"), ("/top.txt", "")]);
+        assert!(ctx.is_dir("/data"));
+        assert_eq!(ctx.children("/"), vec!["/data", "/top.txt"]);
+
+        let clash = BTreeMap::from([("a".to_string(), String::new()), ("a/b".to_string(), String::new())]);
+        assert!(WasiCtx::new(vec![], &BTreeMap::new(), vec![], &clash).is_err());
+        let escape = BTreeMap::from([("../x".to_string(), String::new())]);
+        assert!(WasiCtx::new(vec![], &BTreeMap::new(), vec![], &escape).is_err());
+        let bad_env = BTreeMap::from([("A=B".to_string(), String::new())]);
+        assert!(WasiCtx::new(vec![], &bad_env, vec![], &BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let mut ctx = ctx_with_files(&[("in.txt", "input")]);
+        let mut mem = vec![0u8; 1024];
+        let len = put(&mut mem, 0, "out/result.txt");
+
+        // Missing parent directory
+        assert_eq!(ctx.path_open(&mut mem, 3, 0, len, OFLAGS_CREAT, 0, 100), Err(errno::NOENT));
+        ctx.path_create_directory(&mut mem, 3, 0, 3).unwrap();
+        ctx.path_open(&mut mem, 3, 0, len, OFLAGS_CREAT, 0, 100).unwrap();
+        let fd = u32::from_le_bytes(mem[100..104].try_into().unwrap()) as i32;
+        assert_eq!(fd, 4);
+
+        // One iovec at 200 pointing to "written" at 300
+        let written = put(&mut mem, 300, "written");
+        mem[200..204].copy_from_slice(&300u32.to_le_bytes());
+        mem[204..208].copy_from_slice(&(written as u32).to_le_bytes());
+        ctx.fd_write(&mut mem, fd, 200, 1, 208).unwrap();
+        assert_eq!(u32::from_le_bytes(mem[208..212].try_into().unwrap()), 7);
+
+        ctx.fd_seek(&mut mem, fd, 0, 0, 216).unwrap();
+        mem[200..204].copy_from_slice(&400u32.to_le_bytes());
+        mem[204..208].copy_from_slice(&4u32.to_le_bytes());
+        ctx.fd_read(&mut mem, fd, 200, 1, 208).unwrap();
+        assert_eq!(&mem[400..404], b"writ");
+        ctx.fd_close(fd).unwrap();
+        assert_eq!(ctx.fd_close(fd), Err(errno::BADF));
+
+        let old = put(&mut mem, 500, "in.txt");
+        let new = put(&mut mem, 600, "out/moved.txt");
+        ctx.path_rename(&mut mem, 3, 500, old, 3, 600, new).unwrap();
+        assert!(matches!(ctx.nodes.get("/out/moved.txt"), Some(Node::File(data)) if data == b"input"));
+        assert_eq!(ctx.path_remove_directory(&mut mem, 3, 0, 3), Err(errno::NOTEMPTY));
+    }
+
+    #[test]
+    fn test_stdio() {
+        let mut ctx = WasiCtx::new(vec![], &BTreeMap::new(), b"stdin data".to_vec(), &BTreeMap::new()).unwrap();
+        let mut mem = vec![0u8; 1024];
+        mem[0..4].copy_from_slice(&100u32.to_le_bytes());
+        mem[4..8].copy_from_slice(&5u32.to_le_bytes());
+        ctx.fd_read(&mut mem, 0, 0, 1, 8).unwrap();
+        assert_eq!(&mem[100..105], b"stdin");
+
+        ctx.fd_write(&mut mem, 1, 0, 1, 8).unwrap();
+        ctx.fd_write(&mut mem, 2, 0, 1, 8).unwrap();
+        assert_eq!(ctx.stdout, b"stdin");
+        assert_eq!(ctx.stderr, b"stdin");
+        assert_eq!(ctx.fd_seek(&mut mem, 1, 0, 0, 16), Err(errno::SPIPE));
+    }
+
+    #[test]
+    fn test_args_and_environ() {
+        let env = BTreeMap::from([("KEY".to_string(), "value".to_string())]);
+        let ctx = WasiCtx::new(vec!["prog".to_string(), "-v".to_string()], &env, vec![], &BTreeMap::new()).unwrap();
+        let mut mem = vec![0u8; 1024];
+        ctx.args_sizes_get(&mut mem, 0, 4).unwrap();
+        assert_eq!(u32::from_le_bytes(mem[0..4].try_into().unwrap()), 2);
+        assert_eq!(u32::from_le_bytes(mem[4..8].try_into().unwrap()), 8);
+        ctx.args_get(&mut mem, 16, 100).unwrap();
+        assert_eq!(&mem[100..108], b"prog\0-v\0");
+        assert_eq!(u32::from_le_bytes(mem[20..24].try_into().unwrap()), 105);
+
+        ctx.environ_get(&mut mem, 16, 200).unwrap();
+        assert_eq!(&mem[200..210], b"KEY=value\0");
+        assert_eq!(ctx.args_get(&mut mem, 16, 1020), Err(errno::FAULT));
+    }
+
+    #[test]
+    fn test_deterministic_clocks_and_random() {
+        let mut mem = vec![0u8; 64];
+        let mut draws = Vec::new();
+        for _ in 0..2 {
+            let mut ctx = WasiCtx::default();
+            ctx.make_deterministic();
+            ctx.clock_time_get(&mut mem, 0, 0).unwrap();
+            assert_eq!(&mem[0..8], &[0; 8]);
+            ctx.random_get(&mut mem, 16, 16).unwrap();
+            draws.push(mem[16..32].to_vec());
+        }
+        assert_eq!(draws[0], draws[1]);
+        assert_ne!(draws[0], vec![0; 16]);
+    }
+}
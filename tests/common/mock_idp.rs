@@ -0,0 +1,120 @@
+// Minimal OpenID Connect provider for integration tests.
+//
+// Serves discovery, a JWKS with the fixture RSA key, an /authorize endpoint
+// that immediately redirects back with a code (as if the user had signed in),
+// and a /token endpoint that checks PKCE and returns a signed ID token.
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tide::http::Url;
+
+const PRIVATE_KEY: &str = include_str!("../fixtures/keys/rsa_private.pem");
+const JWKS: &str = include_str!("../fixtures/keys/rsa_public.jwks.json");
+pub const CLIENT_ID: &str = "learn-rust-crud";
+pub const CLIENT_SECRET: &str = "mock-secret";
+
+// The identity the mock provider signs in
+#[derive(Clone)]
+pub struct MockIdentity {
+    pub subject: String,
+    pub username: String,
+    pub groups: Vec<String>,
+}
+
+#[derive(Clone)]
+struct MockIdp {
+    issuer: String,
+    identity: MockIdentity,
+    codes: Arc<Mutex<HashMap<String, (String, String)>>>, // code -> (nonce, code_challenge)
+}
+
+// Starts the provider on a free port and returns its issuer URL
+pub fn start_mock_idp(identity: MockIdentity) -> String {
+    let issuer = format!("http://127.0.0.1:{}", super::find_available_port());
+    let mut app = tide::with_state(MockIdp {
+        issuer: issuer.clone(),
+        identity,
+        codes: Arc::new(Mutex::new(HashMap::new())),
+    });
+
+    app.at("/.well-known/openid-configuration").get(|req: tide::Request<MockIdp>| async move {
+        let issuer = &req.state().issuer;
+        tide::Body::from_json(&serde_json::json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{}/authorize", issuer),
+            "token_endpoint": format!("{}/token", issuer),
+            "jwks_uri": format!("{}/jwks", issuer),
+        }))
+    });
+    app.at("/jwks").get(|_| async { Ok(tide::Body::from_string(JWKS.to_string())) });
+    app.at("/authorize").get(authorize);
+    app.at("/token").post(token);
+
+    let addr = issuer.trim_start_matches("http://").to_string();
+    async_std::task::spawn(async move { app.listen(addr).await });
+    for _ in 0..50 {
+        if ureq::get(&format!("{}/jwks", issuer)).call().is_ok() {
+            return issuer;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    panic!("❌ Mock identity provider did not start");
+}
+
+// Signs a token as the mock provider
+pub fn sign_idp_token(issuer: &str, identity: &MockIdentity, audience: &str, nonce: Option<&str>) -> String {
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some("mock-idp".to_string());
+    let claims = serde_json::json!({
+        "iss": issuer,
+        "sub": identity.subject,
+        "aud": audience,
+        "exp": chrono::Utc::now().timestamp() + 300,
+        "iat": chrono::Utc::now().timestamp(),
+        "nonce": nonce,
+        "preferred_username": identity.username,
+        "groups": identity.groups,
+    });
+    let key = EncodingKey::from_rsa_pem(PRIVATE_KEY.as_bytes()).expect("❌ Invalid fixture key");
+    encode(&header, &claims, &key).expect("❌ Failed to sign token")
+}
+
+async fn authorize(req: tide::Request<MockIdp>) -> tide::Result {
+    let params: HashMap<String, String> = req.query()?;
+    let param = |name: &str| params.get(name).cloned().unwrap_or_default();
+    if param("client_id") != CLIENT_ID || param("code_challenge_method") != "S256" {
+        return Ok(tide::Response::new(400));
+    }
+
+    let code = format!("code-{}", rand::random::<u64>());
+    req.state().codes.lock().unwrap().insert(code.clone(), (param("nonce"), param("code_challenge")));
+    let mut redirect = Url::parse(&param("redirect_uri"))?;
+    redirect.query_pairs_mut().append_pair("code", &code).append_pair("state", &param("state"));
+    Ok(tide::Redirect::new(redirect.as_str()).into())
+}
+
+async fn token(mut req: tide::Request<MockIdp>) -> tide::Result {
+    let form: HashMap<String, String> = req.body_form().await?;
+    let field = |name: &str| form.get(name).cloned().unwrap_or_default();
+    if field("client_id") != CLIENT_ID || field("client_secret") != CLIENT_SECRET {
+        return Ok(tide::Response::new(401));
+    }
+    let Some((nonce, challenge)) = req.state().codes.lock().unwrap().remove(&field("code")) else {
+        return Ok(tide::Response::new(400));
+    };
+    if URL_SAFE_NO_PAD.encode(Sha256::digest(field("code_verifier").as_bytes())) != challenge {
+        return Ok(tide::Response::new(400));
+    }
+
+    let state = req.state();
+    let id_token = sign_idp_token(&state.issuer, &state.identity, CLIENT_ID, Some(&nonce));
+    Ok(tide::Body::from_json(&serde_json::json!({
+        "access_token": "opaque-access-token",
+        "token_type": "Bearer",
+        "id_token": id_token,
+    }))?
+    .into())
+}
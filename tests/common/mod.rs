@@ -1,3 +1,8 @@
+// Shared by every integration test binary, and each one uses only part of it
+#![allow(dead_code)]
+
+pub mod mock_idp;
+
 use std::net::TcpListener;
 use std::time::Duration;
 
@@ -43,6 +48,11 @@ pub fn wait_for_server(base_url: &str, max_attempts: u32) -> bool {
 
 // Helper to start the server (synchronous)
 pub fn start_test_server() -> (String, std::process::Child) {
+    start_test_server_with_env(&[])
+}
+
+// Helper to start the server with extra environment variables
+pub fn start_test_server_with_env(envs: &[(&str, &str)]) -> (String, std::process::Child) {
     let port = find_available_port();
     let base_url = format!("http://127.0.0.1:{}", port);
     
@@ -53,8 +63,10 @@ pub fn start_test_server() -> (String, std::process::Child) {
     
     // Start server process
     let child = std::process::Command::new("cargo")
-        .args(&["run", "--bin", "learn-rust-crud"])
+        .args(["run", "--bin", "learn-rust-crud"])
         .env("SERVER_ADDR", format!("127.0.0.1:{}", port))
+        .env("DEV_MODE", "true") // Allow the default JWT secret
+        .envs(envs.iter().copied())
         .spawn()
         .expect("❌ Failed to start server");
     
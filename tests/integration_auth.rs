@@ -51,4 +51,262 @@ async fn test_login_invalid_credentials() {
     
     println!("✅ Login with invalid credentials correctly rejected");
     stop_test_server(child);
-} 
\ No newline at end of file
+} 
+
+#[async_std::test]
+async fn test_refresh_token_rotation_and_reuse() {
+    println!("\n🧪 Test: Refresh token rotation and reuse detection");
+    let (base_url, child) = start_test_server();
+    let login_data = LoginRequest {
+        username: "user1".to_string(),
+        password: "password123".to_string(),
+    };
+    let login_response: LoginResponse = ureq::post(&format!("{}/auth/login", base_url))
+        .send_json(ureq::json!(login_data))
+        .expect("❌ Login failed")
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    // Returns the status code and JSON body of a refresh call
+    let refresh = |token: &str| -> (u16, serde_json::Value) {
+        match ureq::post(&format!("{}/auth/refresh", base_url))
+            .send_json(ureq::json!({ "refresh_token": token })) {
+            Ok(response) => (response.status(), response.into_json().expect("❌ Failed to parse JSON")),
+            Err(ureq::Error::Status(status, response)) => (status, response.into_json().unwrap_or_default()),
+            Err(e) => panic!("❌ Unexpected error: {}", e),
+        }
+    };
+
+    // 1. A refresh returns a new refresh token
+    let (status, rotated) = refresh(&login_response.refresh_token);
+    assert_eq!(status, 200, "❌ Refresh failed");
+    assert_ne!(rotated["refresh_token"], login_response.refresh_token.as_str(), "❌ Refresh token was not rotated");
+
+    // 2. Reusing the old token is rejected with a distinct error code
+    let (status, body) = refresh(&login_response.refresh_token);
+    assert_eq!(status, 401, "❌ Reuse should return 401");
+    assert_eq!(body["error"], "refresh_token_reused", "❌ Incorrect error code");
+
+    // 3. The whole family is revoked, including the latest token
+    let (status, _) = refresh(rotated["refresh_token"].as_str().unwrap());
+    assert_eq!(status, 401, "❌ Rotated token should be revoked with its family");
+    println!("✅ Refresh token rotation and reuse detection tested successfully");
+    stop_test_server(child);
+}
+
+
+#[async_std::test]
+async fn test_logout_all_revokes_access_tokens() {
+    println!("\n🧪 Test: Logout everywhere revokes access tokens");
+    let (base_url, child) = start_test_server();
+    let login = || -> LoginResponse {
+        ureq::post(&format!("{}/auth/login", base_url))
+            .send_json(ureq::json!(LoginRequest {
+                username: "user2".to_string(),
+                password: "password456".to_string(),
+            }))
+            .expect("❌ Login failed")
+            .into_json()
+            .expect("❌ Failed to parse JSON")
+    };
+    let laptop = login();
+    let phone = login();
+
+    let response = ureq::post(&format!("{}/auth/logout-all", base_url))
+        .set("Authorization", &format!("Bearer {}", laptop.access_token))
+        .call()
+        .expect("❌ Logout-all failed");
+    assert_eq!(response.status(), 200, "❌ Logout-all should return 200");
+
+    // Access tokens from every session are now rejected
+    for session in [&laptop, &phone] {
+        match ureq::get(&format!("{}/data", base_url))
+            .set("Authorization", &format!("Bearer {}", session.access_token))
+            .call() {
+            Err(ureq::Error::Status(401, _)) => {}
+            other => panic!("❌ Expected 401 after logout-all, got {:?}", other.map(|r| r.status())),
+        }
+    }
+    println!("✅ Logout everywhere tested successfully");
+    stop_test_server(child);
+}
+
+
+#[async_std::test]
+async fn test_list_and_revoke_sessions() {
+    println!("\n🧪 Test: Listing and revoking individual sessions");
+    let (base_url, child) = start_test_server();
+    let login = |user_agent: &str| -> LoginResponse {
+        ureq::post(&format!("{}/auth/login", base_url))
+            .set("User-Agent", user_agent)
+            .send_json(ureq::json!(LoginRequest {
+                username: "user2".to_string(),
+                password: "password456".to_string(),
+            }))
+            .expect("❌ Login failed")
+            .into_json()
+            .expect("❌ Failed to parse JSON")
+    };
+    let laptop = login("laptop-browser");
+    let phone = login("phone-app");
+    let status = |result: Result<ureq::Response, ureq::Error>| match result {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response.status(),
+        Err(e) => panic!("❌ Request failed: {}", e),
+    };
+
+    let sessions: Vec<serde_json::Value> = ureq::get(&format!("{}/auth/sessions", base_url))
+        .set("Authorization", &format!("Bearer {}", laptop.access_token))
+        .call()
+        .expect("❌ Session listing failed")
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    assert_eq!(sessions.len(), 2, "❌ Both logins should be listed");
+    let current = sessions.iter().find(|session| session["current"] == true).expect("❌ No current session");
+    assert_eq!(current["user_agent"], "laptop-browser");
+    assert_eq!(current["ip"], "127.0.0.1");
+    let phone_session = sessions.iter().find(|session| session["user_agent"] == "phone-app").expect("❌ Phone session missing");
+
+    // Ending the phone session revokes its access and refresh tokens only
+    let revoked = ureq::delete(&format!("{}/auth/sessions/{}", base_url, phone_session["id"].as_str().unwrap()))
+        .set("Authorization", &format!("Bearer {}", laptop.access_token))
+        .call();
+    assert_eq!(status(revoked), 204, "❌ Session revocation should return 204");
+    let phone_data = ureq::get(&format!("{}/data", base_url)).set("Authorization", &format!("Bearer {}", phone.access_token)).call();
+    assert_eq!(status(phone_data), 401, "❌ Revoked session's access token should be rejected");
+    let phone_refresh = ureq::post(&format!("{}/auth/refresh", base_url)).send_json(ureq::json!({"refresh_token": phone.refresh_token}));
+    assert_eq!(status(phone_refresh), 401, "❌ Revoked session's refresh token should be rejected");
+    let laptop_data = ureq::get(&format!("{}/data", base_url)).set("Authorization", &format!("Bearer {}", laptop.access_token)).call();
+    assert_eq!(status(laptop_data), 200, "❌ Other sessions should keep working");
+
+    // Other users' sessions cannot be ended
+    let user1: LoginResponse = ureq::post(&format!("{}/auth/login", base_url))
+        .send_json(ureq::json!({"username": "user1", "password": "password123"}))
+        .expect("❌ Login failed")
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    let other = ureq::delete(&format!("{}/auth/sessions/{}", base_url, current["id"].as_str().unwrap()))
+        .set("Authorization", &format!("Bearer {}", user1.access_token))
+        .call();
+    assert_eq!(status(other), 404, "❌ Foreign session should look unknown");
+    println!("✅ Session listing tested successfully");
+    stop_test_server(child);
+}
+
+#[async_std::test]
+async fn test_api_key_scopes() {
+    println!("\n🧪 Test: Scoped API keys");
+    let (base_url, child) = start_test_server();
+    let login: LoginResponse = ureq::post(&format!("{}/auth/login", base_url))
+        .send_json(ureq::json!(LoginRequest {
+            username: "user1".to_string(),
+            password: "password123".to_string(),
+        }))
+        .expect("❌ Login failed")
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    let token = login.access_token;
+
+    let created: serde_json::Value = ureq::post(&format!("{}/auth/api-keys", base_url))
+        .set("Authorization", &format!("Bearer {}", token))
+        .send_json(ureq::json!({"name": "ci", "scopes": ["data:read"]}))
+        .expect("❌ API key creation failed")
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    let key = created["key"].as_str().expect("❌ Missing key").to_string();
+    assert!(created.get("key_hash").is_none(), "❌ Hash must not be returned");
+
+    let status = |request: ureq::Request| match request.set("Authorization", &format!("Bearer {}", key)).call() {
+        Ok(response) => response.status(),
+        Err(ureq::Error::Status(code, _)) => code,
+        Err(e) => panic!("❌ Request failed: {}", e),
+    };
+    assert_eq!(status(ureq::get(&format!("{}/data", base_url))), 200, "❌ data:read should allow listing");
+    assert_eq!(status(ureq::post(&format!("{}/execute/1", base_url))), 403, "❌ Missing execute scope should be 403");
+    assert_eq!(status(ureq::get(&format!("{}/auth/api-keys", base_url))), 403, "❌ API keys cannot manage API keys");
+
+    // Revoked keys stop working
+    let id = created["id"].as_str().expect("❌ Missing id");
+    let response = ureq::delete(&format!("{}/auth/api-keys/{}", base_url, id))
+        .set("Authorization", &format!("Bearer {}", token))
+        .call()
+        .expect("❌ Revocation failed");
+    assert_eq!(response.status(), 204, "❌ Revocation should return 204");
+    assert_eq!(status(ureq::get(&format!("{}/data", base_url))), 401, "❌ Revoked key should be 401");
+    println!("✅ API key scopes tested successfully");
+    stop_test_server(child);
+}
+
+
+#[async_std::test]
+async fn test_login_lockout_and_admin_unlock() {
+    println!("\n🧪 Test: Login lockout after repeated failures");
+    let (base_url, child) = start_test_server_with_env(&[("LOGIN_MAX_ATTEMPTS", "3")]);
+    // Returns the response whatever its status
+    let login = |username: &str, password: &str| -> ureq::Response {
+        match ureq::post(&format!("{}/auth/login", base_url)).send_json(ureq::json!(LoginRequest {
+            username: username.to_string(),
+            password: password.to_string(),
+        })) {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => panic!("❌ Request failed: {}", e),
+        }
+    };
+    let admin: LoginResponse = login("admin", "admin123").into_json().expect("❌ Failed to parse JSON");
+
+    for _ in 0..3 {
+        assert_eq!(login("user1", "wrong_password").status(), 401, "❌ Wrong password should be 401");
+    }
+    // Even the right password is refused while the account is locked
+    let locked = login("user1", "password123");
+    assert_eq!(locked.status(), 429, "❌ Locked account should be 429");
+    assert!(locked.header("Retry-After").is_some(), "❌ Missing Retry-After header");
+
+    let response = ureq::post(&format!("{}/admin/users/user1/unlock", base_url))
+        .set("Authorization", &format!("Bearer {}", admin.access_token))
+        .call()
+        .expect("❌ Unlock failed");
+    assert_eq!(response.status(), 204, "❌ Unlock should return 204");
+    assert_eq!(login("user1", "password123").status(), 200, "❌ Login after unlock failed");
+    println!("✅ Login lockout tested successfully");
+    stop_test_server(child);
+}
+
+
+#[async_std::test]
+async fn test_rs256_tokens_and_jwks() {
+    println!("\n🧪 Test: RS256 signing and JWKS endpoint");
+    let key_path = format!("{}/tests/fixtures/keys/rsa_private.pem", env!("CARGO_MANIFEST_DIR"));
+    let (base_url, child) = start_test_server_with_env(&[
+        ("JWT_ALGORITHM", "RS256"),
+        ("JWT_PRIVATE_KEY_PATH", &key_path),
+        ("JWT_KEY_ID", "test-rsa"),
+    ]);
+
+    let jwks: serde_json::Value = ureq::get(&format!("{}/.well-known/jwks.json", base_url))
+        .call()
+        .expect("❌ JWKS request failed")
+        .into_json()
+        .expect("❌ Failed to parse JWKS");
+    assert_eq!(jwks["keys"][0]["kid"], "test-rsa", "❌ Incorrect key id");
+    assert_eq!(jwks["keys"][0]["alg"], "RS256", "❌ Incorrect algorithm");
+
+    let login_response: LoginResponse = ureq::post(&format!("{}/auth/login", base_url))
+        .send_json(ureq::json!(LoginRequest {
+            username: "admin".to_string(),
+            password: "admin123".to_string(),
+        }))
+        .expect("❌ Login failed")
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    let header = jsonwebtoken::decode_header(&login_response.access_token).expect("❌ Invalid JWT header");
+    assert_eq!(header.alg, jsonwebtoken::Algorithm::RS256, "❌ Token not signed with RS256");
+    assert_eq!(header.kid.as_deref(), Some("test-rsa"), "❌ Token missing kid");
+
+    // The RS256 token is accepted by the server itself
+    let response = ureq::get(&format!("{}/data", base_url))
+        .set("Authorization", &format!("Bearer {}", login_response.access_token))
+        .call()
+        .expect("❌ RS256 token rejected");
+    assert_eq!(response.status(), 200, "❌ RS256 token rejected");
+    println!("✅ RS256 signing and JWKS tested successfully");
+    stop_test_server(child);
+}
@@ -77,4 +77,62 @@ async fn test_complete_crud_flow() {
     }
     println!("✅ Complete CRUD flow tested successfully");
     stop_test_server(child);
-} 
\ No newline at end of file
+} 
+
+#[async_std::test]
+async fn test_soft_delete_and_restore() {
+    println!("\n🧪 Test: Soft delete, trash and restore");
+    let (base_url, child) = start_test_server();
+    let token = login_and_get_token(&base_url);
+    let test_data = TestData {
+        func_names: vec!["add".to_string()],
+        bytecode: vec![1, 2, 3],
+    };
+    let create_response = ureq::post(&format!("{}/data", base_url))
+        .set("Authorization", &format!("Bearer {}", token))
+        .send_json(ureq::json!(test_data))
+        .expect("❌ Failed to create data");
+    let create_data: serde_json::Value = create_response.into_json().expect("❌ Failed to parse response");
+    let data_id = create_data["id"].as_u64().expect("❌ ID not found");
+    // 1. Soft delete moves the record to the trash
+    let delete_response = ureq::delete(&format!("{}/data/{}", base_url, data_id))
+        .set("Authorization", &format!("Bearer {}", token))
+        .call()
+        .expect("❌ Failed to delete data");
+    assert_eq!(delete_response.status(), 204, "❌ Soft delete should return 204");
+    let trash: serde_json::Value = ureq::get(&format!("{}/data/trash", base_url))
+        .set("Authorization", &format!("Bearer {}", token))
+        .call()
+        .expect("❌ Failed to list trash")
+        .into_json()
+        .expect("❌ Failed to parse trash");
+    let trashed = &trash[data_id.to_string()];
+    assert_eq!(trashed["entry"]["owner"], "admin", "❌ Record missing from trash");
+    assert_eq!(trashed["deleted_by"], "admin", "❌ Incorrect deleted_by");
+    // 2. Restore brings it back under the same id
+    let restore_response = ureq::post(&format!("{}/data/{}/restore", base_url, data_id))
+        .set("Authorization", &format!("Bearer {}", token))
+        .call()
+        .expect("❌ Failed to restore data");
+    assert_eq!(restore_response.status(), 200, "❌ Restore failed");
+    let read_response = ureq::get(&format!("{}/data/{}", base_url, data_id))
+        .set("Authorization", &format!("Bearer {}", token))
+        .call()
+        .expect("❌ Restored record not readable");
+    assert_eq!(read_response.status(), 200, "❌ Restored record not readable");
+    // 3. Hard delete (admin) skips the trash
+    let hard_response = ureq::delete(&format!("{}/data/{}?hard=true", base_url, data_id))
+        .set("Authorization", &format!("Bearer {}", token))
+        .call()
+        .expect("❌ Failed to hard delete data");
+    assert_eq!(hard_response.status(), 204, "❌ Hard delete should return 204");
+    let trash: serde_json::Value = ureq::get(&format!("{}/data/trash", base_url))
+        .set("Authorization", &format!("Bearer {}", token))
+        .call()
+        .expect("❌ Failed to list trash")
+        .into_json()
+        .expect("❌ Failed to parse trash");
+    assert!(trash.get(data_id.to_string()).is_none(), "❌ Hard-deleted record still in trash");
+    println!("✅ Soft delete and restore tested successfully");
+    stop_test_server(child);
+}
@@ -41,6 +41,34 @@ async fn test_unauthorized_access() {
     stop_test_server(child);
 }
 
+#[async_std::test]
+async fn test_undeclared_routes_require_auth() {
+    println!("\n🧪 Test: Routes not declared public require authentication");
+    let (base_url, child) = start_test_server();
+
+    // Unknown paths are handled by the protected route group, so they are 401 before 404
+    match ureq::get(&format!("{}/not-a-route", base_url)).call() {
+        Err(ureq::Error::Status(code, _)) => assert_eq!(code, 401, "❌ Status code should be 401"),
+        other => panic!("❌ Expected 401, got {:?}", other.map(|r| r.status())),
+    }
+
+    let token = login_and_get_token(&base_url);
+    match ureq::get(&format!("{}/not-a-route", base_url))
+        .set("Authorization", &format!("Bearer {}", token))
+        .call() {
+        Err(ureq::Error::Status(code, _)) => assert_eq!(code, 404, "❌ Status code should be 404"),
+        other => panic!("❌ Expected 404, got {:?}", other.map(|r| r.status())),
+    }
+
+    // Public routes still work without credentials
+    let response = ureq::get(&format!("{}/.well-known/jwks.json", base_url))
+        .call()
+        .expect("❌ JWKS request failed");
+    assert_eq!(response.status(), 200, "❌ JWKS should be public");
+    println!("✅ Route groups tested successfully");
+    stop_test_server(child);
+}
+
 #[async_std::test]
 async fn test_invalid_token() {
     println!("\n🧪 Test: Invalid token");
@@ -57,6 +85,12 @@ async fn test_invalid_token() {
             // If we received a response, it should be 401
             assert_eq!(response.status(), 401, "❌ Status code should be 401");
         }
+        Err(ureq::Error::Status(code, response)) => {
+            assert_eq!(code, 401, "❌ Status code should be 401");
+            let challenge = response.header("WWW-Authenticate").expect("❌ Missing WWW-Authenticate header");
+            assert!(challenge.contains("error=\"invalid_token\""), "❌ Unexpected challenge: {}", challenge);
+            println!("✅ Invalid token correctly rejected (401)");
+        }
         Err(e) => {
             // If we received an error, it should be status 401
             if e.to_string().contains("status code 401") {
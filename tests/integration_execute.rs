@@ -330,4 +330,373 @@ async fn test_wasm_execute_missing_authentication() {
     
     println!("✅ Missing authentication correctly rejected");
     stop_test_server(child);
-} 
\ No newline at end of file
+} 
+// A module using every host_v1 import
+const HOST_API_MODULE: &str = r#"(module
+    (import "host_v1" "log" (func $log (param i32 i32 i32)))
+    (import "host_v1" "input_read" (func $input_read (param i32 i32) (result i32)))
+    (import "host_v1" "input_len" (func $input_len (result i32)))
+    (import "host_v1" "time_now_ms" (func $now (result i64)))
+    (import "host_v1" "random_u64" (func $random (result i64)))
+    (memory (export "memory") 1)
+    ;; Logs the input and returns its length plus `extra`
+    (func (export "echo") (param $extra i32) (result i32)
+        (local $n i32)
+        (local.set $n (call $input_read (i32.const 0) (i32.const 1024)))
+        (call $log (i32.const 2) (i32.const 0) (local.get $n))
+        (i32.add (call $input_len) (local.get $extra)))
+    (func (export "clock_ok") (result i32)
+        (i64.gt_s (call $now) (i64.const 1600000000000)))
+    (func (export "random_ok") (result i32)
+        (i64.ne (call $random) (call $random))))"#;
+
+#[async_std::test]
+#[serial]
+async fn test_wasm_execute_host_api() {
+    println!("\n🧪 Test: WASM execute - host function imports");
+    let (base_url, child) = start_test_server();
+    let token = login_and_get_token(&base_url);
+    let bytecode = wat::parse_str(HOST_API_MODULE).expect("❌ Invalid test module");
+    let upload = |host_api: Option<&str>| {
+        match ureq::post(&format!("{}/data", base_url))
+            .set("Authorization", &format!("Bearer {}", token))
+            .send_json(serde_json::json!({
+                "func_names": ["echo", "clock_ok", "random_ok"],
+                "bytecode": bytecode,
+                "host_api": host_api,
+            })) {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => panic!("❌ Request failed: {}", e),
+        }
+    };
+
+    // Imports need an explicit opt-in, and only known versions exist
+    assert_eq!(upload(None).status(), 400, "❌ Imports without host_api should be rejected");
+    assert_eq!(upload(Some("host_v0")).status(), 400, "❌ Unknown host API version should be rejected");
+    let created = upload(Some("host_v1"));
+    assert_eq!(created.status(), 200, "❌ Module with host_v1 imports should be accepted");
+    let record_id = created.into_json::<serde_json::Value>().expect("❌ Failed to parse JSON")["id"].as_u64().unwrap();
+
+    let execute = |body: serde_json::Value| -> ExecuteResponse {
+        ureq::post(&format!("{}/execute/{}", base_url, record_id))
+            .set("Authorization", &format!("Bearer {}", token))
+            .send_json(body)
+            .expect("❌ Execution failed")
+            .into_json()
+            .expect("❌ Failed to parse JSON")
+    };
+    let echoed = execute(serde_json::json!({"fn": "echo", "arg": [100, 0], "input": "hello"}));
+    assert!(echoed.success);
+    assert_eq!(echoed.result, Some(105), "❌ echo should return the input length plus the argument");
+    assert_eq!(execute(serde_json::json!({"fn": "clock_ok", "arg": [0, 0]})).result, Some(1), "❌ Host clock not visible");
+    assert_eq!(execute(serde_json::json!({"fn": "random_ok", "arg": [0, 0]})).result, Some(1), "❌ Host random not visible");
+    println!("✅ Host function imports tested successfully");
+    stop_test_server(child);
+}
+
+// Implements the payload ABI: a bump allocator and a function copying its input
+const PAYLOAD_MODULE: &str = r#"(module
+    (memory (export "memory") 1)
+    (global $next (mut i32) (i32.const 1024))
+    (func $alloc (export "alloc") (param $len i32) (result i32)
+        (global.get $next)
+        (global.set $next (i32.add (global.get $next) (local.get $len))))
+    (func (export "dealloc") (param i32 i32))
+    (func (export "echo") (param $ptr i32) (param $len i32) (result i64)
+        (local $out i32)
+        (local.set $out (call $alloc (local.get $len)))
+        (memory.copy (local.get $out) (local.get $ptr) (local.get $len))
+        (i64.or
+            (i64.shl (i64.extend_i32_u (local.get $out)) (i64.const 32))
+            (i64.extend_i32_u (local.get $len)))))"#;
+
+#[async_std::test]
+#[serial]
+async fn test_wasm_execute_payload() {
+    println!("\n🧪 Test: WASM execute - JSON and base64 payloads");
+    let (base_url, child) = start_test_server();
+    let token = login_and_get_token(&base_url);
+    let created: serde_json::Value = ureq::post(&format!("{}/data", base_url))
+        .set("Authorization", &format!("Bearer {}", token))
+        .send_json(serde_json::json!({
+            "func_names": ["echo"],
+            "bytecode": wat::parse_str(PAYLOAD_MODULE).expect("❌ Invalid test module"),
+        }))
+        .expect("❌ Failed to create WASM record")
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    let record_id = created["id"].as_u64().unwrap();
+
+    let execute = |body: serde_json::Value| {
+        match ureq::post(&format!("{}/execute/{}", base_url, record_id))
+            .set("Authorization", &format!("Bearer {}", token))
+            .send_json(body) {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => panic!("❌ Request failed: {}", e),
+        }
+    };
+
+    let payload = serde_json::json!({"name": "Ferris", "tags": ["crab", "rust"]});
+    let response = execute(serde_json::json!({"fn": "echo", "payload": payload}));
+    assert_eq!(response.status(), 200, "❌ JSON payload call failed");
+    let body: serde_json::Value = response.into_json().expect("❌ Failed to parse JSON");
+    assert_eq!(body["output"], payload, "❌ JSON output should round-trip");
+
+    // "AAEC/w==" is the bytes 0, 1, 2, 255, which are not JSON
+    let response = execute(serde_json::json!({"fn": "echo", "payload_base64": "AAEC/w=="}));
+    assert_eq!(response.status(), 200, "❌ Base64 payload call failed");
+    let body: serde_json::Value = response.into_json().expect("❌ Failed to parse JSON");
+    assert_eq!(body["output_base64"], "AAEC/w==");
+    assert!(body.get("output").is_none(), "❌ Non-JSON output should only be returned as base64");
+
+    assert_eq!(execute(serde_json::json!({"fn": "echo", "payload_base64": "***"})).status(), 400);
+    assert_eq!(execute(serde_json::json!({"fn": "echo", "payload": 1, "payload_base64": "AA=="})).status(), 400);
+    println!("✅ Payload calls tested successfully");
+    stop_test_server(child);
+}
+
+// A WASI command: copies stdin and in.txt to stdout, writes to stderr and exits with 3
+const WASI_MODULE: &str = r#"(module
+    (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "path_open"
+        (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+    (memory (export "memory") 1)
+    (data (i32.const 100) "in.txt")
+    (data (i32.const 200) "err\n")
+    ;; Reads up to 512 bytes from fd into 1024 and writes them to stdout
+    (func $copy (param $fd i32)
+        (i32.store (i32.const 0) (i32.const 1024))
+        (i32.store (i32.const 4) (i32.const 512))
+        (drop (call $fd_read (local.get $fd) (i32.const 0) (i32.const 1) (i32.const 8)))
+        (i32.store (i32.const 4) (i32.load (i32.const 8)))
+        (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))))
+    (func (export "_start")
+        (call $copy (i32.const 0))
+        (drop (call $path_open (i32.const 3) (i32.const 0) (i32.const 100) (i32.const 6)
+            (i32.const 0) (i64.const 0) (i64.const 0) (i32.const 0) (i32.const 16)))
+        (call $copy (i32.load (i32.const 16)))
+        (i32.store (i32.const 0) (i32.const 200))
+        (i32.store (i32.const 4) (i32.const 4))
+        (drop (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8)))
+        (call $proc_exit (i32.const 3))))"#;
+
+#[async_std::test]
+#[serial]
+async fn test_wasm_execute_wasi() {
+    println!("\n🧪 Test: WASM execute - WASI preview1");
+    let (base_url, child) = start_test_server();
+    let token = login_and_get_token(&base_url);
+    let bytecode = wat::parse_str(WASI_MODULE).expect("❌ Invalid test module");
+    let post = |path: String, body: serde_json::Value| {
+        match ureq::post(&format!("{}{}", base_url, path))
+            .set("Authorization", &format!("Bearer {}", token))
+            .send_json(body) {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => panic!("❌ Request failed: {}", e),
+        }
+    };
+
+    let rejected = post("/data".to_string(), serde_json::json!({"func_names": ["_start"], "bytecode": bytecode}));
+    assert_eq!(rejected.status(), 400, "❌ WASI imports need the record to enable wasi");
+    let created = post("/data".to_string(), serde_json::json!({"func_names": ["_start"], "bytecode": bytecode, "wasi": true}));
+    assert_eq!(created.status(), 200, "❌ WASI module should be accepted");
+    let record_id = created.into_json::<serde_json::Value>().expect("❌ Failed to parse JSON")["id"].as_u64().unwrap();
+
+    let response = post(format!("/execute/{}", record_id), serde_json::json!({
+        "fn": "_start",
+        "wasi": {"stdin": "from stdin, ", "args": ["prog"], "env": {"MODE": "test"}, "files": {"in.txt": "from a file"}},
+    }));
+    assert_eq!(response.status(), 200, "❌ WASI execution failed");
+    let body: serde_json::Value = response.into_json().expect("❌ Failed to parse JSON");
+    assert_eq!(body["stdout"], "from stdin, from a file");
+    assert_eq!(body["stderr"], "err\n");
+    assert_eq!(body["exit_code"], 3);
+
+    // Files never touch the host and do not survive the call
+    let response = post(format!("/execute/{}", record_id), serde_json::json!({"fn": "_start"}));
+    let body: serde_json::Value = response.into_json().expect("❌ Failed to parse JSON");
+    assert_eq!(body["stdout"], "", "❌ Nothing should be left from the previous run");
+    println!("✅ WASI execution tested successfully");
+    stop_test_server(child);
+}
+
+const ADD_WAT: &str = r#"(module
+  (func (export "add") (param i32 i32) (result i32)
+    (i32.add (local.get 0) (local.get 1))))"#;
+
+#[async_std::test]
+#[serial]
+async fn test_wasm_upload_wat() {
+    println!("\n🧪 Test: WASM upload - WebAssembly text format");
+    let (base_url, child) = start_test_server();
+    let token = login_and_get_token(&base_url);
+    let send = |request: ureq::Request, body: Result<serde_json::Value, &str>| {
+        let request = request.set("Authorization", &format!("Bearer {}", token));
+        let result = match body {
+            Ok(json) => request.send_json(json),
+            Err(text) => request.set("Content-Type", "text/wat").send_string(text),
+        };
+        match result {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => panic!("❌ Request failed: {}", e),
+        }
+    };
+    let add = |record_id: u64| {
+        let response = send(
+            ureq::post(&format!("{}/execute/{}", base_url, record_id)),
+            Ok(serde_json::json!({"fn": "add", "arg": [2, 3]})),
+        );
+        assert_eq!(response.status(), 200, "❌ Execution of a WAT record failed");
+        response.into_json::<ExecuteResponse>().expect("❌ Failed to parse response").result
+    };
+
+    // JSON body with "format": "wat"; func_names defaults to the exports
+    let created = send(ureq::post(&format!("{}/data", base_url)), Ok(serde_json::json!({"format": "wat", "source": ADD_WAT})));
+    assert_eq!(created.status(), 200, "❌ WAT upload should be accepted");
+    let record_id = created.into_json::<serde_json::Value>().expect("❌ Failed to parse JSON")["id"].as_u64().unwrap();
+    assert_eq!(add(record_id), Some(5));
+    let record: serde_json::Value = send(ureq::get(&format!("{}/data/{}", base_url, record_id)), Ok(serde_json::json!({})))
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    assert_eq!(record["func_names"], serde_json::json!(["add"]), "❌ Exported functions should be recorded");
+
+    // Raw text body on update, with func_names in the query string
+    let updated = send(
+        ureq::put(&format!("{}/data/{}?func_names=add", base_url, record_id)),
+        Err(&ADD_WAT.replace("i32.add", "i32.mul")),
+    );
+    assert_eq!(updated.status(), 200, "❌ text/wat update should be accepted");
+    assert_eq!(add(record_id), Some(6));
+
+    // Parse errors name the line and column
+    let rejected = send(ureq::post(&format!("{}/data", base_url)), Err("(module\n  (func (export \"f\")\n    i32.cnst 1))"));
+    assert_eq!(rejected.status(), 400, "❌ Invalid WAT should be rejected");
+    let diagnostic: serde_json::Value = rejected.into_json().expect("❌ Failed to parse JSON");
+    assert_eq!((diagnostic["line"].as_u64(), diagnostic["column"].as_u64()), (Some(3), Some(5)), "❌ Wrong position: {}", diagnostic);
+    assert!(diagnostic["snippet"].as_str().unwrap_or_default().contains("i32.cnst 1))"), "❌ Missing source line: {}", diagnostic);
+
+    // Binary JSON uploads still need func_names, and text/plain is not module text
+    let bytecode = wat::parse_str(ADD_WAT).expect("❌ Invalid test module");
+    let unnamed = send(ureq::post(&format!("{}/data", base_url)), Ok(serde_json::json!({"bytecode": bytecode})));
+    assert_eq!(unnamed.status(), 400, "❌ Binary upload without func_names should be rejected");
+    let plain = ureq::post(&format!("{}/data?func_names=add", base_url))
+        .set("Authorization", &format!("Bearer {}", token))
+        .set("Content-Type", "text/plain")
+        .send_string(ADD_WAT);
+    assert!(matches!(plain, Err(ureq::Error::Status(status, _)) if status != 200), "❌ text/plain body should not be compiled");
+    println!("✅ WAT uploads tested successfully");
+    stop_test_server(child);
+}
+
+// 0/0 as f32 bits; hosts differ in the NaN they produce
+const NAN_WAT: &str = r#"(module
+  (import "host_v1" "log" (func (param i32 i32 i32)))
+  (func (export "ratio") (param i32 i32) (result i32)
+    (i32.reinterpret_f32 (f32.div (f32.convert_i32_s (local.get 0)) (f32.convert_i32_s (local.get 1))))))"#;
+
+const CLOCK_WAT: &str = r#"(module
+  (import "host_v1" "time_now_ms" (func $now (result i64)))
+  (func (export "now") (result i32) (i32.wrap_i64 (call $now))))"#;
+
+#[async_std::test]
+#[serial]
+async fn test_wasm_execute_deterministic() {
+    println!("\n🧪 Test: WASM execution - Deterministic mode");
+    let (base_url, child) = start_test_server();
+    let token = login_and_get_token(&base_url);
+    let post = |path: &str, body: serde_json::Value| {
+        match ureq::post(&format!("{}{}", base_url, path))
+            .set("Authorization", &format!("Bearer {}", token))
+            .send_json(body)
+        {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => panic!("❌ Request failed: {}", e),
+        }
+    };
+    let upload = |source: &str| {
+        let created = post("/data", serde_json::json!({"format": "wat", "source": source, "host_api": "host_v1"}));
+        assert_eq!(created.status(), 200, "❌ Upload should be accepted");
+        created.into_json::<serde_json::Value>().expect("❌ Failed to parse JSON")["id"].as_u64().unwrap()
+    };
+
+    let record_id = upload(NAN_WAT);
+    let execute = |arg: [i32; 2]| {
+        let response = post(&format!("/execute/{}", record_id), serde_json::json!({"fn": "ratio", "arg": arg, "deterministic": true}));
+        assert_eq!(response.status(), 200, "❌ Deterministic execution failed");
+        response.into_json::<serde_json::Value>().expect("❌ Failed to parse JSON")
+    };
+    let first = execute([0, 0]);
+    assert_eq!(first["result"], 0x7fc0_0000, "❌ NaN should be canonical: {}", first);
+    let report = &first["deterministic"];
+    assert_eq!(report["module_digest"].as_str().map(str::len), Some(64), "❌ Missing module digest: {}", first);
+    assert!(report["fuel_used"].as_u64().unwrap_or_default() > 0, "❌ Missing fuel use: {}", first);
+    assert_eq!(execute([0, 0]), first, "❌ Identical requests should give identical responses");
+    let other = execute([1, 2]);
+    assert_ne!(other["deterministic"]["input_hash"], report["input_hash"], "❌ Different inputs should hash differently");
+    assert_eq!(other["deterministic"]["module_digest"], report["module_digest"]);
+
+    // Other calls carry no report
+    let plain = post(&format!("/execute/{}", record_id), serde_json::json!({"fn": "ratio", "arg": [1, 2]}));
+    let plain: serde_json::Value = plain.into_json().expect("❌ Failed to parse JSON");
+    assert!(plain.get("deterministic").is_none(), "❌ Unexpected report: {}", plain);
+
+    // Clock imports are not offered
+    let clock_id = upload(CLOCK_WAT);
+    let rejected = post(&format!("/execute/{}", clock_id), serde_json::json!({"fn": "now", "deterministic": true}));
+    assert_eq!(rejected.status(), 400, "❌ time_now_ms should be rejected in deterministic mode");
+    let allowed = post(&format!("/execute/{}", clock_id), serde_json::json!({"fn": "now"}));
+    assert_eq!(allowed.status(), 200, "❌ time_now_ms should work outside deterministic mode");
+    println!("✅ Deterministic mode tested successfully");
+    stop_test_server(child);
+}
+
+#[async_std::test]
+#[serial]
+async fn test_wasm_execute_memoized() {
+    println!("\n🧪 Test: WASM execution - Memoized pure functions");
+    let (base_url, child) = start_test_server();
+    let token = login_and_get_token(&base_url);
+    let send = |request: ureq::Request, body: serde_json::Value| {
+        match request.set("Authorization", &format!("Bearer {}", token)).send_json(body) {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => panic!("❌ Request failed: {}", e),
+        }
+    };
+    let created = send(
+        ureq::post(&format!("{}/data", base_url)),
+        serde_json::json!({"format": "wat", "source": ADD_WAT, "pure_functions": ["add"]}),
+    );
+    assert_eq!(created.status(), 200, "❌ Upload should be accepted");
+    let record_id = created.into_json::<serde_json::Value>().expect("❌ Failed to parse JSON")["id"].as_u64().unwrap();
+    let add = |arg: [i32; 2]| {
+        let response = send(ureq::post(&format!("{}/execute/{}", base_url, record_id)), serde_json::json!({"fn": "add", "arg": arg}));
+        assert_eq!(response.status(), 200, "❌ Execution failed");
+        let body: serde_json::Value = response.into_json().expect("❌ Failed to parse JSON");
+        (body["result"].as_i64(), body["cached"].as_bool().unwrap_or(false))
+    };
+
+    assert_eq!(add([2, 3]), (Some(5), false), "❌ The first call should run the module");
+    assert_eq!(add([2, 3]), (Some(5), true), "❌ A repeated call should be answered from the cache");
+    assert_eq!(add([3, 2]), (Some(5), false), "❌ Other arguments should not hit the cache");
+
+    // Calls with input are not memoized
+    let with_input = send(
+        ureq::post(&format!("{}/execute/{}", base_url, record_id)),
+        serde_json::json!({"fn": "add", "arg": [2, 3], "input": "x"}),
+    );
+    let with_input: serde_json::Value = with_input.into_json().expect("❌ Failed to parse JSON");
+    assert!(with_input.get("cached").is_none(), "❌ Calls with input should run: {}", with_input);
+
+    // Updating the record drops its results
+    let updated = send(
+        ureq::put(&format!("{}/data/{}", base_url, record_id)),
+        serde_json::json!({"format": "wat", "source": ADD_WAT.replace("i32.add", "i32.mul"), "pure_functions": ["add"]}),
+    );
+    assert_eq!(updated.status(), 200, "❌ Update should be accepted");
+    assert_eq!(add([2, 3]), (Some(6), false), "❌ The updated module should run");
+    assert_eq!(add([2, 3]), (Some(6), true));
+    println!("✅ Memoized pure functions tested successfully");
+    stop_test_server(child);
+}
@@ -0,0 +1,110 @@
+mod common;
+use common::*;
+use serial_test::serial;
+
+// `bump` adds its argument to a counter kept in linear memory
+const COUNTER_MODULE: &str = r#"(module
+    (memory 1)
+    (func (export "bump") (param $by i32) (result i32)
+        (i32.store (i32.const 0) (i32.add (i32.load (i32.const 0)) (local.get $by)))
+        (i32.load (i32.const 0))))"#;
+
+fn login_and_get_token(base_url: &str) -> String {
+    let login_data = LoginRequest {
+        username: "admin".to_string(),
+        password: "admin123".to_string(),
+    };
+    let response = ureq::post(&format!("{}/auth/login", base_url))
+        .send_json(ureq::json!(login_data))
+        .expect("❌ Login request failed");
+    assert_eq!(response.status(), 200, "❌ Login failed");
+    let login_response: LoginResponse = response.into_json().expect("❌ Failed to parse response");
+    login_response.access_token
+}
+
+fn send(request: ureq::Request, body: Option<serde_json::Value>) -> ureq::Response {
+    let result = match body {
+        Some(body) => request.send_json(body),
+        None => request.call(),
+    };
+    match result {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(e) => panic!("❌ Request failed: {}", e),
+    }
+}
+
+fn create_counter_record(base_url: &str, auth: &str) -> u64 {
+    let bytecode = wat::parse_str(COUNTER_MODULE).expect("❌ Invalid test module");
+    let created = send(ureq::post(&format!("{}/data", base_url)).set("Authorization", auth),
+        Some(serde_json::json!({"func_names": ["bump"], "bytecode": bytecode})));
+    assert_eq!(created.status(), 200, "❌ Failed to create record");
+    created.into_json::<serde_json::Value>().expect("❌ Failed to parse JSON")["id"].as_u64().unwrap()
+}
+
+fn bump(base_url: &str, auth: &str, record_id: u64, instance: Option<&str>) -> ureq::Response {
+    send(ureq::post(&format!("{}/execute/{}", base_url, record_id)).set("Authorization", auth),
+        Some(serde_json::json!({"fn": "bump", "arg": [5, 0], "instance": instance})))
+}
+
+#[async_std::test]
+#[serial]
+async fn test_instances_keep_memory_between_calls() {
+    println!("\n🧪 Test: instances - memory survives between calls");
+    let (base_url, child) = start_test_server();
+    let auth = format!("Bearer {}", login_and_get_token(&base_url));
+    let record_id = create_counter_record(&base_url, &auth);
+
+    let response = send(ureq::post(&format!("{}/data/{}/instances", base_url, record_id)).set("Authorization", &auth), None);
+    assert_eq!(response.status(), 201, "❌ Instance should be created");
+    let location = response.header("Location").expect("❌ Missing Location header").to_string();
+    let instance: serde_json::Value = response.into_json().expect("❌ Failed to parse JSON");
+    let id = instance["id"].as_str().unwrap().to_string();
+    assert_eq!(location, format!("/instances/{}", id));
+
+    for expected in [5, 10, 15] {
+        let response = bump(&base_url, &auth, record_id, Some(&id));
+        assert_eq!(response.status(), 200, "❌ Call on the instance failed");
+        assert_eq!(response.into_json::<serde_json::Value>().unwrap()["result"], expected);
+    }
+    // Calls without the instance still start from scratch
+    assert_eq!(bump(&base_url, &auth, record_id, None).into_json::<serde_json::Value>().unwrap()["result"], 5);
+
+    let listed: serde_json::Value = send(ureq::get(&format!("{}/instances", base_url)).set("Authorization", &auth), None)
+        .into_json().expect("❌ Failed to parse JSON");
+    assert_eq!(listed.as_array().map(Vec::len), Some(1));
+
+    // An instance keeps the module it was created from
+    let bytecode = wat::parse_str(COUNTER_MODULE.replace("i32.add", "i32.sub")).unwrap();
+    let updated = send(ureq::put(&format!("{}/data/{}", base_url, record_id)).set("Authorization", &auth),
+        Some(serde_json::json!({"func_names": ["bump"], "bytecode": bytecode})));
+    assert_eq!(updated.status(), 200, "❌ Update failed");
+    assert_eq!(bump(&base_url, &auth, record_id, Some(&id)).status(), 409, "❌ Stale instance should be refused");
+
+    let response = send(ureq::delete(&format!("{}{}", base_url, location)).set("Authorization", &auth), None);
+    assert_eq!(response.status(), 204, "❌ Instance should be deleted");
+    assert_eq!(bump(&base_url, &auth, record_id, Some(&id)).status(), 404, "❌ Deleted instance should be gone");
+    println!("✅ Instance sessions tested successfully");
+    stop_test_server(child);
+}
+
+#[async_std::test]
+#[serial]
+async fn test_instances_are_capped_and_expire() {
+    println!("\n🧪 Test: instances - per-user cap and idle timeout");
+    let (base_url, child) = start_test_server_with_env(&[("INSTANCE_LIMIT_PER_USER", "1"), ("INSTANCE_IDLE_TIMEOUT_SECS", "1")]);
+    let auth = format!("Bearer {}", login_and_get_token(&base_url));
+    let record_id = create_counter_record(&base_url, &auth);
+    let create = || send(ureq::post(&format!("{}/data/{}/instances", base_url, record_id)).set("Authorization", &auth), None);
+
+    let response = create();
+    assert_eq!(response.status(), 201, "❌ Instance should be created");
+    let id = response.into_json::<serde_json::Value>().unwrap()["id"].as_str().unwrap().to_string();
+    assert_eq!(create().status(), 429, "❌ Second instance should exceed the cap");
+
+    // Idle instances are torn down and free their slot
+    std::thread::sleep(std::time::Duration::from_millis(2500));
+    assert_eq!(bump(&base_url, &auth, record_id, Some(&id)).status(), 404, "❌ Idle instance should be gone");
+    assert_eq!(create().status(), 201, "❌ Slot should be free again");
+    println!("✅ Instance limits tested successfully");
+    stop_test_server(child);
+}
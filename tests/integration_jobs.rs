@@ -0,0 +1,130 @@
+mod common;
+use common::*;
+use serial_test::serial;
+
+// `add` finishes at once; `spin` never returns
+const JOB_MODULE: &str = r#"(module
+    (func (export "add") (param i32 i32) (result i32)
+        (i32.add (local.get 0) (local.get 1)))
+    (func (export "spin")
+        (loop $forever (br $forever))))"#;
+
+fn login_and_get_token(base_url: &str) -> String {
+    let login_data = LoginRequest {
+        username: "admin".to_string(),
+        password: "admin123".to_string(),
+    };
+    let response = ureq::post(&format!("{}/auth/login", base_url))
+        .send_json(ureq::json!(login_data))
+        .expect("❌ Login request failed");
+    assert_eq!(response.status(), 200, "❌ Login failed");
+    let login_response: LoginResponse = response.into_json().expect("❌ Failed to parse response");
+    login_response.access_token
+}
+
+fn send(request: ureq::Request, body: Option<serde_json::Value>) -> ureq::Response {
+    let result = match body {
+        Some(body) => request.send_json(body),
+        None => request.call(),
+    };
+    match result {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(e) => panic!("❌ Request failed: {}", e),
+    }
+}
+
+// Polls a job until it leaves the queued and running states
+fn wait_for_job(base_url: &str, token: &str, id: &str) -> serde_json::Value {
+    for _ in 0..100 {
+        let response = send(ureq::get(&format!("{}/jobs/{}", base_url, id)).set("Authorization", &format!("Bearer {}", token)), None);
+        assert_eq!(response.status(), 200, "❌ Job lookup failed");
+        let job: serde_json::Value = response.into_json().expect("❌ Failed to parse JSON");
+        if job["status"] != "queued" && job["status"] != "running" {
+            return job;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    panic!("❌ Job {} did not finish", id);
+}
+
+#[async_std::test]
+#[serial]
+async fn test_jobs_run_and_cancel() {
+    println!("\n🧪 Test: jobs - run, poll and cancel");
+    let (base_url, child) = start_test_server();
+    let token = login_and_get_token(&base_url);
+    let auth = format!("Bearer {}", token);
+    let bytecode = wat::parse_str(JOB_MODULE).expect("❌ Invalid test module");
+    let created = send(ureq::post(&format!("{}/data", base_url)).set("Authorization", &auth),
+        Some(serde_json::json!({"func_names": ["add", "spin"], "bytecode": bytecode})));
+    assert_eq!(created.status(), 200, "❌ Failed to create record");
+    let record_id = created.into_json::<serde_json::Value>().expect("❌ Failed to parse JSON")["id"].as_u64().unwrap();
+
+    // A job runs in the background and keeps its result
+    let response = send(ureq::post(&format!("{}/jobs", base_url)).set("Authorization", &auth),
+        Some(serde_json::json!({"record_id": record_id, "fn": "add", "arg": [2, 3]})));
+    assert_eq!(response.status(), 202, "❌ Job should be accepted");
+    let location = response.header("Location").expect("❌ Missing Location header").to_string();
+    let job: serde_json::Value = response.into_json().expect("❌ Failed to parse JSON");
+    let id = job["id"].as_str().unwrap().to_string();
+    assert_eq!(location, format!("/jobs/{}", id));
+    let job = wait_for_job(&base_url, &token, &id);
+    assert_eq!(job["status"], "succeeded");
+    assert_eq!(job["result"]["result"], 5);
+
+    // Bad calls are rejected before they are queued
+    let response = send(ureq::post(&format!("{}/jobs", base_url)).set("Authorization", &auth),
+        Some(serde_json::json!({"record_id": record_id, "fn": "missing"})));
+    assert_eq!(response.status(), 400, "❌ Undeclared functions should be rejected");
+
+    // A running job stops when cancelled
+    let response = send(ureq::post(&format!("{}/jobs", base_url)).set("Authorization", &auth),
+        Some(serde_json::json!({"record_id": record_id, "fn": "spin"})));
+    assert_eq!(response.status(), 202, "❌ Job should be accepted");
+    let id = response.into_json::<serde_json::Value>().expect("❌ Failed to parse JSON")["id"].as_str().unwrap().to_string();
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    let response = send(ureq::delete(&format!("{}/jobs/{}", base_url, id)).set("Authorization", &auth), None);
+    assert_eq!(response.status(), 202, "❌ Running job should accept cancellation");
+    let job = wait_for_job(&base_url, &token, &id);
+    assert_eq!(job["status"], "cancelled");
+    assert_eq!(job["cancel_requested"], true);
+
+    let response = send(ureq::delete(&format!("{}/jobs/{}", base_url, id)).set("Authorization", &auth), None);
+    assert_eq!(response.status(), 409, "❌ Finished jobs cannot be cancelled");
+    let response = send(ureq::get(&format!("{}/jobs/unknown", base_url)).set("Authorization", &auth), None);
+    assert_eq!(response.status(), 404, "❌ Unknown jobs should not be found");
+    println!("✅ Jobs tested successfully");
+    stop_test_server(child);
+}
+
+#[async_std::test]
+#[serial]
+async fn test_jobs_queue_behind_the_concurrency_limit() {
+    println!("\n🧪 Test: jobs - per-user concurrency limit");
+    let (base_url, child) = start_test_server_with_env(&[("JOB_CONCURRENCY_PER_USER", "1")]);
+    let token = login_and_get_token(&base_url);
+    let auth = format!("Bearer {}", token);
+    let bytecode = wat::parse_str(JOB_MODULE).expect("❌ Invalid test module");
+    let created = send(ureq::post(&format!("{}/data", base_url)).set("Authorization", &auth),
+        Some(serde_json::json!({"func_names": ["add", "spin"], "bytecode": bytecode})));
+    let record_id = created.into_json::<serde_json::Value>().expect("❌ Failed to parse JSON")["id"].as_u64().unwrap();
+
+    let mut ids = Vec::new();
+    for func in ["spin", "add"] {
+        let response = send(ureq::post(&format!("{}/jobs", base_url)).set("Authorization", &auth),
+            Some(serde_json::json!({"record_id": record_id, "fn": func, "arg": [1, 1]})));
+        ids.push(response.into_json::<serde_json::Value>().expect("❌ Failed to parse JSON")["id"].as_str().unwrap().to_string());
+    }
+    let second: serde_json::Value = send(ureq::get(&format!("{}/jobs/{}", base_url, ids[1])).set("Authorization", &auth), None)
+        .into_json().expect("❌ Failed to parse JSON");
+    assert_eq!(second["status"], "queued", "❌ Second job should wait for the first");
+
+    // Cancelling the first frees the slot for the second
+    send(ureq::delete(&format!("{}/jobs/{}", base_url, ids[0])).set("Authorization", &auth), None);
+    assert_eq!(wait_for_job(&base_url, &token, &ids[0])["status"], "cancelled");
+    let second = wait_for_job(&base_url, &token, &ids[1]);
+    assert_eq!(second["status"], "succeeded");
+    assert_eq!(second["result"]["result"], 2);
+    println!("✅ Job queueing tested successfully");
+    stop_test_server(child);
+}
@@ -0,0 +1,160 @@
+mod common;
+use common::*;
+use serial_test::serial;
+use std::time::{Duration, Instant};
+
+// burn(n) counts down from n
+const BURN_MODULE: &str = r#"(module
+    (func (export "burn") (param $n i32)
+        (block $done
+            (loop $again
+                (br_if $done (i32.eqz (local.get $n)))
+                (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                (br $again)))))"#;
+
+fn login_and_get_token(base_url: &str) -> String {
+    let login_data = LoginRequest {
+        username: "admin".to_string(),
+        password: "admin123".to_string(),
+    };
+    let response = ureq::post(&format!("{}/auth/login", base_url))
+        .send_json(ureq::json!(login_data))
+        .expect("❌ Login request failed");
+    assert_eq!(response.status(), 200, "❌ Login failed");
+    let login_response: LoginResponse = response.into_json().expect("❌ Failed to parse response");
+    login_response.access_token
+}
+
+fn burn(base_url: &str, token: &str, record_id: u64, n: i32) -> Duration {
+    let start = Instant::now();
+    let response = ureq::post(&format!("{}/execute/{}", base_url, record_id))
+        .set("Authorization", &format!("Bearer {}", token))
+        .timeout(Duration::from_secs(60))
+        .send_json(serde_json::json!({"fn": "burn", "arg": [n, 0]}))
+        .expect("❌ Execution failed");
+    assert_eq!(response.status(), 200, "❌ Execution failed");
+    start.elapsed()
+}
+
+// Uploads the burn module and sizes the work so one execution takes a
+// noticeable time on this machine. Returns the record id, the argument and
+// how long one execution took.
+fn create_burn_record(base_url: &str, token: &str) -> (u64, i32, Duration) {
+    let bytecode = wat::parse_str(BURN_MODULE).expect("❌ Invalid test module");
+    let created = ureq::post(&format!("{}/data", base_url))
+        .set("Authorization", &format!("Bearer {}", token))
+        .send_json(serde_json::json!({"func_names": ["burn"], "bytecode": bytecode}))
+        .expect("❌ Failed to create record");
+    let record_id = created.into_json::<serde_json::Value>().expect("❌ Failed to parse JSON")["id"].as_u64().unwrap();
+
+    let mut n: i32 = 1 << 20;
+    let mut single = burn(base_url, token, record_id, n);
+    while single < Duration::from_millis(400) && n < 1 << 30 {
+        n *= 2;
+        single = burn(base_url, token, record_id, n);
+    }
+    println!("📏 burn({}) takes {:?}", n, single);
+    (record_id, n, single)
+}
+
+#[async_std::test]
+#[serial]
+async fn test_reads_proceed_during_executions() {
+    println!("\n🧪 Test: load - reads while modules run");
+    let (base_url, child) = start_test_server();
+    let token = login_and_get_token(&base_url);
+    let (record_id, n, single) = create_burn_record(&base_url, &token);
+
+    // Keep several executions busy and read records in the meantime. While a
+    // module ran under the state lock, every read waited for it to finish.
+    const EXECUTIONS: usize = 3;
+    let start = Instant::now();
+    let workers: Vec<_> = (0..EXECUTIONS)
+        .map(|_| {
+            let (base_url, token) = (base_url.clone(), token.clone());
+            std::thread::spawn(move || burn(&base_url, &token, record_id, n))
+        })
+        .collect();
+    std::thread::sleep(Duration::from_millis(50));
+    let mut reads = 0;
+    let mut slowest = Duration::ZERO;
+    while !workers.iter().all(|worker| worker.is_finished()) {
+        let read_start = Instant::now();
+        let response = ureq::get(&format!("{}/data/{}", base_url, record_id))
+            .set("Authorization", &format!("Bearer {}", token))
+            .timeout(Duration::from_secs(30))
+            .call()
+            .expect("❌ Read failed");
+        assert_eq!(response.status(), 200, "❌ Read failed");
+        slowest = slowest.max(read_start.elapsed());
+        reads += 1;
+    }
+    for worker in workers {
+        worker.join().expect("❌ Execution thread panicked");
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "📊 {} executions and {} reads in {:?} ({:.1} reads/s, slowest read {:?})",
+        EXECUTIONS,
+        reads,
+        elapsed,
+        reads as f64 / elapsed.as_secs_f64(),
+        slowest
+    );
+    assert!(reads >= 5, "❌ Only {} reads completed while modules ran", reads);
+    assert!(slowest < single / 2, "❌ A read waited {:?}, close to a whole execution ({:?})", slowest, single);
+    println!("✅ Reads are served while modules run");
+    stop_test_server(child);
+}
+
+#[async_std::test]
+#[serial]
+async fn test_concurrent_execution_throughput() {
+    println!("\n🧪 Test: load - executions per second, one at a time vs side by side");
+    let (base_url, child) = start_test_server();
+    let token = login_and_get_token(&base_url);
+    let (record_id, n, _) = create_burn_record(&base_url, &token);
+
+    // One at a time is what every caller got while modules ran under the
+    // state lock; side by side is what the blocking pool allows now
+    const EXECUTIONS: usize = 4;
+    let start = Instant::now();
+    for _ in 0..EXECUTIONS {
+        burn(&base_url, &token, record_id, n);
+    }
+    let serialized = EXECUTIONS as f64 / start.elapsed().as_secs_f64();
+
+    let start = Instant::now();
+    let workers: Vec<_> = (0..EXECUTIONS)
+        .map(|_| {
+            let (base_url, token) = (base_url.clone(), token.clone());
+            std::thread::spawn(move || burn(&base_url, &token, record_id, n))
+        })
+        .collect();
+    for worker in workers {
+        worker.join().expect("❌ Execution thread panicked");
+    }
+    let concurrent = EXECUTIONS as f64 / start.elapsed().as_secs_f64();
+
+    let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+    println!(
+        "📊 {} executions: {:.2}/s one at a time, {:.2}/s side by side ({:.2}x, {} cores)",
+        EXECUTIONS,
+        serialized,
+        concurrent,
+        concurrent / serialized,
+        cores
+    );
+    // Modules only run in parallel with more than one core; on one they
+    // share it, and the throughput must not drop
+    let expected = if cores > 1 { 1.3 } else { 0.8 };
+    assert!(
+        concurrent >= serialized * expected,
+        "❌ Side by side: {:.2}/s, one at a time: {:.2}/s (expected at least {}x)",
+        concurrent,
+        serialized,
+        expected
+    );
+    println!("✅ Concurrent executions keep up with serialized ones");
+    stop_test_server(child);
+}
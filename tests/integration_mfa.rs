@@ -0,0 +1,114 @@
+mod common;
+use common::*;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+// Returns the response of a request whatever its status
+fn response(result: Result<ureq::Response, ureq::Error>) -> ureq::Response {
+    match result {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(e) => panic!("❌ Request failed: {}", e),
+    }
+}
+
+// The code an authenticator app would show `offset_secs` from now
+fn totp_code(secret: &str, offset_secs: i64) -> String {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().expect("❌ Invalid base32 secret");
+    let totp = TOTP::new_unchecked(Algorithm::SHA1, 6, 1, 30, secret, None, String::new());
+    totp.generate((chrono::Utc::now().timestamp() + offset_secs) as u64)
+}
+
+fn post_json(base_url: &str, path: &str, token: Option<&str>, body: serde_json::Value) -> ureq::Response {
+    let mut request = ureq::post(&format!("{}{}", base_url, path));
+    if let Some(token) = token {
+        request = request.set("Authorization", &format!("Bearer {}", token));
+    }
+    response(request.send_json(body))
+}
+
+fn password_login(base_url: &str) -> serde_json::Value {
+    post_json(base_url, "/auth/login", None, serde_json::json!({"username": "user1", "password": "password123"}))
+        .into_json()
+        .expect("❌ Failed to parse JSON")
+}
+
+#[async_std::test]
+async fn test_totp_enrollment_and_login() {
+    println!("\n🧪 Test: TOTP enrollment, two-step login and recovery codes");
+    let (base_url, child) = start_test_server();
+    let token = password_login(&base_url)["access_token"].as_str().expect("❌ No access token").to_string();
+
+    // Enroll, then confirm with a code from the "authenticator"
+    let enrollment: serde_json::Value = post_json(&base_url, "/auth/mfa/enroll", Some(&token), serde_json::json!({}))
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    let secret = enrollment["secret"].as_str().expect("❌ No secret").to_string();
+    assert!(enrollment["otpauth_url"].as_str().unwrap().starts_with("otpauth://totp/"));
+    let recovery_codes: Vec<String> = serde_json::from_value(enrollment["recovery_codes"].clone()).unwrap();
+    assert_eq!(recovery_codes.len(), 10);
+
+    // Not active until verified
+    assert!(password_login(&base_url)["access_token"].is_string(), "❌ Unverified enrollment should not affect login");
+    let verified = post_json(&base_url, "/auth/mfa/verify", Some(&token), serde_json::json!({"code": totp_code(&secret, 0)}));
+    assert_eq!(verified.status(), 204, "❌ Verification should return 204");
+
+    // The password alone now only yields an mfa_pending token
+    let challenge = password_login(&base_url);
+    assert_eq!(challenge["mfa_required"], true);
+    assert!(challenge.get("access_token").is_none(), "❌ Tokens issued without second factor");
+    let mfa_token = challenge["mfa_token"].as_str().unwrap().to_string();
+    let not_access = response(ureq::get(&format!("{}/auth/me", base_url)).set("Authorization", &format!("Bearer {}", mfa_token)).call());
+    assert_eq!(not_access.status(), 401, "❌ mfa_pending token must not work as an access token");
+
+    let wrong = post_json(&base_url, "/auth/mfa/login", None, serde_json::json!({"mfa_token": mfa_token, "code": "000000x"}));
+    assert_eq!(wrong.status(), 401, "❌ Wrong code should be rejected");
+    // The code used for verification cannot be replayed, but the next one works
+    let replayed = post_json(&base_url, "/auth/mfa/login", None, serde_json::json!({"mfa_token": mfa_token, "code": totp_code(&secret, 0)}));
+    assert_eq!(replayed.status(), 401, "❌ Replayed code should be rejected");
+    let session: serde_json::Value = post_json(&base_url, "/auth/mfa/login", None, serde_json::json!({"mfa_token": mfa_token, "code": totp_code(&secret, 30)}))
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    assert!(session["access_token"].is_string(), "❌ Second step should issue tokens");
+
+    // Recovery codes work once
+    let mfa_token = password_login(&base_url)["mfa_token"].as_str().unwrap().to_string();
+    let recovered = post_json(&base_url, "/auth/mfa/login", None, serde_json::json!({"mfa_token": mfa_token, "recovery_code": recovery_codes[0]}));
+    assert_eq!(recovered.status(), 200, "❌ Recovery code should be accepted");
+    let reused = post_json(&base_url, "/auth/mfa/login", None, serde_json::json!({"mfa_token": mfa_token, "recovery_code": recovery_codes[0]}));
+    assert_eq!(reused.status(), 401, "❌ Recovery code should be single-use");
+
+    // Disabling needs a code; afterwards the password is enough again
+    let token = session["access_token"].as_str().unwrap();
+    let refused = post_json(&base_url, "/auth/mfa/disable", Some(token), serde_json::json!({}));
+    assert_eq!(refused.status(), 403, "❌ Disabling without a code should be refused");
+    let disabled = post_json(&base_url, "/auth/mfa/disable", Some(token), serde_json::json!({"recovery_code": recovery_codes[1]}));
+    assert_eq!(disabled.status(), 204, "❌ Disabling should return 204");
+    assert!(password_login(&base_url)["access_token"].is_string(), "❌ Login should no longer need a second factor");
+    println!("✅ TOTP second factor tested successfully");
+    stop_test_server(child);
+}
+
+#[async_std::test]
+async fn test_wrong_codes_lock_the_account_across_password_logins() {
+    println!("\n🧪 Test: wrong MFA codes add up even when the password is entered again");
+    let (base_url, child) = start_test_server();
+    let token = password_login(&base_url)["access_token"].as_str().expect("❌ No access token").to_string();
+    let enrollment: serde_json::Value = post_json(&base_url, "/auth/mfa/enroll", Some(&token), serde_json::json!({}))
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    let secret = enrollment["secret"].as_str().expect("❌ No secret").to_string();
+    let verified = post_json(&base_url, "/auth/mfa/verify", Some(&token), serde_json::json!({"code": totp_code(&secret, 0)}));
+    assert_eq!(verified.status(), 204, "❌ Verification should return 204");
+
+    // Two wrong codes per password login; a correct password must not reset
+    // the count, so the default limit of 5 is reached in the third round
+    for _ in 0..3 {
+        let mfa_token = password_login(&base_url)["mfa_token"].as_str().expect("❌ No mfa_token").to_string();
+        for _ in 0..2 {
+            post_json(&base_url, "/auth/mfa/login", None, serde_json::json!({"mfa_token": mfa_token, "code": "000000x"}));
+        }
+    }
+    let locked = post_json(&base_url, "/auth/login", None, serde_json::json!({"username": "user1", "password": "password123"}));
+    assert_eq!(locked.status(), 429, "❌ Repeated wrong codes should lock the account");
+    println!("✅ Wrong MFA codes lock the account");
+    stop_test_server(child);
+}
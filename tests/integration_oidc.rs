@@ -0,0 +1,92 @@
+mod common;
+use common::mock_idp::{sign_idp_token, start_mock_idp, MockIdentity, CLIENT_ID, CLIENT_SECRET};
+use common::*;
+
+fn start_server_with_idp(issuer: &str) -> (String, std::process::Child) {
+    let port = find_available_port();
+    let redirect_uri = format!("http://127.0.0.1:{}/auth/oidc/callback", port);
+    let (base_url, child) = start_test_server_with_env(&[
+        ("SERVER_ADDR", &format!("127.0.0.1:{}", port)),
+        ("OIDC_ISSUER", issuer),
+        ("OIDC_CLIENT_ID", CLIENT_ID),
+        ("OIDC_CLIENT_SECRET", CLIENT_SECRET),
+        ("OIDC_REDIRECT_URI", &redirect_uri),
+        ("OIDC_ADMIN_GROUPS", "crud-admins"),
+    ]);
+    assert_eq!(base_url, format!("http://127.0.0.1:{}", port), "❌ Server picked another port");
+    (base_url, child)
+}
+
+// Follows one redirect by hand and returns the Location header
+fn redirect_location(url: &str) -> String {
+    let agent = ureq::AgentBuilder::new().redirects(0).build();
+    let response = agent.get(url).call().expect("❌ Request failed");
+    assert_eq!(response.status(), 302, "❌ Expected a redirect from {}", url);
+    response.header("Location").expect("❌ Missing Location header").to_string()
+}
+
+#[async_std::test]
+async fn test_oidc_authorization_code_login() {
+    println!("\n🧪 Test: OIDC authorization-code login against a mock provider");
+    let issuer = start_mock_idp(MockIdentity {
+        subject: "sso-123".to_string(),
+        username: "alice".to_string(),
+        groups: vec!["crud-admins".to_string()],
+    });
+    let (base_url, child) = start_server_with_idp(&issuer);
+
+    // Server -> provider -> back to the server's callback
+    let authorize_url = redirect_location(&format!("{}/auth/oidc/login", base_url));
+    assert!(authorize_url.starts_with(&format!("{}/authorize", issuer)), "❌ Unexpected redirect: {}", authorize_url);
+    let callback_url = redirect_location(&authorize_url);
+    let login: LoginResponse = ureq::get(&callback_url)
+        .call()
+        .expect("❌ Callback failed")
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    assert_eq!(login.username, "alice", "❌ External user should map to the local username");
+
+    // The admin group grants the admin role
+    let response = ureq::get(&format!("{}/admin/export", base_url))
+        .set("Authorization", &format!("Bearer {}", login.access_token))
+        .call()
+        .expect("❌ Export failed");
+    assert_eq!(response.status(), 200, "❌ Admin group member should be able to export");
+
+    // Replaying the callback fails: the state is single-use
+    match ureq::get(&callback_url).call() {
+        Err(ureq::Error::Status(400, _)) => {}
+        other => panic!("❌ Expected 400 on replay, got {:?}", other.map(|r| r.status())),
+    }
+    println!("✅ OIDC login tested successfully");
+    stop_test_server(child);
+}
+
+#[async_std::test]
+async fn test_oidc_bearer_tokens_from_provider() {
+    println!("\n🧪 Test: Provider-issued JWTs as bearer tokens");
+    let identity = MockIdentity {
+        subject: "sso-456".to_string(),
+        username: "bob".to_string(),
+        groups: vec!["staff".to_string()],
+    };
+    let issuer = start_mock_idp(identity.clone());
+    let (base_url, child) = start_server_with_idp(&issuer);
+    let status = |token: &str, path: &str| match ureq::get(&format!("{}{}", base_url, path))
+        .set("Authorization", &format!("Bearer {}", token))
+        .call() {
+        Ok(response) => response.status(),
+        Err(ureq::Error::Status(code, _)) => code,
+        Err(e) => panic!("❌ Request failed: {}", e),
+    };
+
+    let token = sign_idp_token(&issuer, &identity, CLIENT_ID, None);
+    assert_eq!(status(&token, "/data"), 200, "❌ Provider token should be accepted");
+    assert_eq!(status(&token, "/admin/export"), 403, "❌ Non-admin group should not export");
+
+    // Wrong audience is rejected
+    let token = sign_idp_token(&issuer, &identity, "another-client", None);
+    assert_eq!(status(&token, "/data"), 401, "❌ Token for another audience should be 401");
+    println!("✅ Provider bearer tokens tested successfully");
+    stop_test_server(child);
+}
@@ -0,0 +1,172 @@
+mod common;
+use common::*;
+
+// Returns the status of a request whatever it is
+fn status(result: Result<ureq::Response, ureq::Error>) -> u16 {
+    match result {
+        Ok(response) => response.status(),
+        Err(ureq::Error::Status(code, _)) => code,
+        Err(e) => panic!("❌ Request failed: {}", e),
+    }
+}
+
+fn login(base_url: &str, username: &str, password: &str) -> Result<LoginResponse, u16> {
+    match ureq::post(&format!("{}/auth/login", base_url)).send_json(ureq::json!(LoginRequest {
+        username: username.to_string(),
+        password: password.to_string(),
+    })) {
+        Ok(response) => Ok(response.into_json().expect("❌ Failed to parse JSON")),
+        Err(ureq::Error::Status(code, _)) => Err(code),
+        Err(e) => panic!("❌ Request failed: {}", e),
+    }
+}
+
+const ADD_MODULE: &str = r#"(module (func (export "add") (param i32 i32) (result i32) (i32.add (local.get 0) (local.get 1))))"#;
+
+#[async_std::test]
+async fn test_user_lifecycle() {
+    println!("\n🧪 Test: Admin user management and self-service");
+    let (base_url, child) = start_test_server();
+    let admin = login(&base_url, "admin", "admin123").expect("❌ Admin login failed").access_token;
+    let as_admin = |request: ureq::Request| request.set("Authorization", &format!("Bearer {}", admin));
+
+    // Create a user and sign in as them
+    let created = as_admin(ureq::post(&format!("{}/admin/users", base_url)))
+        .send_json(ureq::json!({"username": "carol", "password": "carol-pass-1"}));
+    assert_eq!(status(created), 201, "❌ User creation should return 201");
+    let carol = login(&base_url, "carol", "carol-pass-1").expect("❌ New user login failed");
+
+    let me: serde_json::Value = ureq::get(&format!("{}/auth/me", base_url))
+        .set("Authorization", &format!("Bearer {}", carol.access_token))
+        .call()
+        .expect("❌ /auth/me failed")
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    assert_eq!(me["username"], "carol");
+    assert_eq!(me["roles"], serde_json::json!(["user"]));
+
+    // Changing the password revokes the session it was made from
+    let changed = ureq::put(&format!("{}/auth/me/password", base_url))
+        .set("Authorization", &format!("Bearer {}", carol.access_token))
+        .send_json(ureq::json!({"current_password": "carol-pass-1", "new_password": "carol-pass-2"}));
+    assert_eq!(status(changed), 204, "❌ Password change should return 204");
+    let stale = ureq::get(&format!("{}/auth/me", base_url)).set("Authorization", &format!("Bearer {}", carol.access_token)).call();
+    assert_eq!(status(stale), 401, "❌ Old session should be revoked");
+    let carol = login(&base_url, "carol", "carol-pass-2").expect("❌ Login with new password failed");
+    let carol_auth = format!("Bearer {}", carol.access_token);
+
+    // An instance and a job, which must not outlive the account
+    let bytecode = wat::parse_str(ADD_MODULE).expect("❌ Invalid test module");
+    let record: serde_json::Value = ureq::post(&format!("{}/data", base_url))
+        .set("Authorization", &carol_auth)
+        .send_json(ureq::json!({"func_names": ["add"], "bytecode": bytecode}))
+        .expect("❌ Record creation failed")
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    let record_id = record["id"].as_u64().unwrap();
+    let instance = ureq::post(&format!("{}/data/{}/instances", base_url, record_id)).set("Authorization", &carol_auth).call();
+    assert_eq!(status(instance), 201, "❌ Instance creation should return 201");
+    let job: serde_json::Value = ureq::post(&format!("{}/jobs", base_url))
+        .set("Authorization", &carol_auth)
+        .send_json(ureq::json!({"record_id": record_id, "fn": "add", "arg": [2, 3]}))
+        .expect("❌ Job creation failed")
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    let job_id = job["id"].as_str().unwrap().to_string();
+
+    // Disabled users are locked out, including sessions they already had
+    assert_eq!(status(as_admin(ureq::post(&format!("{}/admin/users/carol/disable", base_url))).call()), 204);
+    // ...with the same answer as a wrong password, which would give the password away
+    assert_eq!(login(&base_url, "carol", "carol-pass-2").err(), Some(401), "❌ Disabled user should not log in");
+    assert_eq!(login(&base_url, "carol", "wrong-pass").err(), Some(401));
+    let disabled = ureq::get(&format!("{}/auth/me", base_url)).set("Authorization", &format!("Bearer {}", carol.access_token)).call();
+    assert_eq!(status(disabled), 401, "❌ Disabled user's token should be rejected");
+
+    // Non-admins cannot manage users
+    let user1 = login(&base_url, "user1", "password123").expect("❌ user1 login failed").access_token;
+    let forbidden = ureq::get(&format!("{}/admin/users", base_url)).set("Authorization", &format!("Bearer {}", user1)).call();
+    assert_eq!(status(forbidden), 403, "❌ Non-admin should get 403");
+
+    let deleted = as_admin(ureq::delete(&format!("{}/admin/users/carol?records=delete", base_url))).call();
+    assert_eq!(status(deleted), 200, "❌ User deletion should return 200");
+    let users: Vec<serde_json::Value> = as_admin(ureq::get(&format!("{}/admin/users", base_url)))
+        .call()
+        .expect("❌ User listing failed")
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    assert!(users.iter().all(|user| user["username"] != "carol"), "❌ Deleted user still listed");
+
+    // A new account with the same name starts without them
+    let recreated = as_admin(ureq::post(&format!("{}/admin/users", base_url)))
+        .send_json(ureq::json!({"username": "carol", "password": "carol-pass-3"}));
+    assert_eq!(status(recreated), 201, "❌ User re-creation should return 201");
+    let carol_auth = format!("Bearer {}", login(&base_url, "carol", "carol-pass-3").expect("❌ Login failed").access_token);
+    let instances: Vec<serde_json::Value> = ureq::get(&format!("{}/instances", base_url))
+        .set("Authorization", &carol_auth)
+        .call()
+        .expect("❌ Instance listing failed")
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    assert!(instances.is_empty(), "❌ Deleted user's instances were inherited");
+    let job = ureq::get(&format!("{}/jobs/{}", base_url, job_id)).set("Authorization", &carol_auth).call();
+    assert_eq!(status(job), 404, "❌ Deleted user's job was inherited");
+    println!("✅ User lifecycle tested successfully");
+    stop_test_server(child);
+}
+
+#[async_std::test]
+async fn test_wasm_feature_policy() {
+    println!("\n🧪 Test: Server-wide and per-user WASM feature policy");
+    let (base_url, child) = start_test_server_with_env(&[("WASM_FEATURES", "bulk_memory,multi_value,reference_types,tail_call,floats")]);
+    let admin = login(&base_url, "admin", "admin123").expect("❌ Admin login failed").access_token;
+    let user = login(&base_url, "user1", "password123").expect("❌ User login failed").access_token;
+    let send = |request: ureq::Request, token: &str, body: serde_json::Value| {
+        match request.set("Authorization", &format!("Bearer {}", token)).send_json(body) {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => panic!("❌ Request failed: {}", e),
+        }
+    };
+    let upload = |text: &str| {
+        let bytecode = wat::parse_str(text).expect("❌ Invalid test module");
+        send(ureq::post(&format!("{}/data", base_url)), &user, ureq::json!({"func_names": ["f"], "bytecode": bytecode}))
+    };
+    let simd = r#"(module (func (export "f") (result i32) (i32x4.extract_lane 0 (v128.const i32x4 7 0 0 0))))"#;
+    let floats = r#"(module (func (export "f") (result i32) (i32.trunc_f32_s (f32.const 2.5))))"#;
+
+    // The server does not allow SIMD
+    let rejected = upload(simd);
+    assert_eq!(rejected.status(), 400, "❌ SIMD module should be rejected");
+    let body: serde_json::Value = rejected.into_json().expect("❌ Failed to parse JSON");
+    assert_eq!(body["features"], ureq::json!(["simd"]), "❌ Offending feature should be named: {}", body);
+
+    let created = upload(floats);
+    assert_eq!(created.status(), 200, "❌ Float module should be accepted");
+    let record_id = created.into_json::<serde_json::Value>().expect("❌ Failed to parse JSON")["id"].as_u64().unwrap();
+    let execute = || send(ureq::post(&format!("{}/execute/{}", base_url, record_id)), &user, ureq::json!({"fn": "f"}));
+    assert_eq!(execute().status(), 200);
+
+    // A user policy narrows the server's and applies to existing records
+    let policy_url = format!("{}/admin/users/user1/wasm-features", base_url);
+    let policy: serde_json::Value = send(ureq::put(&policy_url), &admin, ureq::json!({"allowed": ["bulk_memory", "simd"]}))
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    assert_eq!(policy["effective"], ureq::json!(["bulk_memory"]), "❌ SIMD stays disallowed by the server");
+    let me: serde_json::Value = ureq::get(&format!("{}/auth/me", base_url))
+        .set("Authorization", &format!("Bearer {}", user))
+        .call()
+        .expect("❌ /auth/me failed")
+        .into_json()
+        .expect("❌ Failed to parse JSON");
+    assert_eq!(me["wasm_features"], ureq::json!(["bulk_memory"]));
+    let denied = execute();
+    assert_eq!(denied.status(), 400, "❌ Execution should follow the owner's policy");
+    assert_eq!(denied.into_json::<serde_json::Value>().expect("❌ Failed to parse JSON")["features"], ureq::json!(["floats"]));
+    assert_eq!(upload(floats).status(), 400, "❌ Upload should follow the uploader's policy");
+
+    assert_eq!(send(ureq::put(&policy_url), &admin, ureq::json!({"allowed": ["warp_drive"]})).status(), 400);
+    assert_eq!(send(ureq::put(&policy_url), &user, ureq::json!({"allowed": []})).status(), 403, "❌ Admin only");
+    assert_eq!(status(ureq::delete(&policy_url).set("Authorization", &format!("Bearer {}", admin)).call()), 204);
+    assert_eq!(execute().status(), 200, "❌ Server policy should apply again");
+    println!("✅ Feature policy tested successfully");
+    stop_test_server(child);
+}